@@ -1,7 +1,8 @@
+use data::{IdInterface, ShapeInterface};
 use rand::seq::SliceRandom;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use sqlx::types::chrono::FixedOffset;
-use std::sync::Arc;
+use sqlx::types::chrono::{FixedOffset, Utc};
+use std::{collections::HashMap, sync::Arc};
 
 pub async fn message() -> String {
     parse::get_random_message("./random_message_templates.json".to_string()).await
@@ -19,21 +20,15 @@ pub async fn update_chatlogs() {
         .await
         .expect("failed to commit transaction");
 
+    let registry = parse::ChatLogRegistry::default();
     for (campaign_name, campaign_config) in &config.campaigns {
-        // TODO: refactor ChatLogs to bake offset into db to avoid the need to check this so much
-        let offset = if campaign_config.log.starts_with("fnd_") {
-            None
-        } else {
-            Some(campaign_config.timezone_offset)
-        };
-
         println!("Updating campaign: {campaign_name}");
         data::update_posts_from_log(
             &pool,
+            &registry,
             campaign_name,
             "./chatlogs",
-            campaign_config.log.as_str(),
-            offset,
+            campaign_config,
         )
         .await;
     }
@@ -41,7 +36,8 @@ pub async fn update_chatlogs() {
 
 pub async fn dump_unmapped_senders() -> Vec<String> {
     let config = parse::parse_config("./config.json".to_string()).await;
-    let senders_map = data::dump_unmapped_senders(&config).await;
+    let senders_map =
+        data::dump_unmapped_senders(&config, &parse::ChatLogRegistry::default()).await;
     let mut messages: Vec<String> = vec![];
     let mut message = "```".to_string();
 
@@ -182,38 +178,371 @@ pub async fn around(message_id: String, num_around: i32) -> String {
     return format!("Sorry - I couldn't find '`{}`'!", message_id);
 }
 
-pub async fn roll(expr: &str) -> String {
-    let result = parse::dicemath(expr);
+/// Formats a single die the way Roll20's tooltips do, with dropped and rerolled dice struck out and
+/// exploded dice marked with a `!`.
+fn format_single_roll(single_roll: &parse::RollSingle) -> String {
+    let outcome = match (single_roll.is_fate, single_roll.outcome) {
+        (true, 1) => "+".to_string(),
+        (true, -1) => "-".to_string(),
+        (_, outcome) => outcome.to_string(),
+    };
+    let outcome = if single_roll.is_exploded {
+        format!("{outcome}!")
+    } else {
+        outcome
+    };
 
-    match result {
-        Some(value) => format!("Result for `{expr}`:\n`{value}`"),
-        None => format!("Sorry, I couldn't figure out how to parse `{expr}`!"),
+    if single_roll.is_dropped || single_roll.is_rerolled {
+        format!("~~{outcome}~~")
+    } else {
+        outcome
     }
 }
 
-pub async fn odds(expr: &str, val: f64, num_rolls: u64) -> String {
-    let results: Vec<bool> = (0..num_rolls)
-        .into_par_iter()
-        .filter_map(|_| {
-            let result = parse::dicemath(expr)?;
-            if result >= val {
-                Some(true)
-            } else {
-                None
+/// Explains what went wrong with a dice expression, pointing at the exact spot.
+fn describe_dice_error(expr: &str, error: parse::DiceError) -> String {
+    format!(
+        "Sorry, I couldn't roll that - {error}:\n```\n{expr}\n{}^\n```",
+        " ".repeat(error.position())
+    )
+}
+
+/// The macros saved by whichever player uses the given Discord username.
+async fn player_macros(
+    config: &parse::parse_config::Config,
+    username: &str,
+) -> HashMap<String, String> {
+    match config.player_for_discord_user(username) {
+        Some(player_name) => {
+            let pool = data::create_connection_pool("./.env").await;
+            data::fetch_roll_macros(&pool, player_name).await
+        }
+        None => HashMap::new(),
+    }
+}
+
+/// The dice in one term of a roll, like `[3, ~~1~~, 4, 5]`.
+fn format_term_rolls(term: &parse::Roll) -> String {
+    format!(
+        "[{}]",
+        parse::displayed_single_rolls(term)
+            .iter()
+            .map(format_single_roll)
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}
+
+fn format_total(result: &parse::DiceResult) -> String {
+    match result.kind {
+        parse::ResultKind::Sum => format!("`{}`", result.value),
+        parse::ResultKind::Successes => format!("`{}` successes", result.value),
+    }
+}
+
+/// Every term of a roll on its own line, followed by the total and any botches or criticals.
+fn describe_result(result: &parse::DiceResult) -> String {
+    let mut response = String::new();
+    for term in &result.terms {
+        response.push_str(&format!(
+            "`{}`: {} = `{}`\n",
+            term.formula,
+            format_term_rolls(term),
+            term.outcome
+        ));
+    }
+    response.push_str(&format!("Total: {}", format_total(result)));
+    if result.is_botch {
+        response.push_str("\nThat's a botch!");
+    }
+    if result.critical_successes > 0 {
+        response.push_str(&format!(
+            "\nCritical successes: `{}`",
+            result.critical_successes
+        ));
+    }
+    if result.critical_failures > 0 {
+        response.push_str(&format!(
+            "\nCritical failures: `{}`",
+            result.critical_failures
+        ));
+    }
+
+    response
+}
+
+/// A whole roll on one line, for listing each result of a repeated roll.
+fn summarize_result(result: &parse::DiceResult) -> String {
+    let mut summary = result
+        .terms
+        .iter()
+        .map(|term| format!("`{}` {}", term.formula, format_term_rolls(term)))
+        .collect::<Vec<String>>()
+        .join(", ");
+    if !summary.is_empty() {
+        summary.push_str(" = ");
+    }
+    summary.push_str(&format_total(result));
+    if result.is_botch {
+        summary.push_str(" (botch!)");
+    }
+    if result.critical_successes > 0 {
+        summary.push_str(&format!(
+            " (critical successes: `{}`)",
+            result.critical_successes
+        ));
+    }
+    if result.critical_failures > 0 {
+        summary.push_str(&format!(
+            " (critical failures: `{}`)",
+            result.critical_failures
+        ));
+    }
+
+    summary
+}
+
+/// Records rolls made through the bot under the configured Discord campaign, if there is one and
+/// the Discord user is one of its players.
+async fn record_discord_rolls(
+    config: &parse::parse_config::Config,
+    username: &str,
+    post_id: u64,
+    content: &str,
+    rolls: Vec<parse::Roll>,
+) {
+    let Some(campaign_name) = &config.discord_campaign else {
+        return;
+    };
+    let Some(campaign_config) = config.campaigns.get(campaign_name) else {
+        return;
+    };
+    if config.player_for_discord_user(username).is_none() || rolls.is_empty() {
+        return;
+    }
+
+    let fixed_offset = FixedOffset::east_opt(campaign_config.timezone_offset * 3600).unwrap();
+    let post = parse::Post {
+        id: post_id.to_string(),
+        sender_name: username.to_string(),
+        datetime: Utc::now().with_timezone(&fixed_offset),
+        content_raw: content.to_string(),
+        is_message: false,
+        rolls,
+        visibility: parse::Visibility::Public,
+    };
+
+    let pool = data::create_connection_pool("./.env").await;
+    if let Err(error) = data::insert_discord_post(&pool, campaign_name, post).await {
+        println!("Failed to record rolls from {username}: {error}");
+    }
+}
+
+/// A roll as it gets stored, with every die from every term. Exploded and rerolled dice are each
+/// their own die showing the face they landed on, the same as rolls imported from a log.
+fn as_stored_roll(formula: &str, result: &parse::DiceResult) -> parse::Roll {
+    parse::Roll {
+        formula: formula.to_string(),
+        outcome: result.value,
+        single_rolls: result
+            .terms
+            .iter()
+            .flat_map(|term| term.single_rolls.clone())
+            .collect(),
+    }
+}
+
+pub async fn roll(expr: &str, username: &str, post_id: u64) -> String {
+    let config = parse::parse_config("./config.json".to_string()).await;
+    let expanded = if expr.contains(['#', '@']) {
+        let macros = player_macros(&config, username).await;
+        match parse::expand_macros(expr, &macros, &config.dice_limits) {
+            Ok(expanded) => expanded,
+            Err(error) => return describe_dice_error(expr, error),
+        }
+    } else {
+        expr.to_string()
+    };
+    let results =
+        match parse::dicemath_repeated(&expanded, &mut rand::thread_rng(), &config.dice_limits) {
+            Ok(results) => results,
+            Err(error) => return describe_dice_error(&expanded, error),
+        };
+
+    let mut response = if expanded == expr {
+        format!("Result for `{expr}`:\n")
+    } else {
+        format!("Result for `{expr}` (`{}`):\n", expanded.trim())
+    };
+    match results.as_slice() {
+        [result] => response.push_str(&describe_result(result)),
+        results => {
+            for (index, result) in results.iter().enumerate() {
+                response.push_str(&format!("{}. {}\n", index + 1, summarize_result(result)));
             }
-        })
+        }
+    }
+
+    let formula = match parse::split_repeat(&expanded, &config.dice_limits) {
+        Ok((_, formula)) => formula.trim().to_string(),
+        Err(_) => expanded.trim().to_string(),
+    };
+    let rolls = results
+        .iter()
+        .map(|result| as_stored_roll(&formula, result))
         .collect();
+    record_discord_rolls(&config, username, post_id, expr, rolls).await;
+
+    response
+}
+
+/// Rolls every `[[...]]` inline roll in a message, replying with the message's text with each roll
+/// swapped for its result. Returns `None` if the message doesn't have any inline rolls.
+pub async fn inline_rolls(content: &str, username: &str, post_id: u64) -> Option<String> {
+    if !content.contains("[[") {
+        return None;
+    }
+
+    let config = parse::parse_config("./config.json".to_string()).await;
+    let macros = if content.contains(['#', '@']) {
+        player_macros(&config, username).await
+    } else {
+        HashMap::new()
+    };
+
+    let mut rolls: Vec<parse::Roll> = vec![];
+    let reply = parse::replace_inline_rolls(content, |expr| {
+        let result =
+            parse::expand_macros(expr, &macros, &config.dice_limits).and_then(|expanded| {
+                let result =
+                    parse::dicemath_with(&expanded, &mut rand::thread_rng(), &config.dice_limits)?;
+                rolls.push(as_stored_roll(expanded.trim(), &result));
+                Ok(result)
+            });
+        match result {
+            Ok(result) => format!("`{}`", result.value),
+            Err(error) => format!("`[[{expr}]]` ({error})"),
+        }
+    });
+    record_discord_rolls(&config, username, post_id, content, rolls).await;
+
+    reply
+}
+
+/// Saves a macro like `#atk = 1d20+@str+@prof` or a variable like `@str = 3` for the player.
+pub async fn macro_add(definition: &str, username: &str) -> String {
+    let config = parse::parse_config("./config.json".to_string()).await;
+    let Some(player_name) = config.player_for_discord_user(username) else {
+        return format!("Sorry, I don't know which player `{username}` is!");
+    };
+    let (macro_name, expression) = match parse::parse_macro_definition(definition) {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            return format!(
+                "Sorry, I couldn't read `{definition}` - try something like `#atk = 1d20+@str`!"
+            )
+        }
+    };
+
+    let pool = data::create_connection_pool("./.env").await;
+    let mut transaction = data::begin_transaction(&pool).await;
+    let player_id = data::Player::from_values(&player_name.to_string())
+        .await
+        .fetch_or_insert_id(&mut transaction)
+        .await;
+    data::RollMacro {
+        macro_name: &macro_name,
+        expression: &expression,
+        player_id,
+    }
+    .upsert(&mut transaction)
+    .await;
+    transaction
+        .commit()
+        .await
+        .expect("failed to commit transaction");
+
+    format!("Saved `{macro_name}` as `{expression}` for {player_name}!")
+}
+
+pub async fn macro_list(username: &str) -> String {
+    let config = parse::parse_config("./config.json".to_string()).await;
+    let Some(player_name) = config.player_for_discord_user(username) else {
+        return format!("Sorry, I don't know which player `{username}` is!");
+    };
+    let mut macros: Vec<(String, String)> =
+        player_macros(&config, username).await.into_iter().collect();
+
+    if macros.is_empty() {
+        return format!("{player_name} doesn't have any macros yet!");
+    }
 
+    macros.sort_unstable();
     format!(
-        "\
-Out of `{}` rolls of `{expr}`, I rolled a `{val}` or higher a total of `{}` times.
-My estimated odds of rolling a `{val}` or higher is about `{}`%!",
-        parse::num_with_thousands_commas(num_rolls as u64),
-        parse::num_with_thousands_commas(results.len() as u64),
-        results.len() as f64 / (num_rolls as f64) * 100.
+        "Here are {player_name}'s macros:\n```\n{}\n```",
+        macros
+            .into_iter()
+            .map(|(macro_name, expression)| format!("{macro_name} = {expression}"))
+            .collect::<Vec<String>>()
+            .join("\n")
     )
 }
 
+pub async fn macro_remove(macro_name: &str, username: &str) -> String {
+    let config = parse::parse_config("./config.json".to_string()).await;
+    let Some(player_name) = config.player_for_discord_user(username) else {
+        return format!("Sorry, I don't know which player `{username}` is!");
+    };
+
+    let pool = data::create_connection_pool("./.env").await;
+    let mut transaction = data::begin_transaction(&pool).await;
+    let player_id = data::Player::from_values(&player_name.to_string())
+        .await
+        .try_fetch_id(&mut transaction)
+        .await;
+    let was_removed = match player_id {
+        Ok(player_id) => data::RollMacro::delete(&mut transaction, macro_name, player_id).await,
+        Err(_) => false,
+    };
+    transaction
+        .commit()
+        .await
+        .expect("failed to commit transaction");
+
+    if was_removed {
+        format!("Removed `{macro_name}` for {player_name}!")
+    } else {
+        format!("Sorry, {player_name} doesn't have a macro called `{macro_name}`!")
+    }
+}
+
+pub async fn odds(expr: &str, val: f64) -> String {
+    let config = parse::parse_config("./config.json".to_string()).await;
+    // Working out or sampling a distribution can take a while, so it's kept off the threads
+    // that handle Discord events.
+    let formula = expr.to_string();
+    let distribution = tokio::task::spawn_blocking(move || {
+        parse::dicemath_distribution(&formula, 1_000_000, &config.dice_limits)
+    })
+    .await
+    .expect("failed to work out odds");
+    let distribution = match distribution {
+        Ok(distribution) => distribution,
+        Err(error) => return describe_dice_error(expr, error),
+    };
+    let odds = distribution.probability_at_least(val) * 100.;
+
+    if distribution.is_exact {
+        format!("The odds of rolling a `{val}` or higher on `{expr}` are exactly `{odds}`%!")
+    } else {
+        format!(
+            "\
+`{expr}` has too many possible results to work out exactly, so I rolled it a million times.
+My estimated odds of rolling a `{val}` or higher is about `{odds}`%!"
+        )
+    }
+}
+
 pub async fn simulate(player_name: &str, num_repetitions: i32) -> String {
     let pool = data::create_connection_pool("./.env").await;
     let all_pronouns = data::fetch_player_pronouns(&pool, player_name).await;
@@ -229,11 +558,22 @@ pub async fn simulate(player_name: &str, num_repetitions: i32) -> String {
     let mut num_beat: u64 = 0;
     let mut num_tied: u64 = 0;
 
-    for parse::RollSingle { faces, outcome } in rolls {
+    for parse::RollSingle {
+        faces,
+        outcome,
+        is_fate,
+        ..
+    } in rolls
+    {
+        let die = if is_fate {
+            "1dF".to_string()
+        } else {
+            format!("1d{faces}")
+        };
         let results = (0..num_repetitions)
             .into_par_iter()
             .filter_map(|_| {
-                let result = parse::dicemath(format!("1d{faces}").as_str())?;
+                let result = parse::dicemath(die.as_str()).ok()?;
                 if result > outcome as f64 {
                     Some(true)
                 } else if result == outcome as f64 {
@@ -271,108 +611,86 @@ I'd estimate {player_name}'s luck to be about {}% of perfect.",
     )
 }
 
-// TODO: Refactor worst_roll & best_roll shared behavior
-pub async fn worst_roll(precise: bool) -> String {
-    let num_trials: usize = if precise { 100_000 } else { 1000 };
+/// How many times to roll a historical roll whose odds can't be worked out exactly.
+const NUM_FALLBACK_TRIALS: u32 = 10_000;
 
-    let pool = data::create_connection_pool("./.env").await;
-    let all_rolls = data::fetch_all_parseable_rolls(&pool).await;
-    let mut odds: Vec<_> = all_rolls
-        .into_par_iter()
-        .map(
-            |(player_name, campaign_name, formula, outcome, timestamp_sent, timezone_offset)| {
-                let cmp_outcome = outcome + 1.;
-
-                let results: Vec<bool> = (0..num_trials)
-                    .into_iter()
-                    .filter_map(|_| {
-                        let result = parse::dicemath(formula.as_str())?;
-                        if result >= cmp_outcome {
-                            Some(true)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-
-                (
-                    player_name,
-                    campaign_name,
-                    formula,
-                    outcome,
-                    timestamp_sent,
-                    timezone_offset,
-                    100. - results.len() as f64 / num_trials as f64 * 100.,
-                )
-            },
+/// The chance of a historical roll, worded as an estimate when it had to be rolled
+/// `NUM_FALLBACK_TRIALS` times instead of worked out exactly.
+fn describe_chance(odds: f64, is_exact: bool) -> String {
+    if is_exact {
+        format!("a `{odds:?}`% chance")
+    } else {
+        format!(
+            "an estimated `{odds:?}`% chance (from rolling it {} times)",
+            parse::num_with_thousands_commas(NUM_FALLBACK_TRIALS.into())
         )
-        .collect();
-    odds.sort_by(|a, b| {
-        let odds_1 = a.6;
-        let odds_2 = b.6;
-
-        odds_1.total_cmp(&odds_2)
-    });
-
-    let (
-        player_name,
-        campaign_name,
-        formula,
-        outcome,
-        timestamp_sent,
-        timezone_offset,
-        odds_this_bad,
-    ) = &odds[0];
-
-    let fixed_offset = FixedOffset::east_opt(timezone_offset * 3600).unwrap();
-    let offset_timezone = *timestamp_sent + fixed_offset;
-    let date = offset_timezone.date_naive().format("%m/%d/%Y");
-    let time = offset_timezone.time().format("%-I:%M %p");
+    }
+}
 
-    format!("\
-The worst single roll anyone has ever rolled was from {player_name} in \"{campaign_name}\" on {date} at {time}.
-They rolled `{formula}` and got a `{outcome}`, which I estimated to have a `{:?}`% chance of being this bad.", odds_this_bad)
+/// Whether an all-time roll record is for the least likely low roll or the least likely high one.
+#[derive(Clone, Copy)]
+enum RollRecord {
+    Worst,
+    Best,
 }
 
-pub async fn best_roll(precise: bool) -> String {
-    let num_trials: usize = if precise { 100_000 } else { 1000 };
+impl RollRecord {
+    /// The percent chance of rolling `outcome`, or anything as bad or as good.
+    fn odds(self, distribution: &parse::Distribution, outcome: f64) -> f64 {
+        let probability = match self {
+            RollRecord::Worst => distribution.probability_at_most(outcome),
+            RollRecord::Best => distribution.probability_at_least(outcome),
+        };
+        probability * 100.
+    }
+}
 
+/// Describes whichever historical roll was least likely to come out as bad or as good as it did,
+/// like `from Bob in "Curse of Strahd" on 01/02/2024 at 8:15 PM`, followed by the roll and its
+/// odds. Returns `None` if there aren't any rolls.
+async fn describe_record_roll(record: RollRecord) -> Option<String> {
+    let config = parse::parse_config("./config.json".to_string()).await;
     let pool = data::create_connection_pool("./.env").await;
     let all_rolls = data::fetch_all_parseable_rolls(&pool).await;
-    let mut odds: Vec<_> = all_rolls
-        .into_par_iter()
-        .map(
-            |(player_name, campaign_name, formula, outcome, timestamp_sent, timezone_offset)| {
-                let results: Vec<bool> = (0..num_trials)
-                    .into_iter()
-                    .filter_map(|_| {
-                        let result = parse::dicemath(formula.as_str())?;
-                        if result >= outcome {
-                            Some(true)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-
-                (
+    // Working out the odds of every roll ever made takes a while, so it's kept off the threads
+    // that handle Discord events.
+    let least_likely = tokio::task::spawn_blocking(move || {
+        all_rolls
+            .into_par_iter()
+            .map(
+                |(
                     player_name,
                     campaign_name,
                     formula,
                     outcome,
                     timestamp_sent,
                     timezone_offset,
-                    results.len() as f64 / num_trials as f64 * 100.,
-                )
-            },
-        )
-        .collect();
-    odds.sort_by(|a, b| {
-        let odds_1 = a.6;
-        let odds_2 = b.6;
-
-        odds_1.total_cmp(&odds_2)
-    });
+                )| {
+                    let (odds, is_exact) = parse::dicemath_distribution(
+                        &formula,
+                        NUM_FALLBACK_TRIALS,
+                        &config.dice_limits,
+                    )
+                    .map_or((100., true), |distribution| {
+                        (record.odds(&distribution, outcome), distribution.is_exact)
+                    });
+
+                    (
+                        player_name,
+                        campaign_name,
+                        formula,
+                        outcome,
+                        timestamp_sent,
+                        timezone_offset,
+                        odds,
+                        is_exact,
+                    )
+                },
+            )
+            .min_by(|a, b| a.6.total_cmp(&b.6))
+    })
+    .await
+    .expect("failed to work out the odds of every roll")?;
 
     let (
         player_name,
@@ -381,15 +699,37 @@ pub async fn best_roll(precise: bool) -> String {
         outcome,
         timestamp_sent,
         timezone_offset,
-        odds_this_good,
-    ) = odds[0].clone();
+        odds,
+        is_exact,
+    ) = least_likely;
 
     let fixed_offset = FixedOffset::east_opt(timezone_offset * 3600).unwrap();
     let offset_timezone = timestamp_sent + fixed_offset;
     let date = offset_timezone.date_naive().format("%m/%d/%Y");
     let time = offset_timezone.time().format("%-I:%M %p");
+    let how = match record {
+        RollRecord::Worst => "bad",
+        RollRecord::Best => "good",
+    };
+
+    Some(format!(
+        "\
+from {player_name} in \"{campaign_name}\" on {date} at {time}.
+They rolled `{formula}` and got a `{outcome}`, which had {} of being this {how}.",
+        describe_chance(odds, is_exact)
+    ))
+}
 
-    format!("\
-The best single roll ever recorded was from {player_name} in \"{campaign_name}\" on {date} at {time}.
-They rolled `{formula}` and got a `{outcome}`, which I estimated to have a `{:?}`% chance of being this good.", odds_this_good)
+pub async fn worst_roll() -> String {
+    match describe_record_roll(RollRecord::Worst).await {
+        Some(roll) => format!("The worst single roll anyone has ever rolled was {roll}"),
+        None => "Sorry, I couldn't find any rolls!".to_string(),
+    }
+}
+
+pub async fn best_roll() -> String {
+    match describe_record_roll(RollRecord::Best).await {
+        Some(roll) => format!("The best single roll ever recorded was {roll}"),
+        None => "Sorry, I couldn't find any rolls!".to_string(),
+    }
 }
@@ -208,7 +208,7 @@ async fn around(
     Ok(())
 }
 
-/// Roll some dice or do some math (or both!)
+/// Roll some dice or do some math (or both!) You can use your saved `#macros` and `@variables` too.
 #[poise::command(
     slash_command,
     prefix_command,
@@ -221,36 +221,59 @@ async fn roll(
     #[rest]
     expr: String,
 ) -> Result<(), Error> {
-    ctx.say(controllers::roll(expr.as_str()).await).await?;
+    ctx.say(controllers::roll(expr.as_str(), &ctx.author().name, ctx.id()).await)
+        .await?;
     Ok(())
 }
 
-/// Get an estimated odds of rolling a certain result (out of 1 million rolls).
-#[poise::command(slash_command, prefix_command, aliases("o"), category = "Nerd")]
-async fn odds(
+/// Save, list or remove your roll macros, like `#atk = 1d20+@str+@prof`
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "macro",
+    subcommands("macro_add", "macro_list", "macro_remove"),
+    subcommand_required,
+    category = "Nerd"
+)]
+async fn roll_macro(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Save a macro like `#atk = 1d20+@str+@prof` or a variable like `@str = 3`
+#[poise::command(slash_command, prefix_command, rename = "add", category = "Nerd")]
+async fn macro_add(
     ctx: Context<'_>,
-    #[description = "The outcome of the roll"] result: String,
-    #[description = "The roll being made"]
+    #[description = "The macro to save, like `#atk = 1d20+@str`"]
     #[rest]
-    expr: String,
+    definition: String,
 ) -> Result<(), Error> {
-    let res_float_option = result.parse::<f64>().ok();
-    if res_float_option.is_none() {
-        ctx.say(
-            "Sorry, I couldn't get the estimated odds of rolling `{result}` or higher on: `{expr}`",
-        )
+    ctx.say(controllers::macro_add(definition.as_str(), &ctx.author().name).await)
+        .await?;
+    Ok(())
+}
+
+/// List all of your macros and variables
+#[poise::command(slash_command, prefix_command, rename = "list", category = "Nerd")]
+async fn macro_list(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say(controllers::macro_list(&ctx.author().name).await)
+        .await?;
+    Ok(())
+}
+
+/// Remove one of your macros or variables
+#[poise::command(slash_command, prefix_command, rename = "remove", category = "Nerd")]
+async fn macro_remove(
+    ctx: Context<'_>,
+    #[description = "The macro to remove, like `#atk`"] name: String,
+) -> Result<(), Error> {
+    ctx.say(controllers::macro_remove(name.as_str(), &ctx.author().name).await)
         .await?;
-    } else {
-        let res_float = res_float_option.unwrap();
-        ctx.say(controllers::odds(expr.as_str(), res_float, 1_000_000).await)
-            .await?;
-    }
     Ok(())
 }
 
-/// Get an estimated odds of rolling a certain result (out of 100 million rolls).
-#[poise::command(prefix_command, aliases("op"), category = "Nerd")]
-async fn odds_precise(
+/// Get the odds of rolling a certain result or higher.
+#[poise::command(slash_command, prefix_command, aliases("o"), category = "Nerd")]
+async fn odds(
     ctx: Context<'_>,
     #[description = "The outcome of the roll"] result: String,
     #[description = "The roll being made"]
@@ -265,7 +288,7 @@ async fn odds_precise(
         .await?;
     } else {
         let res_float = res_float_option.unwrap();
-        ctx.say(controllers::odds(expr.as_str(), res_float, 100_000_000).await)
+        ctx.say(controllers::odds(expr.as_str(), res_float).await)
             .await?;
     }
     Ok(())
@@ -321,7 +344,7 @@ async fn simulate(
 #[poise::command(prefix_command, aliases("cn1"), category = "Fun")]
 async fn cosmicnat1(ctx: Context<'_>) -> Result<(), Error> {
     ctx.say("If you insist... gimme a sec.").await?;
-    ctx.say(controllers::worst_roll(false).await).await?;
+    ctx.say(controllers::worst_roll().await).await?;
     Ok(())
 }
 
@@ -329,25 +352,7 @@ async fn cosmicnat1(ctx: Context<'_>) -> Result<(), Error> {
 #[poise::command(prefix_command, aliases("cn20"), category = "Fun")]
 async fn cosmicnat20(ctx: Context<'_>) -> Result<(), Error> {
     ctx.say("Okay, gimme a sec!").await?;
-    ctx.say(controllers::best_roll(false).await).await?;
-    Ok(())
-}
-
-/// The (extremely) slow, but more precise, version of .cosmicnat1
-#[poise::command(prefix_command, aliases("cn1p"), category = "Fun")]
-async fn cosmicnat1_precise(ctx: Context<'_>) -> Result<(), Error> {
-    ctx.say("If you insist... this is gonna take a minute. I would appreciate some space.")
-        .await?;
-    ctx.say(controllers::worst_roll(true).await).await?;
-    Ok(())
-}
-
-/// The (extremely) slow, but more precise, version of .cosmicnat20
-#[poise::command(prefix_command, aliases("cn20p"), category = "Fun")]
-async fn cosmicnat20_precise(ctx: Context<'_>) -> Result<(), Error> {
-    ctx.say("Okay, this is where the fun begins! You'll need to give me... a minute or two.")
-        .await?;
-    ctx.say(controllers::best_roll(true).await).await?;
+    ctx.say(controllers::best_roll().await).await?;
     Ok(())
 }
 
@@ -421,6 +426,20 @@ async fn event_handler(
             }
         }
         serenity::FullEvent::Message { new_message } => {
+            let inline_rolls = if new_message.author.bot {
+                None
+            } else {
+                controllers::inline_rolls(
+                    &new_message.content,
+                    &new_message.author.name,
+                    new_message.id.get(),
+                )
+                .await
+            };
+            if let Some(reply) = inline_rolls {
+                println!("Executing response to inline rolls");
+                new_message.reply(ctx, reply).await?;
+            }
             let bot_mentioned = new_message
                 .mentions
                 .iter()
@@ -481,14 +500,12 @@ async fn main() {
                 search_context(),
                 around(),
                 roll(),
+                roll_macro(),
                 odds(),
-                odds_precise(),
                 luck(),
                 simulate(),
                 cosmicnat1(),
                 cosmicnat20(),
-                cosmicnat1_precise(),
-                cosmicnat20_precise(),
                 help(),
                 register(),
             ],
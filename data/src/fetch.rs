@@ -1,9 +1,10 @@
-use parse::{parse_config::Config, ChatLog, RollSingle};
+use futures::StreamExt;
+use parse::{parse_config::Config, ChatLogRegistry, RollSingle};
 use rand::seq::SliceRandom;
 use sqlx::{
     query, query_as,
     types::chrono::{DateTime, FixedOffset, Utc},
-    Pool, Postgres, Transaction,
+    Pool, Postgres,
 };
 use std::collections::{HashMap, HashSet};
 
@@ -13,39 +14,10 @@ struct UniqueSender {
     campaign_name: String,
 }
 
-pub async fn fetch_parsed_post_ids<'a, 'tr>(
-    transaction: &'a mut Transaction<'tr, Postgres>,
-    campaign_id: i32,
-) -> HashSet<String> {
-    let already_parsed_ids_result = query!(
-        r#"SELECT post.id FROM post
-            JOIN campaign ON campaign_id = campaign.id
-        WHERE
-            campaign.id = $1"#,
-        campaign_id
-    )
-    .fetch_all(&mut **transaction)
-    .await;
-
-    let already_parsed_ids: Vec<String> = if already_parsed_ids_result.is_err() {
-        vec![]
-    } else {
-        already_parsed_ids_result
-            .unwrap()
-            .into_iter()
-            .map(|rec| rec.id)
-            .collect()
-    };
-
-    let mut already_parsed_hash = HashSet::new();
-    for id in already_parsed_ids {
-        already_parsed_hash.insert(id);
-    }
-
-    already_parsed_hash
-}
-
-pub async fn dump_unmapped_senders(config: &Config) -> HashMap<String, Vec<String>> {
+pub async fn dump_unmapped_senders(
+    config: &Config,
+    registry: &ChatLogRegistry,
+) -> HashMap<String, Vec<String>> {
     let senders = config
         .campaigns
         .iter()
@@ -69,53 +41,30 @@ pub async fn dump_unmapped_senders(config: &Config) -> HashMap<String, Vec<Strin
 
     let mut unmapped_senders: Vec<UniqueSender> = vec![];
     for (campaign_name, campaign_config) in &config.campaigns {
+        let Some(format) = campaign_config.log_format("./chatlogs").await else {
+            continue;
+        };
         let path_to_log = format!("./chatlogs/{}", campaign_config.log);
+        let Some(mut posts) = registry
+            .open(&format, &path_to_log, campaign_config)
+            .await
+        else {
+            continue;
+        };
 
-        if campaign_config.log.starts_with("fnd_") {
-            let mut log = parse::parse_foundry_log(&path_to_log, None).await;
-            while let Some(post) = log.next_post().await {
-                let sender = UniqueSender {
-                    sender_name: post.sender_name.clone(),
-                    campaign_name: campaign_name.clone(),
-                };
-                if !unmapped_senders.contains(&sender)
-                    && !senders_hash.contains(&sender)
-                    && sender.sender_name.len() > 0
-                {
-                    unmapped_senders.push(sender);
-                }
-            }
-        }
-
-        if campaign_config.log.starts_with("r20_") {
-            let mut log = parse::parse_roll20_log(&path_to_log, None).await;
-            while let Some(post) = log.next_post().await {
-                let sender = UniqueSender {
-                    sender_name: post.sender_name.clone(),
-                    campaign_name: campaign_name.clone(),
-                };
-                if !unmapped_senders.contains(&sender)
-                    && !senders_hash.contains(&sender)
-                    && sender.sender_name.len() > 0
-                {
-                    unmapped_senders.push(sender);
-                }
-            }
-        }
-
-        if campaign_config.log.starts_with("fg_") {
-            let mut log = parse::parse_fantasy_grounds_log(&path_to_log, None).await;
-            while let Some(post) = log.next_post().await {
-                let sender = UniqueSender {
-                    sender_name: post.sender_name.clone(),
-                    campaign_name: campaign_name.clone(),
-                };
-                if !unmapped_senders.contains(&sender)
-                    && !senders_hash.contains(&sender)
-                    && sender.sender_name.len() > 0
-                {
-                    unmapped_senders.push(sender);
-                }
+        while let Some(post) = posts.next().await {
+            let Ok(post) = post else {
+                continue;
+            };
+            let sender = UniqueSender {
+                sender_name: post.sender_name.clone(),
+                campaign_name: campaign_name.clone(),
+            };
+            if !unmapped_senders.contains(&sender)
+                && !senders_hash.contains(&sender)
+                && !sender.sender_name.is_empty()
+            {
+                unmapped_senders.push(sender);
             }
         }
     }
@@ -197,10 +146,30 @@ pub async fn fetch_player_pronouns(pool: &Pool<Postgres>, player_name: &str) ->
     .collect()
 }
 
+/// Every `#macro` and `@variable` the player has saved, keyed by name.
+pub async fn fetch_roll_macros(
+    pool: &Pool<Postgres>,
+    player_name: &str,
+) -> HashMap<String, String> {
+    query!(
+        r#"SELECT macro_name, expression FROM roll_macro
+            JOIN player ON player_id = player.id
+        WHERE
+            player_name = $1"#,
+        player_name
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|roll_macro| (roll_macro.macro_name, roll_macro.expression))
+    .collect()
+}
+
 pub async fn fetch_all_single_rolls(pool: &Pool<Postgres>, player_name: &str) -> Vec<RollSingle> {
     query_as!(
         RollSingle,
-        r#"SELECT faces, roll_single.outcome FROM roll_single
+        r#"SELECT faces, roll_single.outcome, is_rerolled, is_fate, is_exploded, is_dropped FROM roll_single
             JOIN roll ON roll_single.roll_id = roll.id
             JOIN post ON roll.post_id = post.id
             JOIN sender ON post.sender_id = sender.id
@@ -226,15 +195,14 @@ pub async fn fetch_all_parseable_rolls(
             JOIN alias ON sender.id = alias.sender_id
             JOIN player ON alias.player_id = player.id
         WHERE
-            formula NOT LIKE '%k%' AND
-            formula NOT LIKE '%ro%' AND
-            formula NOT LIKE '%dF%' AND
-            formula LIKE '%d%'"#
+            formula LIKE '%d%' AND
+            post.visibility = 'public'"#
     )
     .fetch_all(pool)
     .await
     .unwrap_or(vec![])
     .into_iter()
+    .filter(|rec| parse::parse_expression(&rec.formula).is_ok())
     .map(|rec| {
         (
             rec.player_name,
@@ -263,6 +231,7 @@ pub async fn fetch_random_chat_message(
             JOIN alias ON sender.id = alias.sender_id
             JOIN player ON alias.player_id = player.id
         WHERE
+            visibility = 'public' AND
             LOWER(campaign_name) LIKE LOWER('%' || $1 || '%') AND
             LOWER(sender_name) LIKE LOWER('%' || $2 || '%') AND
             LOWER(player_name) LIKE LOWER('%' || $3 || '%')"#,
@@ -383,6 +352,7 @@ pub async fn trace_message(pool: &Pool<Postgres>, message: &str) -> Option<Vec<M
             JOIN chat_message ON post.id = post_id
             JOIN campaign ON post.campaign_id = campaign.id
         WHERE
+            visibility = 'public' AND
             LOWER(content) = LOWER( $1 )"#,
         message.trim()
     )
@@ -422,6 +392,7 @@ pub async fn search_for_message(
             JOIN chat_message ON post.id = post_id
             JOIN campaign ON post.campaign_id = campaign.id
         WHERE
+            visibility = 'public' AND
             LOWER(content) LIKE '%' || LOWER( $1 ) || '%'
         ORDER BY
             timestamp_sent DESC
@@ -481,6 +452,7 @@ pub async fn trace_around_message(
                 JOIN chat_message ON post.id = post_id
                 JOIN campaign ON post.campaign_id = campaign.id
             WHERE
+                visibility = 'public' AND
                 campaign.id = (SELECT campaign_id FROM post_timestamp)
         )
         SELECT *
@@ -489,7 +461,7 @@ pub async fn trace_around_message(
             WHERE timestamp_sent <= (SELECT timestamp_sent FROM post_timestamp)
             ORDER BY timestamp_sent DESC
             LIMIT 1 + $2
-        )
+        ) AS before_post
         UNION
         (
             SELECT * FROM joined_fields
@@ -2,20 +2,24 @@ pub use alias::Alias;
 use async_trait::async_trait;
 pub use campaign::Campaign;
 pub use censor::Censor;
+pub use import_checkpoint::ImportCheckpoint;
 pub use player::Player;
 pub use post_interface::PostInterface;
 pub use pronouns::Pronouns;
 pub use pronouns_map::PronounsMap;
+pub use roll_macro::RollMacro;
 pub use sender::Sender;
 use sqlx::{Postgres, Transaction};
 
 mod alias;
 mod campaign;
 mod censor;
+mod import_checkpoint;
 mod player;
 mod post_interface;
 mod pronouns;
 mod pronouns_map;
+mod roll_macro;
 mod sender;
 
 #[async_trait]
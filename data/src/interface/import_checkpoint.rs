@@ -0,0 +1,130 @@
+use parse::{LogPosition, ResumePoint};
+use sha2::{Digest, Sha256};
+use sqlx::{query, Pool, Postgres};
+use std::{io, path::Path};
+use tokio::{fs::File, io::AsyncReadExt};
+
+/// A SHA-256 hash of the first `length` bytes of the file at `path`.
+async fn hash_prefix(path: &Path, length: u64) -> io::Result<String> {
+    let mut file = File::open(path).await?.take(length);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Where the last import of a campaign's log stopped, so the next one can skip what was already
+/// read as long as the log has only had posts added to the end since.
+pub struct ImportCheckpoint {
+    pub log: String,
+    /// How big the log was when it was imported.
+    pub file_size: u64,
+    /// A hash of the log up to `resume_point`, to tell whether anything before it has changed.
+    pub prefix_hash: String,
+    pub resume_point: ResumePoint,
+    pub last_post_id: String,
+}
+
+impl ImportCheckpoint {
+    /// Takes a checkpoint of the log at `path_to_log`, which was read up to `resume_point`.
+    pub async fn take(
+        log: &str,
+        path_to_log: &Path,
+        resume_point: ResumePoint,
+        last_post_id: String,
+    ) -> io::Result<Self> {
+        let file_size = tokio::fs::metadata(path_to_log).await?.len();
+        let prefix_hash = hash_prefix(path_to_log, resume_point.position.offset).await?;
+
+        Ok(ImportCheckpoint {
+            log: log.to_string(),
+            file_size,
+            prefix_hash,
+            resume_point,
+            last_post_id,
+        })
+    }
+
+    /// Whether the log at `path_to_log` is the one the checkpoint was taken of, with nothing
+    /// changed but posts added to the end.
+    pub async fn has_only_grown(&self, log: &str, path_to_log: &Path) -> bool {
+        if log != self.log {
+            return false;
+        }
+        let Ok(metadata) = tokio::fs::metadata(path_to_log).await else {
+            return false;
+        };
+        if metadata.len() < self.file_size {
+            return false;
+        }
+
+        hash_prefix(path_to_log, self.resume_point.position.offset)
+            .await
+            .is_ok_and(|prefix_hash| prefix_hash == self.prefix_hash)
+    }
+
+    pub async fn fetch(pool: &Pool<Postgres>, campaign_id: i32) -> Option<Self> {
+        let values = query!(
+            r#"SELECT log, file_size, prefix_hash, resume_offset, resume_line, last_post_id,
+                last_sender_name, last_timestamp
+            FROM import_checkpoint
+            WHERE campaign_id = $1"#,
+            campaign_id
+        )
+        .fetch_optional(pool)
+        .await
+        .expect("failed to fetch import checkpoint")?;
+
+        Some(ImportCheckpoint {
+            log: values.log,
+            file_size: values.file_size as u64,
+            prefix_hash: values.prefix_hash,
+            resume_point: ResumePoint {
+                position: LogPosition {
+                    offset: values.resume_offset as u64,
+                    line: values.resume_line as usize,
+                },
+                sender_name: values.last_sender_name,
+                datetime: values.last_timestamp.fixed_offset(),
+            },
+            last_post_id: values.last_post_id,
+        })
+    }
+
+    /// Saves the checkpoint, replacing the campaign's last one.
+    pub async fn upsert(&self, pool: &Pool<Postgres>, campaign_id: i32) {
+        query!(
+            r#"INSERT INTO import_checkpoint (campaign_id, log, file_size, prefix_hash,
+                resume_offset, resume_line, last_post_id, last_sender_name, last_timestamp)
+            VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9 )
+            ON CONFLICT (campaign_id) DO UPDATE SET
+                log = EXCLUDED.log,
+                file_size = EXCLUDED.file_size,
+                prefix_hash = EXCLUDED.prefix_hash,
+                resume_offset = EXCLUDED.resume_offset,
+                resume_line = EXCLUDED.resume_line,
+                last_post_id = EXCLUDED.last_post_id,
+                last_sender_name = EXCLUDED.last_sender_name,
+                last_timestamp = EXCLUDED.last_timestamp"#,
+            campaign_id,
+            self.log,
+            self.file_size as i64,
+            self.prefix_hash,
+            self.resume_point.position.offset as i64,
+            self.resume_point.position.line as i32,
+            self.last_post_id,
+            self.resume_point.sender_name,
+            self.resume_point.datetime,
+        )
+        .execute(pool)
+        .await
+        .expect("failed to save import checkpoint");
+    }
+}
@@ -9,42 +9,50 @@ pub struct PostInterface<'a> {
 }
 
 impl<'a> PostInterface<'a> {
-    pub async fn try_insert(&mut self) -> sqlx::Result<()> {
+    /// The id a post is stored under, which is the same every time its log is imported.
+    pub fn post_id(campaign_id: i32, source_id: &str) -> String {
+        let name = format!("{campaign_id}/{source_id}");
+        Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()).to_string()
+    }
+
+    /// Inserts the post, or updates it if it was already imported, so importing the same log
+    /// again doesn't change anything.
+    pub async fn try_upsert(&mut self) -> sqlx::Result<()> {
         let sender_id = query!(
-            r#"SELECT id FROM sender WHERE sender_name = $1"#,
+            r#"SELECT id FROM sender WHERE sender_name = $1 AND campaign_id = $2"#,
             self.post.sender_name,
+            self.campaign_id,
         )
         .fetch_one(&mut *self.transaction)
         .await?
         .id;
 
-        // let insert_id = if self.post.is_message {
-        //     format!("{}{}", "mes_", self.post.id)
-        // } else if self.post.rolls.len() > 0 {
-        //     format!("{}{}", "rol_", self.post.id)
-        // } else {
-        //     format!("{}{}", "gen_", self.post.id)
-        // };
-        let insert_id = Uuid::new_v4().to_string();
+        let id = Self::post_id(self.campaign_id, &self.post.id);
 
-        let id =
-            query!(
-                r#"INSERT INTO post (id, campaign_id, sender_id, timestamp_sent)
-                VALUES ( $1, $2, $3, $4 )
-                RETURNING id"#,
-                insert_id,
-                self.campaign_id,
-                sender_id,
-                self.post.datetime,
-            )
-            .fetch_one(&mut *self.transaction)
-            .await?
-            .id;
+        query!(
+            r#"INSERT INTO post (id, campaign_id, sender_id, timestamp_sent, visibility)
+            VALUES ( $1, $2, $3, $4, $5 )
+            ON CONFLICT (id) DO UPDATE SET
+                sender_id = EXCLUDED.sender_id,
+                timestamp_sent = EXCLUDED.timestamp_sent,
+                visibility = EXCLUDED.visibility
+            WHERE (post.sender_id, post.timestamp_sent, post.visibility)
+                IS DISTINCT FROM (EXCLUDED.sender_id, EXCLUDED.timestamp_sent, EXCLUDED.visibility)"#,
+            id,
+            self.campaign_id,
+            sender_id,
+            self.post.datetime,
+            self.post.visibility.as_str(),
+        )
+        .execute(&mut *self.transaction)
+        .await?;
 
         if self.post.is_message {
             query!(
                 r#"INSERT INTO chat_message (post_id, content)
-                VALUES ( $1, $2 )"#,
+                VALUES ( $1, $2 )
+                ON CONFLICT (post_id) DO UPDATE SET content = EXCLUDED.content
+                WHERE chat_message.content IS DISTINCT FROM EXCLUDED.content"#,
                 id,
                 self.post.content_raw,
             )
@@ -52,6 +60,18 @@ impl<'a> PostInterface<'a> {
             .await?;
         }
 
+        // Rolls can't be edited after they're made, so a post's rolls are only ever inserted once.
+        let has_rolls = query!(
+            r#"SELECT EXISTS(SELECT 1 FROM roll WHERE post_id = $1) AS "has_rolls!""#,
+            id,
+        )
+        .fetch_one(&mut *self.transaction)
+        .await?
+        .has_rolls;
+        if has_rolls {
+            return Ok(());
+        }
+
         for roll in &self.post.rolls {
             let roll_id = query!(
                 r#"INSERT INTO roll (post_id, formula, outcome)
@@ -67,11 +87,15 @@ impl<'a> PostInterface<'a> {
 
             for single_roll in &roll.single_rolls {
                 query!(
-                    r#"INSERT INTO roll_single (roll_id, faces, outcome)
-                    VALUES ( $1, $2, $3)"#,
+                    r#"INSERT INTO roll_single (roll_id, faces, outcome, is_rerolled, is_fate, is_exploded, is_dropped)
+                    VALUES ( $1, $2, $3, $4, $5, $6, $7 )"#,
                     roll_id,
                     single_roll.faces,
                     single_roll.outcome,
+                    single_roll.is_rerolled,
+                    single_roll.is_fate,
+                    single_roll.is_exploded,
+                    single_roll.is_dropped,
                 )
                 .execute(&mut *self.transaction)
                 .await?;
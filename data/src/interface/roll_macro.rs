@@ -0,0 +1,132 @@
+use super::{IdInterface, ShapeInterface};
+use async_trait::async_trait;
+use sqlx::{query, Postgres, Transaction};
+
+/// A player's saved `#macro` or `@variable`, with the sigil kept as part of its name.
+pub struct RollMacro<'a> {
+    pub macro_name: &'a str,
+    pub expression: &'a str,
+    pub player_id: i32,
+}
+
+impl<'a, 'tr> RollMacro<'a> {
+    /// Saves the macro, replacing the player's old definition if they already had one by this name.
+    pub async fn upsert(&self, transaction: &'a mut Transaction<'tr, Postgres>) -> i32 {
+        query!(
+            r#"INSERT INTO roll_macro (macro_name, expression, player_id)
+            VALUES ( $1, $2, $3 )
+            ON CONFLICT (player_id, macro_name)
+                DO UPDATE SET expression = EXCLUDED.expression
+            RETURNING id"#,
+            self.macro_name,
+            self.expression,
+            self.player_id
+        )
+        .fetch_one(&mut **transaction)
+        .await
+        .expect("failed to save roll macro")
+        .id
+    }
+
+    /// Deletes the player's macro with the given name, returning whether there was one.
+    pub async fn delete(
+        transaction: &'a mut Transaction<'tr, Postgres>,
+        macro_name: &str,
+        player_id: i32,
+    ) -> bool {
+        query!(
+            r#"DELETE FROM roll_macro
+            WHERE macro_name = $1 AND player_id = $2"#,
+            macro_name,
+            player_id
+        )
+        .execute(&mut **transaction)
+        .await
+        .expect("failed to delete roll macro")
+        .rows_affected()
+            > 0
+    }
+}
+
+#[async_trait]
+impl<'a, 'tr> ShapeInterface<'a, 'tr> for RollMacro<'a> {
+    type Shape = (String, String, i32);
+
+    async fn from_values(values_tuple: &'a Self::Shape) -> Self {
+        Self {
+            macro_name: &values_tuple.0,
+            expression: &values_tuple.1,
+            player_id: values_tuple.2,
+        }
+    }
+
+    async fn try_fetch_values(
+        transaction: &'a mut Transaction<'tr, Postgres>,
+        id: i32,
+    ) -> sqlx::Result<Self::Shape> {
+        let values = query!(
+            r#"SELECT macro_name, expression, player_id
+            FROM roll_macro
+            WHERE id = $1"#,
+            id
+        )
+        .fetch_one(&mut **transaction)
+        .await?;
+
+        Ok((values.macro_name, values.expression, values.player_id))
+    }
+}
+
+#[async_trait]
+impl<'a, 'tr> IdInterface<'a, 'tr> for RollMacro<'a> {
+    type IdType = i32;
+
+    async fn try_fetch_id(
+        &self,
+        transaction: &'a mut Transaction<'tr, Postgres>,
+    ) -> sqlx::Result<Self::IdType> {
+        let id = query!(
+            r#"SELECT id FROM roll_macro
+            WHERE macro_name = $1 AND player_id = $2"#,
+            self.macro_name,
+            self.player_id,
+        )
+        .fetch_one(&mut **transaction)
+        .await?
+        .id;
+
+        Ok(id)
+    }
+
+    async fn try_insert(
+        &self,
+        transaction: &'a mut Transaction<'tr, Postgres>,
+    ) -> sqlx::Result<Self::IdType> {
+        let id = query!(
+            r#"INSERT INTO roll_macro (macro_name, expression, player_id)
+            VALUES ( $1, $2, $3 )
+            RETURNING id"#,
+            self.macro_name,
+            self.expression,
+            self.player_id
+        )
+        .fetch_one(&mut **transaction)
+        .await?
+        .id;
+
+        Ok(id)
+    }
+
+    async fn fetch_or_insert_id(
+        &self,
+        transaction: &'a mut Transaction<'tr, Postgres>,
+    ) -> Self::IdType {
+        if let Ok(id) = self.try_fetch_id(&mut *transaction).await {
+            return id;
+        }
+
+        self.try_insert(&mut *transaction)
+            .await
+            .expect("failed to insert new roll macro record")
+    }
+}
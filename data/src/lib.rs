@@ -2,10 +2,10 @@ pub use fetch::*;
 pub use interface::*;
 use parse::{
     parse_config::{CampaignConfig, Config, PlayerConfig},
-    ChatLog,
+    ChatLogRegistry, Post, ResumePoint,
 };
 use sqlx::{postgres::PgPoolOptions, query, Pool, Postgres, Transaction};
-use std::env;
+use std::{env, path::Path};
 
 mod fetch;
 mod interface;
@@ -100,6 +100,15 @@ pub async fn update_players<'a, 'tr>(
             .await
             .expect("failed to prune pronouns_map for stale player data");
 
+            query!(
+                r#"DELETE FROM roll_macro
+                WHERE player_id = $1"#,
+                player_id
+            )
+            .execute(&mut **transaction)
+            .await
+            .expect("failed to prune roll_macro for stale player data");
+
             query!(
                 r#"DELETE FROM player
                 WHERE id = $1"#,
@@ -119,9 +128,12 @@ pub async fn update_campaigns<'a, 'tr>(
     let current_campaigns = &config.campaigns;
 
     let mut valid_campaigns: Vec<i32> = vec![];
-    for (campaign_name, campaign_config) in current_campaigns {
+    for campaign_name in current_campaigns.keys() {
+        let campaign_config = config
+            .campaign_with_discord_aliases(campaign_name)
+            .expect("failed to find config for campaign");
         let campaign_id =
-            update_campaign_from_config(&mut *transaction, campaign_name, campaign_config).await;
+            update_campaign_from_config(&mut *transaction, campaign_name, &campaign_config).await;
         valid_campaigns.push(campaign_id);
     }
 
@@ -161,6 +173,15 @@ pub async fn update_campaigns<'a, 'tr>(
             .await
             .expect("failed to prune sender table for stale campaign data");
 
+            query!(
+                r#"DELETE FROM import_checkpoint
+                WHERE campaign_id = $1"#,
+                campaign_id
+            )
+            .execute(&mut **transaction)
+            .await
+            .expect("failed to prune import_checkpoint for stale campaign data");
+
             query!(
                 r#"DELETE FROM campaign
                 WHERE id = $1"#,
@@ -173,14 +194,58 @@ pub async fn update_campaigns<'a, 'tr>(
     }
 }
 
-// TODO: Ensure there's no way to improve this code with better polymorphism
+/// Records a post made through the bot, like a `/roll`, under the given campaign.
+pub async fn insert_discord_post(
+    pool: &Pool<Postgres>,
+    campaign_name: &str,
+    post: Post,
+) -> sqlx::Result<()> {
+    let campaign_id = query!(
+        r#"SELECT id FROM campaign WHERE campaign_name = $1"#,
+        campaign_name
+    )
+    .fetch_one(pool)
+    .await?
+    .id;
+
+    let transaction = begin_transaction(pool).await;
+    let mut interface = PostInterface {
+        transaction,
+        post,
+        campaign_id,
+    };
+
+    interface.try_upsert().await?;
+    interface.transaction.commit().await
+}
+
+/// Imports a campaign's chat log with whichever parser in `registry` handles its format. If the
+/// log has only had posts added since it was last imported, it picks up from where that import
+/// stopped, and otherwise the whole log is imported again.
 pub async fn update_posts_from_log(
     pool: &Pool<Postgres>,
+    registry: &ChatLogRegistry,
     campaign_name: &str,
     directory: &str,
-    filename: &str,
-    timezone_offset: Option<i32>,
+    campaign_config: &CampaignConfig,
 ) {
+    let filename = campaign_config.log.as_str();
+    let Some(format) = campaign_config.log_format(directory).await else {
+        if !filename.is_empty() {
+            println!("Couldn't tell what kind of chat log {filename} is, so it was skipped");
+        }
+        return;
+    };
+
+    let path_to_log = format!("{directory}/{filename}");
+    let Some(mut log) = registry
+        .open_log(&format, &path_to_log, campaign_config)
+        .await
+    else {
+        println!("No parser is registered for {format} logs, so {filename} was skipped");
+        return;
+    };
+
     let campaign_id = query!(
         r#"SELECT id FROM campaign WHERE campaign_name = $1"#,
         campaign_name
@@ -190,97 +255,69 @@ pub async fn update_posts_from_log(
     .expect("failed to fetch campaign id")
     .id;
 
-    let fetch_ids_transaction = begin_transaction(pool).await;
-    // let already_parsed_hash = fetch_parsed_post_ids(&mut fetch_ids_transaction, campaign_id).await;
-    fetch_ids_transaction
-        .rollback()
-        .await
-        .expect("failed to rollback fetch transaction");
-
-    let path_to_log = format!("{directory}/{filename}");
-
-    if filename.starts_with("fnd_") {
-        let mut log = parse::parse_foundry_log(&path_to_log, timezone_offset).await;
-
-        while let Some(post) = log.next_post().await {
-            // if already_parsed_hash.contains(&post.id) {
-            //     continue;
-            // }
-
-            let transaction = begin_transaction(&pool).await;
+    if let Some(checkpoint) = ImportCheckpoint::fetch(pool, campaign_id).await
+        && checkpoint
+            .has_only_grown(filename, Path::new(&path_to_log))
+            .await
+        && log.resume(&checkpoint.resume_point).await
+    {
+        println!("Picking {filename} up from where the last import stopped");
+    }
 
-            let mut interface = PostInterface {
-                transaction,
-                post,
-                campaign_id,
-            };
+    let mut skipped = 0;
+    let mut has_unmapped_senders = false;
+    let mut read_up_to: Option<(ResumePoint, String)> = None;
+    while let Some(post) = log.next_post().await {
+        let post = match post {
+            Ok(post) => post,
+            Err(error) => {
+                println!("Skipped part of {filename} that couldn't be read - {error}");
+                skipped += 1;
+                continue;
+            }
+        };
+
+        let resume_point = log.position().map(|position| ResumePoint {
+            position,
+            sender_name: post.sender_name.clone(),
+            datetime: post.datetime,
+        });
+        let post_id = post.id.clone();
+        let has_sender = !post.sender_name.is_empty();
+
+        let transaction = begin_transaction(pool).await;
+
+        let mut interface = PostInterface {
+            transaction,
+            post,
+            campaign_id,
+        };
+
+        let inserted = interface.try_upsert().await.is_ok();
+        interface
+            .transaction
+            .commit()
+            .await
+            .expect("failed to commit transaction");
 
-            interface.try_insert().await.unwrap_or(());
-            interface
-                .transaction
-                .commit()
-                .await
-                .expect("failed to commit transaction");
-        }
-    } else if filename.starts_with("r20_") {
-        let mut log = parse::parse_roll20_log(&path_to_log, timezone_offset).await;
-
-        while let Some(post) = log.next_post().await {
-            // if already_parsed_hash.contains(&post.id) {
-            //     continue;
-            // }
-
-            let transaction = begin_transaction(&pool).await;
-
-            let mut interface = PostInterface {
-                transaction,
-                post,
-                campaign_id,
-            };
-
-            interface.try_insert().await.unwrap_or(());
-            interface
-                .transaction
-                .commit()
-                .await
-                .expect("failed to commit transaction");
+        // Posts from senders that aren't in config.json yet are imported once they're added, so
+        // the next import has to start from before the first of them.
+        has_unmapped_senders |= !inserted && has_sender;
+        if !has_unmapped_senders && let Some(resume_point) = resume_point {
+            read_up_to = Some((resume_point, post_id));
         }
+    }
 
-//         if cfg!(debug_assertions) {
-//             println!(
-//                 "\
-// --Time spent parsing div depth: {:#?}
-// --Time spent parsing post fragments: {:#?}
-// --Time spent updating timestamps: {:#?}
-// --Time spent updating sender names: {:#?}",
-//                 log.time_spent_parsing_div_depth,
-//                 log.time_spent_parsing_fragments,
-//                 log.time_spent_updating_datetime,
-//                 log.time_spent_updating_sender
-//             );
-//         }
-    } else if filename.starts_with("fg_") {
-        let mut log = parse::parse_fantasy_grounds_log(&path_to_log, timezone_offset).await;
-
-        while let Some(post) = log.next_post().await {
-            // if already_parsed_hash.contains(&post.id) {
-            //     continue;
-            // }
-
-            let transaction = begin_transaction(&pool).await;
-
-            let mut interface = PostInterface {
-                transaction,
-                post,
-                campaign_id,
-            };
-
-            interface.try_insert().await.unwrap_or(());
-            interface
-                .transaction
-                .commit()
-                .await
-                .expect("failed to commit transaction");
+    if skipped > 0 {
+        println!("{skipped} posts in {filename} couldn't be read and were skipped");
+    }
+
+    if let Some((resume_point, last_post_id)) = read_up_to {
+        match ImportCheckpoint::take(filename, Path::new(&path_to_log), resume_point, last_post_id)
+            .await
+        {
+            Ok(checkpoint) => checkpoint.upsert(pool, campaign_id).await,
+            Err(error) => println!("Couldn't save where {filename} was read up to - {error}"),
         }
     }
 }
@@ -0,0 +1,2 @@
+ALTER TABLE roll_single
+DROP COLUMN IF EXISTS is_rerolled;
@@ -0,0 +1,2 @@
+ALTER TABLE roll_single
+ADD COLUMN IF NOT EXISTS is_fate BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,3 @@
+ALTER TABLE roll_single
+DROP COLUMN IF EXISTS is_exploded,
+DROP COLUMN IF EXISTS is_dropped;
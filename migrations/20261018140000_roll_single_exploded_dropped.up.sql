@@ -0,0 +1,3 @@
+ALTER TABLE roll_single
+ADD COLUMN IF NOT EXISTS is_exploded BOOLEAN NOT NULL DEFAULT false,
+ADD COLUMN IF NOT EXISTS is_dropped BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS roll_macro (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  player_id INTEGER NOT NULL REFERENCES player,
+  macro_name TEXT NOT NULL,
+  expression TEXT NOT NULL,
+  UNIQUE(player_id, macro_name)
+);
+
+CREATE OR REPLACE PROCEDURE clear_all_tables() LANGUAGE plpgsql AS $$ BEGIN TRUNCATE roll_single,
+  roll,
+  chat_message,
+  post,
+  alias,
+  sender,
+  campaign,
+  censor,
+  pronouns_map,
+  roll_macro,
+  player,
+  pronouns;
+
+END;
+
+$$;
@@ -0,0 +1,2 @@
+ALTER TABLE post
+ADD COLUMN IF NOT EXISTS visibility TEXT NOT NULL DEFAULT 'public' CHECK (visibility IN ('public', 'whisper', 'blind', 'self'));
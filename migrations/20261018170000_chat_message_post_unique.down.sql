@@ -0,0 +1,2 @@
+ALTER TABLE chat_message
+DROP CONSTRAINT IF EXISTS chat_message_post_id_key;
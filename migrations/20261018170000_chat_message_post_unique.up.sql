@@ -0,0 +1,2 @@
+ALTER TABLE chat_message
+ADD CONSTRAINT chat_message_post_id_key UNIQUE (post_id);
@@ -0,0 +1,18 @@
+CREATE OR REPLACE PROCEDURE clear_all_tables() LANGUAGE plpgsql AS $$ BEGIN TRUNCATE roll_single,
+  roll,
+  chat_message,
+  post,
+  alias,
+  sender,
+  campaign,
+  censor,
+  pronouns_map,
+  roll_macro,
+  player,
+  pronouns;
+
+END;
+
+$$;
+
+DROP TABLE IF EXISTS import_checkpoint;
@@ -0,0 +1,29 @@
+CREATE TABLE IF NOT EXISTS import_checkpoint (
+  campaign_id INTEGER PRIMARY KEY REFERENCES campaign,
+  log TEXT NOT NULL,
+  file_size BIGINT NOT NULL,
+  prefix_hash TEXT NOT NULL,
+  resume_offset BIGINT NOT NULL,
+  resume_line INTEGER NOT NULL,
+  last_post_id TEXT NOT NULL,
+  last_sender_name TEXT NOT NULL,
+  last_timestamp TIMESTAMPTZ NOT NULL
+);
+
+CREATE OR REPLACE PROCEDURE clear_all_tables() LANGUAGE plpgsql AS $$ BEGIN TRUNCATE roll_single,
+  roll,
+  chat_message,
+  post,
+  import_checkpoint,
+  alias,
+  sender,
+  campaign,
+  censor,
+  pronouns_map,
+  roll_macro,
+  player,
+  pronouns;
+
+END;
+
+$$;
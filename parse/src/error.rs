@@ -0,0 +1,45 @@
+use std::{fmt, io};
+
+/// Why part of a chat log couldn't be read. Logs keep going after any of these, so a bad line
+/// only costs the post it was part of. `line` is the line the problem was found at, or for logs
+/// that aren't read line by line, which message it was.
+#[derive(Debug)]
+pub enum ParseError {
+    /// Reading the log failed, like when a line isn't valid UTF-8.
+    Io { line: usize, error: io::Error },
+    /// Something that should have been a post, but couldn't be understood.
+    Malformed { line: usize, reason: String },
+}
+
+impl ParseError {
+    pub fn line(&self) -> usize {
+        match self {
+            ParseError::Io { line, .. } | ParseError::Malformed { line, .. } => *line,
+        }
+    }
+
+    pub(crate) fn malformed(line: usize, reason: impl fmt::Display) -> Self {
+        ParseError::Malformed {
+            line,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io { line, error } => write!(f, "couldn't read line {line}: {error}"),
+            ParseError::Malformed { line, reason } => write!(f, "line {line}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io { error, .. } => Some(error),
+            ParseError::Malformed { .. } => None,
+        }
+    }
+}
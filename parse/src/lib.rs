@@ -1,37 +1,92 @@
 use async_trait::async_trait;
-use parse_config::Config;
+pub use error::ParseError;
+use futures::{stream, Stream};
+pub use log_format::{detect_log_format, LogFormat};
+use parse_config::{Config, JsonMapping, TranscriptFormat};
 pub use parse_dicemath::{
-    dicemath, get_roll_from_expression_and_outcomes, num_with_thousands_commas,
+    dicemath, dicemath_distribution, dicemath_repeated, dicemath_result, dicemath_with,
+    displayed_single_rolls, expand_macros, get_roll_from_expression_and_outcomes, is_macro_name, num_with_thousands_commas,
+    parse_expression, parse_expression_with, parse_macro_definition, replace_inline_rolls,
+    split_repeat, tokenize, BinaryOp, Comparison, Dice, DiceError, DiceLimits, DiceResult,
+    Distribution, Explosion, Expr, Faces, Modifier, ResultKind, Token, TokenKind,
 };
+use parse_discord::DiscordChatLog;
 use parse_fantasy_grounds::FantasyGroundsChatLog;
 use parse_foundry::FoundryChatLog;
+use parse_json_lines::JsonLinesChatLog;
 use parse_random_message_templates::RandomMessageTemplates;
 use parse_roll_20::Roll20ChatLog;
+use parse_transcript::TranscriptChatLog;
 use rand::seq::SliceRandom;
+pub use registry::{BoxedChatLog, ChatLogFactory, ChatLogFuture, ChatLogRegistry};
 use sqlx::types::chrono::{DateTime, FixedOffset};
-use std::path::Path;
-use tokio::{fs::File, io::AsyncReadExt};
+use std::{
+    io::{self, ErrorKind, SeekFrom},
+    path::Path,
+    pin::Pin,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader},
+};
 pub use util::*;
 
+mod error;
+mod log_format;
 pub mod parse_config;
 mod parse_dicemath;
+mod parse_discord;
 mod parse_fantasy_grounds;
 mod parse_foundry;
+mod parse_json_lines;
 mod parse_random_message_templates;
 mod parse_roll_20;
+mod parse_transcript;
+mod registry;
 pub mod util;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct RollSingle {
     pub faces: i64,
     pub outcome: i64,
+    pub is_rerolled: bool,
+    pub is_fate: bool,
+    pub is_exploded: bool,
+    pub is_dropped: bool,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Roll {
     pub formula: String,
     pub outcome: f64,
     pub single_rolls: Vec<RollSingle>,
 }
 
+/// Who could see a post when it was sent. Only public posts are ever quoted back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Whispered to some players or the GM.
+    Whisper,
+    /// Rolled blind, so only the GM saw the result.
+    Blind,
+    /// Only the sender saw it.
+    SelfOnly,
+}
+
+impl Visibility {
+    /// How the visibility is stored in `post.visibility`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Whisper => "whisper",
+            Visibility::Blind => "blind",
+            Visibility::SelfOnly => "self",
+        }
+    }
+}
+
 pub struct Post {
     pub id: String,
     pub sender_name: String,
@@ -39,13 +94,127 @@ pub struct Post {
     pub content_raw: String,
     pub is_message: bool,
     pub rolls: Vec<Roll>,
+    pub visibility: Visibility,
 }
 
 #[async_trait]
 pub trait ChatLog {
-    async fn new(file: File, timezone_offset: Option<i32>) -> Self;
+    async fn new(file: File, timezone_offset: Option<i32>) -> Self
+    where
+        Self: Sized;
+
+    /// The next post in the log, or `None` once it's finished. An `Err` only means that one post
+    /// couldn't be read, so there can still be more after it.
+    async fn next_post(&mut self) -> Option<Result<Post, ParseError>>;
+
+    /// How far into its file the log has read so far, if it can `resume` from there later.
+    fn position(&self) -> Option<LogPosition> {
+        None
+    }
+
+    /// Skips to where an earlier read of the same file got to, returning `false` if the log can't
+    /// and will be read from the start instead.
+    async fn resume(&mut self, _from: &ResumePoint) -> bool {
+        false
+    }
+}
+
+pub type PostStream = Pin<Box<dyn Stream<Item = Result<Post, ParseError>> + Send>>;
+
+/// Reads every post in a log as a `Stream`, so they can be batched, filtered or counted with
+/// `StreamExt`.
+pub fn post_stream(log: BoxedChatLog) -> PostStream {
+    Box::pin(stream::unfold(log, |mut log| async move {
+        let post = log.next_post().await?;
+        Some((post, log))
+    }))
+}
+
+/// How far into its file a log has read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LogPosition {
+    /// How many bytes have been read.
+    pub offset: u64,
+    /// How many lines those bytes were.
+    pub line: usize,
+}
+
+/// Where an earlier read of a log got to, so reading the same file again can pick up from there.
+/// Logs carry some things on from one post to the next, so it has the last post's sender and when
+/// it was sent too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumePoint {
+    pub position: LogPosition,
+    pub sender_name: String,
+    pub datetime: DateTime<FixedOffset>,
+}
+
+/// Reads a log line by line, keeping count of which line it's on for `ParseError`s.
+pub(crate) struct LogLines {
+    reader: BufReader<File>,
+    position: LogPosition,
+    finished: bool,
+}
+
+impl LogLines {
+    fn new(file: File) -> Self {
+        LogLines {
+            reader: BufReader::new(file),
+            position: LogPosition::default(),
+            finished: false,
+        }
+    }
+
+    /// The line that was read last, counting from 1.
+    fn line(&self) -> usize {
+        self.position.line
+    }
+
+    fn position(&self) -> LogPosition {
+        self.position
+    }
+
+    /// Skips to a position an earlier read of the same file got to.
+    async fn seek(&mut self, position: LogPosition) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(position.offset)).await?;
+        self.position = position;
 
-    async fn next_post(&mut self) -> Option<Post>;
+        Ok(())
+    }
+
+    /// A line that isn't valid UTF-8 is an error that can be skipped, but any other error ends
+    /// the log, since reading any further would only fail again.
+    async fn next_line(&mut self) -> Option<Result<String, ParseError>> {
+        if self.finished {
+            return None;
+        }
+
+        let mut line = vec![];
+        let read = match self.reader.read_until(b'\n', &mut line).await {
+            Ok(0) => return None,
+            Ok(read) => read,
+            Err(error) => {
+                self.finished = true;
+                return Some(Err(ParseError::Io {
+                    line: self.position.line + 1,
+                    error,
+                }));
+            }
+        };
+        self.position.offset += read as u64;
+        self.position.line += 1;
+
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        Some(String::from_utf8(line).map_err(|error| ParseError::Io {
+            line: self.position.line,
+            error: io::Error::new(ErrorKind::InvalidData, error),
+        }))
+    }
 }
 
 async fn validate_and_open_file(
@@ -85,6 +254,13 @@ async fn validate_and_open_file(
     )))
 }
 
+/// Logs can be named anything, since their format is worked out from what's in them.
+async fn open_log_file(path: &Path) -> File {
+    File::open(path)
+        .await
+        .unwrap_or_else(|_| panic!("couldn't find or failed to open log '{}'", path.display()))
+}
+
 pub async fn parse_config(path_to_config: String) -> Config {
     let path_to_config = Path::new(&path_to_config);
     let mut file = validate_and_open_file(path_to_config, None, Some("config"), Some("json"))
@@ -101,22 +277,20 @@ pub async fn parse_config(path_to_config: String) -> Config {
 
 pub async fn parse_foundry_log(path_to_log: &str, timezone_offset: Option<i32>) -> FoundryChatLog {
     let path = Path::new(path_to_log);
-    let file = validate_and_open_file(path, Some("fnd_"), None, Some("db"))
-        .await
-        .expect(
-            "wrong filename format for foundry log - see README or config.example.json for help",
-        );
+
+    // Foundry v11 and newer keep messages in a LevelDB directory instead of a `.db` file.
+    if path.is_dir() {
+        return FoundryChatLog::from_leveldb(path).await;
+    }
+
+    let file = open_log_file(path).await;
 
     FoundryChatLog::new(file, timezone_offset).await
 }
 
 pub async fn parse_roll20_log(path_to_log: &str, timezone_offset: Option<i32>) -> Roll20ChatLog {
     let path = Path::new(path_to_log);
-    let file = validate_and_open_file(path, Some("r20_"), None, Some("html"))
-        .await
-        .expect(
-            "wrong filename format for roll20 log - see README or config.example.json for help",
-        );
+    let file = open_log_file(path).await;
 
     Roll20ChatLog::new(file, timezone_offset).await
 }
@@ -126,15 +300,41 @@ pub async fn parse_fantasy_grounds_log(
     timezone_offset: Option<i32>,
 ) -> FantasyGroundsChatLog {
     let path = Path::new(path_to_log);
-    let file = validate_and_open_file(path, Some("fg_"), None, Some("html"))
-        .await
-        .expect(
-            "wrong filename format for fantasy grounds log - see README or config.example.json for help",
-        );
+    let file = open_log_file(path).await;
 
     FantasyGroundsChatLog::new(file, timezone_offset).await
 }
 
+pub async fn parse_discord_log(path_to_log: &str, timezone_offset: Option<i32>) -> DiscordChatLog {
+    let path = Path::new(path_to_log);
+    let file = open_log_file(path).await;
+
+    DiscordChatLog::new(file, timezone_offset).await
+}
+
+pub async fn parse_json_lines_log(
+    path_to_log: &str,
+    timezone_offset: Option<i32>,
+    mapping: &JsonMapping,
+) -> JsonLinesChatLog {
+    let path = Path::new(path_to_log);
+    let file = open_log_file(path).await;
+
+    JsonLinesChatLog::with_mapping(file, timezone_offset, mapping.clone())
+}
+
+pub async fn parse_transcript_log(
+    path_to_log: &str,
+    timezone_offset: Option<i32>,
+    format: &TranscriptFormat,
+) -> TranscriptChatLog {
+    let path = Path::new(path_to_log);
+    let file = open_log_file(path).await;
+
+    TranscriptChatLog::with_format(file, timezone_offset, format.clone())
+        .expect("failed to compile transcript_format patterns - check config.json")
+}
+
 pub async fn get_random_message(path_to_templates: String) -> String {
     let path = Path::new(&path_to_templates);
 
@@ -0,0 +1,136 @@
+use serde_json::Value;
+use std::path::Path;
+use tokio::{fs::File, io::AsyncReadExt};
+
+/// How many bytes from the start of a log are read to work out its format.
+const SNIFF_LENGTH: u64 = 16 * 1024;
+
+/// The built-in kinds of chat log, which can be told apart by their contents or filenames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Foundry,
+    Roll20,
+    FantasyGrounds,
+    Discord,
+    JsonLines,
+    Transcript,
+}
+
+impl LogFormat {
+    /// The format the filename prefix used to pick, like `fnd_` for Foundry.
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        [
+            ("fnd_", LogFormat::Foundry),
+            ("r20_", LogFormat::Roll20),
+            ("fg_", LogFormat::FantasyGrounds),
+            ("dsc_", LogFormat::Discord),
+            ("jsl_", LogFormat::JsonLines),
+            ("txt_", LogFormat::Transcript),
+        ]
+        .into_iter()
+        .find(|(prefix, _)| filename.starts_with(prefix))
+        .map(|(_, format)| format)
+    }
+
+    /// The name the format is registered under in a `ChatLogRegistry`, which is also what a
+    /// campaign's `format` is set to.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogFormat::Foundry => "foundry",
+            LogFormat::Roll20 => "roll20",
+            LogFormat::FantasyGrounds => "fantasy_grounds",
+            LogFormat::Discord => "discord",
+            LogFormat::JsonLines => "json_lines",
+            LogFormat::Transcript => "transcript",
+        }
+    }
+
+    /// Works out a log's format from the start of its contents.
+    fn sniff(contents: &str) -> Option<Self> {
+        let contents = contents.trim_start();
+
+        if contents.starts_with('{') {
+            // DiscordChatExporter writes one JSON document that starts with the server and
+            // channel, while Foundry and most other VTTs write one JSON object per line.
+            let first_line = contents.lines().next()?.trim();
+            if first_line == "{"
+                && contents.contains("\"guild\"")
+                && contents.contains("\"channel\"")
+            {
+                return Some(LogFormat::Discord);
+            }
+
+            let first_post: Value = serde_json::from_str(first_line).ok()?;
+            if first_post.get("_id").is_some() && first_post.get("speaker").is_some() {
+                return Some(LogFormat::Foundry);
+            }
+            return Some(LogFormat::JsonLines);
+        }
+
+        if contents.contains("data-messageid") || contents.contains("textchatcontainer") {
+            return Some(LogFormat::Roll20);
+        }
+        if contents.contains("<font") && contents.contains("<br />") {
+            return Some(LogFormat::FantasyGrounds);
+        }
+
+        None
+    }
+}
+
+/// Works out what kind of chat log is at `path` by looking at what's in it, falling back to its
+/// filename prefix for logs like transcripts that can't be told apart by their contents. Foundry
+/// v11+ logs are the only ones that are directories.
+pub async fn detect_log_format(path: &Path) -> Option<LogFormat> {
+    let from_filename = LogFormat::from_filename(&path.file_name()?.to_string_lossy());
+    if path.is_dir() {
+        return Some(LogFormat::Foundry);
+    }
+
+    let mut start = vec![];
+    File::open(path)
+        .await
+        .ok()?
+        .take(SNIFF_LENGTH)
+        .read_to_end(&mut start)
+        .await
+        .ok()?;
+
+    LogFormat::sniff(&String::from_utf8_lossy(&start)).or(from_filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_formats() {
+        assert_eq!(
+            LogFormat::sniff(r#"{"_id": "a", "type": 2, "speaker": {"alias": "Bob"}}"#),
+            Some(LogFormat::Foundry)
+        );
+        assert_eq!(
+            LogFormat::sniff("{\n  \"guild\": {},\n  \"channel\": {},\n  \"messages\": []\n}"),
+            Some(LogFormat::Discord)
+        );
+        assert_eq!(
+            LogFormat::sniff(r#"{"messageId": "m1", "author": {"name": "Bob"}}"#),
+            Some(LogFormat::JsonLines)
+        );
+        assert_eq!(
+            LogFormat::sniff(r#"<div class="message general" data-messageid="-Tes">"#),
+            Some(LogFormat::Roll20)
+        );
+        assert_eq!(
+            LogFormat::sniff("<br />\n<font color=\"#000000\">Bob: hi</font><br />"),
+            Some(LogFormat::FantasyGrounds)
+        );
+        assert_eq!(LogFormat::sniff("[8:14 PM] Bob: I open the door"), None);
+
+        assert_eq!(
+            LogFormat::from_filename("txt_session.md"),
+            Some(LogFormat::Transcript)
+        );
+        assert_eq!(LogFormat::from_filename("session.md"), None);
+    }
+}
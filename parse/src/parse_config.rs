@@ -1,24 +1,129 @@
+use crate::{detect_log_format, DiceLimits};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
 #[derive(Deserialize)]
 pub struct PlayerConfig {
     pub pronouns: Vec<String>,
     pub deadnames: Vec<String>,
+    /// Discord usernames this player rolls from, so the bot knows whose macros to use.
+    #[serde(default)]
+    pub discord_usernames: Vec<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct AliasConfig {
     pub player: String,
     pub senders: Vec<String>,
 }
 
-#[derive(Deserialize)]
+/// How numeric timestamps in a JSON-lines log count time since the Unix epoch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampUnit {
+    Seconds,
+    #[default]
+    Milliseconds,
+}
+
+/// Where each part of a post is found in one line of a `jsl_` JSON-lines log. Fields are dotted
+/// paths like `author.name` or `rolls.0.total`, and any left out use the defaults below.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct JsonMapping {
+    pub id: String,
+    pub sender: String,
+    /// Either a number counted in `timestamp_unit`, or a string. Strings are read as RFC 3339,
+    /// or failing that with `timestamp_format` in the campaign's timezone.
+    pub timestamp: String,
+    pub timestamp_unit: TimestampUnit,
+    pub timestamp_format: String,
+    pub content: String,
+    /// Lines with a roll formula are imported as rolls rather than chat messages.
+    pub roll_formula: String,
+    pub roll_total: String,
+    /// An array of each die's outcome, if the log records them.
+    pub roll_dice: String,
+}
+
+impl Default for JsonMapping {
+    fn default() -> Self {
+        Self {
+            id: "id".to_string(),
+            sender: "sender".to_string(),
+            timestamp: "timestamp".to_string(),
+            timestamp_unit: TimestampUnit::default(),
+            timestamp_format: "%Y-%m-%d %H:%M:%S".to_string(),
+            content: "content".to_string(),
+            roll_formula: "roll.formula".to_string(),
+            roll_total: "roll.total".to_string(),
+            roll_dice: "roll.dice".to_string(),
+        }
+    }
+}
+
+/// How to read a `txt_` transcript. Patterns are regular expressions with named groups.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TranscriptFormat {
+    /// Matches the line a post starts on, with `sender` and `content` groups and optionally
+    /// `time` and `date` groups. Lines it doesn't match are added to the post before them.
+    pub line_pattern: String,
+    /// Matches a line giving the date of the posts after it, with a `date` group.
+    pub date_pattern: String,
+    pub date_format: String,
+    pub time_format: String,
+    /// Matches a post's content if it's a roll, with `formula` and `total` groups and optionally
+    /// a `dice` group listing each die's outcome.
+    pub roll_pattern: String,
+}
+
+impl Default for TranscriptFormat {
+    fn default() -> Self {
+        Self {
+            line_pattern: r"^\[(?P<time>[^\]]+)\]\s*(?P<sender>[^:]+):\s*(?P<content>.*)$".to_string(),
+            date_pattern: r"^(?:#+\s*)?(?P<date>\d{4}-\d{2}-\d{2})\s*$".to_string(),
+            date_format: "%Y-%m-%d".to_string(),
+            time_format: "%I:%M %p".to_string(),
+            roll_pattern: r"^(?:/r(?:oll)?\s+|rolls?\s+)?(?P<formula>[0-9dFkhlro!<>()+\-*/ ]*d[0-9dFkhlro!<>()+\-*/ ]*?)\s*[:=]\s*(?:\[(?P<dice>[^\]]*)\]\s*=\s*)?(?P<total>-?\d+(?:\.\d+)?)$".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
 pub struct CampaignConfig {
+    /// Left out for campaigns played on Discord, which don't have a log to import.
+    #[serde(default)]
     pub log: String,
+    /// What kind of chat log `log` is, if it shouldn't be worked out from the log itself. Any
+    /// format in the `ChatLogRegistry` can be named, including ones other crates registered.
+    #[serde(default)]
+    pub format: Option<String>,
     pub dungeon_master: String,
     pub timezone_offset: i32,
     pub aliases: Vec<AliasConfig>,
+    /// How to read the campaign's log if it's a `jsl_` JSON-lines log.
+    #[serde(default)]
+    pub json_mapping: JsonMapping,
+    /// How to read the campaign's log if it's a `txt_` transcript.
+    #[serde(default)]
+    pub transcript_format: TranscriptFormat,
+}
+
+impl CampaignConfig {
+    /// What kind of chat log the campaign's log is, or `None` if it doesn't have one or it
+    /// couldn't be worked out.
+    pub async fn log_format(&self, directory: &str) -> Option<String> {
+        if self.log.is_empty() {
+            return None;
+        }
+        if self.format.is_some() {
+            return self.format.clone();
+        }
+
+        let format = detect_log_format(&Path::new(directory).join(&self.log)).await?;
+        Some(format.name().to_string())
+    }
 }
 
 #[derive(Deserialize)]
@@ -26,12 +131,44 @@ pub struct Config {
     pub players: HashMap<String, PlayerConfig>,
     pub replace_all_deadnames_with: String,
     pub campaigns: HashMap<String, CampaignConfig>,
+    #[serde(default)]
+    pub dice_limits: DiceLimits,
+    /// The campaign that rolls made through the bot are recorded under, if any.
+    #[serde(default)]
+    pub discord_campaign: Option<String>,
 }
 
 impl Config {
     pub fn parse(config_json: &str) -> serde_json::Result<Self> {
         serde_json::from_str(config_json)
     }
+
+    /// The name of the player who uses the given Discord username, if any.
+    pub fn player_for_discord_user(&self, username: &str) -> Option<&str> {
+        self.players
+            .iter()
+            .find(|(_, player)| player.discord_usernames.iter().any(|name| name == username))
+            .map(|(player_name, _)| player_name.as_str())
+    }
+
+    /// The campaign's config, with each player's Discord usernames added as senders if it's the
+    /// Discord campaign.
+    pub fn campaign_with_discord_aliases(&self, campaign_name: &str) -> Option<CampaignConfig> {
+        let mut campaign = self.campaigns.get(campaign_name)?.clone();
+        if self.discord_campaign.as_deref() == Some(campaign_name) {
+            campaign.aliases.extend(
+                self.players
+                    .iter()
+                    .filter(|(_, player)| !player.discord_usernames.is_empty())
+                    .map(|(player_name, player)| AliasConfig {
+                        player: player_name.clone(),
+                        senders: player.discord_usernames.clone(),
+                    }),
+            );
+        }
+
+        Some(campaign)
+    }
 }
 
 #[cfg(test)]
@@ -49,7 +186,8 @@ mod tests {
                 },
                 "Bob": {
                     "pronouns": ["he/him/his/his"],
-                    "deadnames": [""]
+                    "deadnames": [""],
+                    "discord_usernames": ["cool_guy_420"]
                 },
                 "Sally": {
                     "pronouns": ["she/her/her/hers"],
@@ -57,6 +195,7 @@ mod tests {
                 }
             },
             "replace_all_deadnames_with": ":)",
+            "discord_campaign": "Tomb of Annihilation",
             "campaigns": {
                 "Curse of Strahd": {
                     "log": "r20_curse_of_strahd.html",
@@ -79,6 +218,11 @@ mod tests {
                             "senders": ["cool_guy 421"]
                         }
                     ]
+                },
+                "Tomb of Annihilation": {
+                    "dungeon_master": "Alex",
+                    "timezone_offset": -6,
+                    "aliases": []
                 }
             }
         }
@@ -90,7 +234,7 @@ mod tests {
             "he/him/his/his".to_owned()
         );
         assert_eq!(test_config.replace_all_deadnames_with, ":)".to_owned());
-        assert_eq!(test_config.campaigns.len(), 2);
+        assert_eq!(test_config.campaigns.len(), 3);
         assert_eq!(
             test_config
                 .campaigns
@@ -100,5 +244,26 @@ mod tests {
                 .senders[0],
             "cool_guy 420".to_owned()
         );
+        assert_eq!(
+            test_config.player_for_discord_user("cool_guy_420"),
+            Some("Bob")
+        );
+        assert_eq!(test_config.player_for_discord_user("Sally"), None);
+
+        let discord_campaign = test_config
+            .campaign_with_discord_aliases("Tomb of Annihilation")
+            .unwrap();
+        assert_eq!(discord_campaign.log, "");
+        assert_eq!(discord_campaign.aliases.len(), 1);
+        assert_eq!(discord_campaign.aliases[0].player, "Bob");
+        assert_eq!(discord_campaign.aliases[0].senders, vec!["cool_guy_420"]);
+        assert_eq!(
+            test_config
+                .campaign_with_discord_aliases("Curse of Strahd")
+                .unwrap()
+                .aliases
+                .len(),
+            1
+        );
     }
 }
@@ -1,247 +1,28 @@
-use crate::{Roll, RollSingle};
 use rand::Rng;
-use std::str::Chars;
 
-fn roll_dice(number: u32, faces: u32, is_negative: bool) -> Vec<i64> {
-    let mut results: Vec<i64> = Vec::with_capacity(number as usize);
-    for _ in 0..number {
-        let positive = rand::thread_rng().gen_range(1..faces + 1) as i64;
-        if is_negative {
-            results.push(0 - positive);
-        } else {
-            results.push(positive);
-        }
-    }
-
-    results
-}
-
-fn get_precedence(symbol: char) -> Option<i32> {
-    match symbol {
-        '+' => Some(1),
-        '-' => Some(1),
-        '*' => Some(2),
-        '/' => Some(2),
-        '^' => Some(3),
-        'd' => Some(4),
-        _ => None,
-    }
-}
-
-fn evaluate(value1: f64, value2: f64, op: char) -> Option<f64> {
-    match op {
-        '+' => Some(value1 + value2),
-        '-' => Some(value1 - value2),
-        '*' => Some(value1 * value2),
-        '/' => {
-            if value2 == 0. {
-                None
-            } else {
-                Some(value1 / value2)
-            }
-        }
-        '^' => {
-            if value2 > 0. && value2 < u32::MAX.into() {
-                if let Some(_) = i64::checked_pow(value1.ceil() as i64, value2.ceil() as u32) {
-                    Some(f64::powf(value1, value2))
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        }
-        'd' => {
-            if value2.abs().round() == 1. {
-                Some(value1 * value2)
-            } else if value1.abs().round() > u32::MAX.into()
-                || value2.round() > u32::MAX.into()
-                || value1.abs().round() == 0.
-                || value2.round() <= 0.
-            {
-                None
-            } else {
-                Some(
-                    roll_dice(
-                        value1.abs().round() as u32,
-                        value2.round() as u32,
-                        value1 < 0.,
-                    )
-                    .into_iter()
-                    .map(|val| val)
-                    .sum::<i64>() as f64,
-                )
-            }
-        }
-        _ => None,
-    }
-}
-
-fn recursive_descent(start_value: f64, operator: char, expr: &mut Chars<'_>) -> Option<f64> {
-    let mut op = operator;
-    let mut current_precedence = get_precedence(op)?;
-    let mut first_value = start_value;
-    let mut parsing_value = String::from("");
-    let mut need_op_after_parenthesis = false;
-    let mut inside_square_brackets = false;
-    while let Some(symbol) = expr.next() {
-        if symbol == '[' {
-            inside_square_brackets = true;
-            continue;
-        }
-
-        if symbol == ']' {
-            inside_square_brackets = false;
-            continue;
-        }
-
-        if inside_square_brackets {
-            continue;
-        }
-
-        if symbol == '-' && parsing_value.len() == 0 {
-            parsing_value.push(symbol);
-            continue;
-        }
-
-        if symbol == 'd' && parsing_value.len() == 0 {
-            parsing_value.push('1');
-        }
-
-        if symbol == '(' {
-            let mut nested_value = recursive_descent(0., '+', expr)?;
-            if parsing_value.len() > 0 {
-                if (parsing_value) == "-" {
-                    parsing_value.push('1');
-                }
-                nested_value = evaluate(parsing_value.parse::<f64>().ok()?, nested_value, '*')?;
-            }
-
-            parsing_value = format!("{}", nested_value);
-            need_op_after_parenthesis = true;
-            continue;
-        }
-
-        if symbol == ')' {
-            return evaluate(first_value, parsing_value.parse::<f64>().ok()?, op);
-        }
-
-        if let Some(next_precedence) = get_precedence(symbol) {
-            need_op_after_parenthesis = false;
-            if parsing_value.len() == 0 {
-                op = symbol;
-                continue;
-            }
-
-            if next_precedence > current_precedence {
-                let parsed_value =
-                    recursive_descent(parsing_value.parse::<f64>().ok()?, symbol, expr)?;
-                return evaluate(first_value, parsed_value, op);
-            }
-
-            first_value = evaluate(first_value, parsing_value.parse::<f64>().ok()?, op)?;
-            parsing_value.drain(..);
-            op = symbol;
-            current_precedence = get_precedence(op)?;
-            continue;
-        }
-
-        if symbol.to_digit(10).is_none() && symbol != '.' {
-            continue;
-        }
-
-        if need_op_after_parenthesis {
-            need_op_after_parenthesis = false;
-            let mult_prec = get_precedence('*').unwrap();
-
-            if mult_prec > current_precedence {
-                let mut appended_string = String::new();
-                appended_string.push(symbol);
-                appended_string.push_str(expr.collect::<String>().as_str());
-                let mut appended_expr = appended_string.chars();
-                let parsed_value =
-                    recursive_descent(parsing_value.parse::<f64>().ok()?, '*', &mut appended_expr)?;
-                return evaluate(first_value, parsed_value, op);
-            }
-
-            first_value = evaluate(first_value, parsing_value.parse::<f64>().ok()?, op)?;
-            parsing_value.drain(..);
-            op = '*';
-            current_precedence = get_precedence(op)?;
-        }
-
-        parsing_value.push(symbol);
-    }
-
-    if parsing_value.len() > 0 {
-        evaluate(first_value, parsing_value.parse::<f64>().ok()?, op)
-    } else {
-        Some(first_value)
-    }
-}
-
-pub fn get_roll_from_expression_and_outcomes(
-    expr: &str,
-    outcomes: Vec<i64>,
-    expr_outcome: f64,
-) -> Option<Roll> {
-    let mut single_rolls: Vec<RollSingle> = vec![];
-    let mut results = outcomes.into_iter();
-
-    let mut num_dice_string = String::from("");
-    let mut parsing_value = String::from("");
-    for symbol in expr.chars() {
-        if symbol == 'd' {
-            num_dice_string.drain(..);
-            let val_to_push = if parsing_value.len() > 0 {
-                parsing_value.as_str()
-            } else {
-                "1"
-            };
-            num_dice_string.push_str(val_to_push);
-            parsing_value.drain(..);
-            continue;
-        }
-
-        if symbol.to_digit(10).is_none() {
-            if num_dice_string.len() > 0 {
-                let num_dice = num_dice_string.parse::<i64>().ok()?;
-                if let Some(faces) = parsing_value.parse::<i64>().ok() {
-                    for _ in 0..num_dice {
-                        let outcome = results.next()?;
-                        single_rolls.push(RollSingle { faces, outcome });
-                    }
-                }
-
-                num_dice_string.drain(..);
-            }
-
-            parsing_value.drain(..);
-        } else {
-            parsing_value.push(symbol);
-        }
-    }
-
-    if num_dice_string.len() > 0 {
-        let num_dice = num_dice_string.parse::<i64>().ok()?;
-        let faces = parsing_value.parse::<i64>().ok()?;
-
-        for _ in 0..num_dice {
-            let outcome = results.next()?;
-            single_rolls.push(RollSingle { faces, outcome });
-        }
-
-        num_dice_string.drain(..);
-    }
-
-    let roll = Roll {
-        formula: expr.to_string(),
-        outcome: expr_outcome,
-        single_rolls,
-    };
-
-    Some(roll)
-}
+pub use ast::{BinaryOp, Comparison, Dice, Explosion, Expr, Faces, Modifier};
+pub use distribution::Distribution;
+pub use error::DiceError;
+pub use eval::{DiceResult, ResultKind};
+pub use inline::replace_inline_rolls;
+pub use lexer::{tokenize, Token, TokenKind};
+pub use limits::DiceLimits;
+pub use macros::{expand_macros, is_macro_name, parse_macro_definition};
+pub use parser::{parse_expression, parse_expression_with};
+pub use repeat::split_repeat;
+pub use replay::{displayed_single_rolls, get_roll_from_expression_and_outcomes};
+
+mod ast;
+mod distribution;
+mod error;
+mod eval;
+mod inline;
+mod lexer;
+mod limits;
+mod macros;
+mod parser;
+mod repeat;
+mod replay;
 
 pub fn num_with_thousands_commas(num: u64) -> String {
     num.to_string()
@@ -254,145 +35,92 @@ pub fn num_with_thousands_commas(num: u64) -> String {
         .join(",")
 }
 
-pub fn dicemath(expr: &str) -> Option<f64> {
-    let expr = expr.replace("cs>20", "").replace("cs>19", "");
-    let result = recursive_descent(0., '+', &mut expr.chars())?;
-
-    Some(result)
+pub fn dicemath(expr: &str) -> Result<f64, DiceError> {
+    parse_expression(expr)?.evaluate()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn eval_add() {
-        assert_eq!(evaluate(1., 1., '+').unwrap(), 2.);
-        assert_eq!(evaluate(1., -1., '+').unwrap(), 0.);
-        assert_eq!(evaluate(1., 2.5, '+').unwrap(), 3.5);
-    }
-
-    #[test]
-    fn eval_sub() {
-        assert_eq!(evaluate(1., 1., '-').unwrap(), 0.);
-        assert_eq!(evaluate(1., -1., '-').unwrap(), 2.);
-        assert_eq!(evaluate(1., 2.5, '-').unwrap(), -1.5);
-    }
+/// Like `dicemath`, but also says whether the result is a sum or a success count, and how many
+/// criticals were rolled.
+pub fn dicemath_result(expr: &str) -> Result<DiceResult, DiceError> {
+    parse_expression(expr)?.roll()
+}
 
-    #[test]
-    fn eval_mul() {
-        assert_eq!(evaluate(1., 1., '*').unwrap(), 1.);
-        assert_eq!(evaluate(1., -1., '*').unwrap(), -1.);
-        assert_eq!(evaluate(1., 2.5, '*').unwrap(), 2.5);
-    }
+/// Like `dicemath_result`, but rolls with `rng` and rejects anything bigger than `limits` allows.
+/// Passing a seeded `rng` gives the same result every time.
+pub fn dicemath_with<R: Rng + ?Sized>(
+    expr: &str,
+    rng: &mut R,
+    limits: &DiceLimits,
+) -> Result<DiceResult, DiceError> {
+    parse_expression_with(expr, limits)?.roll_with(rng, limits)
+}
 
-    #[test]
-    fn eval_div() {
-        assert_eq!(evaluate(1., 1., '/').unwrap(), 1.);
-        assert_eq!(evaluate(1., -1., '/').unwrap(), -1.);
-        assert_eq!(evaluate(10., 2., '/').unwrap(), 5.);
-    }
+/// Like `dicemath_with`, but rolls a repeated expression like `6x 4d6dl1` or `3#1d20` as many
+/// times as it asks for, returning every result in order.
+pub fn dicemath_repeated<R: Rng + ?Sized>(
+    expr: &str,
+    rng: &mut R,
+    limits: &DiceLimits,
+) -> Result<Vec<DiceResult>, DiceError> {
+    let (repeats, expr) = split_repeat(expr, limits)?;
+    let parsed = parse_expression_with(&expr, limits)?;
+
+    (0..repeats)
+        .map(|_| parsed.roll_with(rng, limits))
+        .collect()
+}
 
-    #[test]
-    fn eval_exp() {
-        assert_eq!(evaluate(1., 1., '^').unwrap(), 1.);
-        assert_eq!(evaluate(1., -1., '^').unwrap_or(0.), 0.);
-        assert_eq!(evaluate(10., 2., '^').unwrap(), 100.);
+/// The exact distribution of the expression's result where possible, otherwise an estimate from
+/// rolling it `num_samples` times. Anything bigger than `limits` allows is rejected.
+pub fn dicemath_distribution(
+    expr: &str,
+    num_samples: u32,
+    limits: &DiceLimits,
+) -> Result<Distribution, DiceError> {
+    let parsed = parse_expression_with(expr, limits)?;
+    match parsed.distribution_with(limits) {
+        Some(distribution) => Ok(distribution),
+        None => Distribution::sample(&parsed, num_samples, limits),
     }
+}
 
-    #[test]
-    fn eval_roll() {
-        assert_eq!(evaluate(1., 1., 'd').unwrap(), 1.);
-        assert_eq!(evaluate(2., 1., 'd').unwrap(), 2.);
-        assert!(evaluate(2., 0., 'd').is_none());
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
-    fn parse_flat() {
-        assert_eq!(
-            recursive_descent(0., '+', &mut "1 ++ 1".chars()).unwrap(),
-            2.
-        );
-        assert_eq!(
-            recursive_descent(0., '+', &mut "1 + 1".chars()).unwrap(),
-            2.
-        );
-        assert_eq!(
-            recursive_descent(0., '+', &mut "1 * 2 + 2".chars()).unwrap(),
-            4.
-        );
-        assert_eq!(
-            recursive_descent(0., '+', &mut "1 + 2.5 * 2".chars()).unwrap(),
-            6.
-        );
-        assert_eq!(
-            recursive_descent(0., '+', &mut "1 + 2 * 2 - 3 ^ 2".chars()).unwrap(),
-            -4.
-        );
-        assert_eq!(
-            recursive_descent(
-                0.,
-                '+',
-                &mut " 1 + as2 * 2 vaagmt- 3 maDSGbW$$$^ 2DV vv Wwq    ".chars()
-            )
-            .unwrap(),
-            -4.
-        );
+    fn dicemath_flat() {
+        assert_eq!(dicemath("1 ++ 1").unwrap(), 2.);
+        assert_eq!(dicemath("1 + 1").unwrap(), 2.);
+        assert_eq!(dicemath("1 * 2 + 2").unwrap(), 4.);
+        assert_eq!(dicemath("1 + 2.5 * 2").unwrap(), 6.);
+        assert_eq!(dicemath("1 + 2 * 2 - 3 ^ 2").unwrap(), -4.);
+        assert!(dicemath("1d20cs>20cf1 - 1d20").unwrap().abs() < 20.);
+        assert!((0. ..=10.).contains(&dicemath("10d10>7").unwrap()));
+        assert!(dicemath(" 1 + as2 * 2 vaagmt- 3 maDSGbW$$$^ 2DV vv Wwq    ").is_err());
     }
 
     #[test]
-    fn parse_nested() {
-        assert_eq!(
-            recursive_descent(0., '+', &mut "(1 + 2) * 2 - 3 ^ 2".chars()).unwrap(),
-            -3.
-        );
-        assert_eq!(
-            recursive_descent(0., '+', &mut "1 + (2 * 2 - 3) ^ 2".chars()).unwrap(),
-            2.
-        );
+    fn dicemath_nested() {
+        assert_eq!(dicemath("(1 + 2) * 2 - 3 ^ 2").unwrap(), -3.);
+        assert_eq!(dicemath("1 + (2 * 2 - 3) ^ 2").unwrap(), 2.);
+        assert_eq!(dicemath("1 + (2 * 2 - 3) ^ 2 * 3 + 1 / 2").unwrap(), 4.5);
         assert_eq!(
-            recursive_descent(0., '+', &mut "1 + (2 * 2 - 3) ^ 2 * 3 + 1 / 2".chars()).unwrap(),
-            4.5
-        );
-        assert_eq!(
-            recursive_descent(0., '+', &mut "1 + (2 * 2 - 1) ^ (2 * (3 + 1) / 2)".chars()).unwrap(),
+            dicemath("1 + (2 * 2 - 1) ^ (2 * (3 + 1) / 2)").unwrap(),
             82.
         );
-        assert!([5., 7., 9.]
-            .contains(&recursive_descent(0., '+', &mut "1 + 2d(2.3 / 1) * (2)".chars()).unwrap()));
+        assert!([5., 7., 9.].contains(&dicemath("1 + 2d(2.3 / 1) * (2)").unwrap()));
+        assert_eq!(dicemath("2(3)").unwrap(), 6.);
+        assert_eq!(dicemath("(2)3").unwrap(), 6.);
     }
 
     #[test]
-    fn get_rolls_from_expr_and_results() {
-        let roll = get_roll_from_expression_and_outcomes("d20", vec![10], 10.).unwrap();
-        assert_eq!(roll.single_rolls.len(), 1);
-        assert_eq!(roll.single_rolls[0].faces, 20);
-        assert_eq!(roll.single_rolls[0].outcome, 10);
-        assert_eq!(roll.formula, "d20");
-        assert_eq!(roll.outcome, 10.);
-
-        let roll =
-            get_roll_from_expression_and_outcomes("2d20+(15+0) + 3d6", vec![10, 15, 3, 4, 5], 52.)
-                .unwrap();
-
-        assert_eq!(roll.single_rolls.len(), 5);
-        assert_eq!(roll.single_rolls[0].faces, 20);
-        assert_eq!(roll.single_rolls[0].outcome, 10);
-        assert_eq!(roll.single_rolls[1].outcome, 15);
-        assert_eq!(roll.single_rolls[2].outcome, 3);
-        assert_eq!(roll.single_rolls[3].outcome, 4);
-        assert_eq!(roll.single_rolls[4].outcome, 5);
-        assert_eq!(roll.formula, "2d20+(15+0) + 3d6");
-        assert_eq!(roll.outcome, 52.);
-
-        let roll = get_roll_from_expression_and_outcomes("4d6k3", vec![3, 1, 1, 3], 7.).unwrap();
-        assert_eq!(roll.single_rolls.len(), 4);
-        assert_eq!(roll.single_rolls[0].faces, 6);
-        assert_eq!(roll.single_rolls[0].outcome, 3);
-        assert_eq!(roll.single_rolls[1].outcome, 1);
-        assert_eq!(roll.single_rolls[2].outcome, 1);
-        assert_eq!(roll.single_rolls[3].outcome, 3);
-        assert_eq!(roll.formula, "4d6k3");
-        assert_eq!(roll.outcome, 7.);
+    fn dicemath_distribution_limits() {
+        let limits = DiceLimits {
+            max_dice: 10,
+            ..DiceLimits::default()
+        };
+        assert!(dicemath_distribution("10d6", 10, &limits).unwrap().is_exact);
+        assert!(dicemath_distribution("11d6", 10, &limits).is_err());
     }
 }
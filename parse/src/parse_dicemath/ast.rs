@@ -0,0 +1,224 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> char {
+        match self {
+            BinaryOp::Add => '+',
+            BinaryOp::Subtract => '-',
+            BinaryOp::Multiply => '*',
+            BinaryOp::Divide => '/',
+            BinaryOp::Power => '^',
+        }
+    }
+}
+
+/// Roll20 treats `>` and `<` as inclusive, and most of our logs come from Roll20, so `>19` and
+/// `>=19` both parse to `AtLeast(19)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal(i64),
+    AtLeast(i64),
+    AtMost(i64),
+}
+
+impl Comparison {
+    pub fn matches(&self, outcome: i64) -> bool {
+        match *self {
+            Comparison::Equal(target) => outcome == target,
+            Comparison::AtLeast(target) => outcome >= target,
+            Comparison::AtMost(target) => outcome <= target,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparison::Equal(target) => write!(f, "={target}"),
+            Comparison::AtLeast(target) => write!(f, ">{target}"),
+            Comparison::AtMost(target) => write!(f, "<{target}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Explosion {
+    /// `!` - every die that hits the threshold rolls another die alongside it
+    Explode,
+    /// `!!` - extra rolls are added onto the die that exploded
+    Compound,
+    /// `!p` - like `!`, but every extra die has 1 subtracted from it
+    Penetrate,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Modifier {
+    /// With no threshold, dice explode on their highest face.
+    Explode(Explosion, Option<Comparison>),
+    /// `r` - keeps rerolling a die for as long as it hits the threshold
+    Reroll(Comparison),
+    /// `ro` - rerolls a die at most once, keeping the second result
+    RerollOnce(Comparison),
+    KeepHighest(u32),
+    KeepLowest(u32),
+    DropHighest(u32),
+    DropLowest(u32),
+    CriticalSuccess(Comparison),
+    CriticalFailure(Comparison),
+    /// `>N`, `<N` or `=N` straight after the dice - counts the dice that hit the target number
+    /// instead of adding them up
+    Success(Comparison),
+    /// `fN` - in a success pool, every die that hits this takes a success away
+    Failure(Comparison),
+}
+
+impl fmt::Display for Modifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Modifier::Explode(explosion, threshold) => {
+                match explosion {
+                    Explosion::Explode => write!(f, "!")?,
+                    Explosion::Compound => write!(f, "!!")?,
+                    Explosion::Penetrate => write!(f, "!p")?,
+                }
+                match threshold {
+                    Some(comparison) => write!(f, "{comparison}"),
+                    None => Ok(()),
+                }
+            }
+            Modifier::Reroll(comparison) => write!(f, "r{comparison}"),
+            Modifier::RerollOnce(comparison) => write!(f, "ro{comparison}"),
+            Modifier::KeepHighest(count) => write!(f, "kh{count}"),
+            Modifier::KeepLowest(count) => write!(f, "kl{count}"),
+            Modifier::DropHighest(count) => write!(f, "dh{count}"),
+            Modifier::DropLowest(count) => write!(f, "dl{count}"),
+            Modifier::CriticalSuccess(comparison) => write!(f, "cs{comparison}"),
+            Modifier::CriticalFailure(comparison) => write!(f, "cf{comparison}"),
+            Modifier::Success(comparison) => write!(f, "{comparison}"),
+            Modifier::Failure(comparison) => write!(f, "f{comparison}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Faces {
+    Numbered(Box<Expr>),
+    /// `dF` - Fate/Fudge dice, which land on -1, 0 or +1
+    Fate,
+}
+
+impl fmt::Display for Faces {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Faces::Numbered(faces) => write!(f, "{faces}"),
+            Faces::Fate => write!(f, "F"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dice {
+    pub count: Option<Box<Expr>>,
+    pub faces: Faces,
+    pub modifiers: Vec<Modifier>,
+    /// Character offset of the start of the term in the original expression.
+    pub position: usize,
+}
+
+impl Dice {
+    pub fn explosion(&self) -> Option<(Explosion, Option<Comparison>)> {
+        self.modifiers.iter().find_map(|modifier| match modifier {
+            Modifier::Explode(explosion, threshold) => Some((*explosion, *threshold)),
+            _ => None,
+        })
+    }
+
+    /// The target number for a success pool like `10d10>7`, if this is one.
+    pub fn success_target(&self) -> Option<Comparison> {
+        self.modifiers.iter().find_map(|modifier| match modifier {
+            Modifier::Success(target) => Some(*target),
+            _ => None,
+        })
+    }
+
+    /// Whether a die showing `outcome` gets rerolled, given how many times it already has been.
+    pub fn should_reroll(&self, outcome: i64, num_rerolls: u32) -> bool {
+        self.modifiers.iter().any(|modifier| match modifier {
+            Modifier::Reroll(threshold) => threshold.matches(outcome),
+            Modifier::RerollOnce(threshold) => num_rerolls == 0 && threshold.matches(outcome),
+            _ => false,
+        })
+    }
+}
+
+impl fmt::Display for Dice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(count) = &self.count {
+            write!(f, "{count}")?;
+        }
+        write!(f, "d{}", self.faces)?;
+        for modifier in &self.modifiers {
+            write!(f, "{modifier}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Dice(Dice),
+    Negate(Box<Expr>),
+    /// The last field is the character offset of the operator in the original expression.
+    Binary(BinaryOp, Box<Expr>, Box<Expr>, usize),
+    Group(Box<Expr>),
+}
+
+impl Expr {
+    /// Every dice term in the expression, in the order they appear in the formula.
+    pub fn dice(&self) -> Vec<&Dice> {
+        match self {
+            Expr::Number(_) => vec![],
+            Expr::Dice(dice) => {
+                let mut all_dice = match &dice.count {
+                    Some(count) => count.dice(),
+                    None => vec![],
+                };
+                if let Faces::Numbered(faces) = &dice.faces {
+                    all_dice.extend(faces.dice());
+                }
+                all_dice.push(dice);
+                all_dice
+            }
+            Expr::Negate(expr) | Expr::Group(expr) => expr.dice(),
+            Expr::Binary(_, lhs, rhs, _) => {
+                let mut all_dice = lhs.dice();
+                all_dice.extend(rhs.dice());
+                all_dice
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{value}"),
+            Expr::Dice(dice) => write!(f, "{dice}"),
+            Expr::Negate(expr) => write!(f, "-{expr}"),
+            Expr::Binary(BinaryOp::Power, lhs, rhs, _) => write!(f, "{lhs}^{rhs}"),
+            Expr::Binary(op, lhs, rhs, _) => write!(f, "{lhs} {} {rhs}", op.symbol()),
+            Expr::Group(expr) => write!(f, "({expr})"),
+        }
+    }
+}
@@ -0,0 +1,605 @@
+use super::{
+    ast::{BinaryOp, Comparison, Dice, Explosion, Expr, Faces, Modifier},
+    error::DiceError,
+    eval::{apply, Die, Resolved, MAX_EXPLOSIONS, MAX_REROLLS},
+    limits::DiceLimits,
+};
+
+/// Distributions with more distinct results than this are sampled instead of worked out exactly.
+const MAX_OUTCOMES: usize = 100_000;
+/// Upper bound on how many dice a single term can add together exactly.
+const MAX_DICE: u32 = 1_000;
+/// Keep/drop depends on every die at once, so every combination of dice has to be checked.
+const MAX_COMBINATIONS: usize = 1_000_000;
+/// Rough upper bound on the multiplications spent adding up dice, e.g. `1000d20` takes 2 * 10^8.
+const MAX_CONVOLUTION_WORK: usize = 300_000_000;
+/// Explosion chains are cut off once they become less likely than this, which is too little to
+/// change a probability that's printed. Cutting off more than this makes a distribution inexact.
+const MIN_PROBABILITY: f64 = 1e-15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    /// Every possible result paired with its probability, sorted by result.
+    pub outcomes: Vec<(f64, f64)>,
+    /// `false` when the distribution was estimated by rolling the expression many times, or when
+    /// dice that were still likely to explode had to stop.
+    pub is_exact: bool,
+}
+
+/// Sorts outcomes by value and merges any duplicates.
+fn merge(mut outcomes: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    outcomes.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut merged: Vec<(f64, f64)> = Vec::with_capacity(outcomes.len());
+    for (value, probability) in outcomes {
+        match merged.last_mut() {
+            Some(last) if last.0 == value => last.1 += probability,
+            _ => merged.push((value, probability)),
+        }
+    }
+
+    merged
+}
+
+impl Distribution {
+    fn constant(value: f64) -> Self {
+        Self {
+            outcomes: vec![(value, 1.)],
+            is_exact: true,
+        }
+    }
+
+    /// Marks a distribution as inexact if anything it was worked out from was.
+    fn exact_if(mut self, is_exact: bool) -> Self {
+        self.is_exact &= is_exact;
+        self
+    }
+
+    fn exact(outcomes: Vec<(f64, f64)>) -> Option<Self> {
+        let outcomes = merge(outcomes);
+        if outcomes.len() > MAX_OUTCOMES {
+            return None;
+        }
+
+        Some(Self {
+            outcomes,
+            is_exact: true,
+        })
+    }
+
+    /// Estimates the distribution by rolling the expression `num_samples` times within `limits`.
+    /// Rolls that fail are left out, unless the very first one does.
+    pub fn sample(expr: &Expr, num_samples: u32, limits: &DiceLimits) -> Result<Self, DiceError> {
+        let mut rng = rand::thread_rng();
+        let mut roll = || Ok(expr.roll_with(&mut rng, limits)?.value);
+        let mut results: Vec<f64> = vec![roll()?];
+        results.extend((1..num_samples).filter_map(|_| roll().ok()));
+
+        let probability = 1. / results.len() as f64;
+        Ok(Self {
+            outcomes: merge(
+                results
+                    .into_iter()
+                    .map(|result| (result, probability))
+                    .collect(),
+            ),
+            is_exact: false,
+        })
+    }
+
+    pub fn probability_at_least(&self, value: f64) -> f64 {
+        self.outcomes
+            .iter()
+            .filter(|(outcome, _)| *outcome >= value)
+            .map(|(_, probability)| probability)
+            .sum()
+    }
+
+    pub fn probability_at_most(&self, value: f64) -> f64 {
+        self.outcomes
+            .iter()
+            .filter(|(outcome, _)| *outcome <= value)
+            .map(|(_, probability)| probability)
+            .sum()
+    }
+
+    fn map(&self, f: impl Fn(f64) -> f64) -> Option<Self> {
+        Some(
+            Self::exact(
+                self.outcomes
+                    .iter()
+                    .map(|&(value, probability)| (f(value), probability))
+                    .collect(),
+            )?
+            .exact_if(self.is_exact),
+        )
+    }
+
+    /// The distribution of `op` applied to independent results from `self` and `other`.
+    fn combine(&self, op: BinaryOp, other: &Self) -> Option<Self> {
+        if self.outcomes.len().saturating_mul(other.outcomes.len()) > MAX_COMBINATIONS {
+            return None;
+        }
+
+        let mut outcomes = Vec::with_capacity(self.outcomes.len() * other.outcomes.len());
+        for &(value1, probability1) in &self.outcomes {
+            for &(value2, probability2) in &other.outcomes {
+                outcomes.push((apply(op, value1, value2)?, probability1 * probability2));
+            }
+        }
+
+        Some(Self::exact(outcomes)?.exact_if(self.is_exact && other.is_exact))
+    }
+
+    /// The distribution of `number` independent results from `self` added together.
+    fn repeat(&self, number: u32) -> Option<Self> {
+        if self.outcomes.iter().any(|(value, _)| value.fract() != 0.) {
+            let mut total = Self::constant(0.);
+            for _ in 0..number {
+                total = total.combine(BinaryOp::Add, self)?;
+            }
+            return Some(total);
+        }
+
+        // Dice only land on whole numbers, so the totals can be tracked by offset from the lowest
+        // possible total instead of merging outcomes every time another die is added.
+        let lowest = self.outcomes.first()?.0 as i64;
+        let highest = self.outcomes.last()?.0 as i64;
+        let mut single = vec![0.; (highest - lowest + 1) as usize];
+        for &(value, probability) in &self.outcomes {
+            single[(value as i64 - lowest) as usize] = probability;
+        }
+
+        let work = (number as usize).pow(2) * single.len().pow(2) / 2;
+        if work > MAX_CONVOLUTION_WORK {
+            return None;
+        }
+
+        let mut total = vec![1.];
+        for _ in 0..number {
+            let mut next = vec![0.; total.len() + single.len() - 1];
+            if next.len() > MAX_OUTCOMES {
+                return None;
+            }
+            for (i, &probability1) in total.iter().enumerate() {
+                for (j, &probability2) in single.iter().enumerate() {
+                    next[i + j] += probability1 * probability2;
+                }
+            }
+            total = next;
+        }
+
+        Some(
+            Self::exact(
+                total
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, probability)| *probability > 0.)
+                    .map(|(i, probability)| {
+                        ((lowest * number as i64 + i as i64) as f64, probability)
+                    })
+                    .collect(),
+            )?
+            .exact_if(self.is_exact),
+        )
+    }
+
+    /// Picks one of `parts` with the paired probability, then a result from that distribution.
+    fn mix(parts: Vec<(f64, Self)>) -> Option<Self> {
+        let is_exact = parts.iter().all(|(_, part)| part.is_exact);
+        Some(
+            Self::exact(
+                parts
+                    .into_iter()
+                    .flat_map(|(weight, part)| {
+                        part.outcomes
+                            .into_iter()
+                            .map(move |(value, probability)| (value, weight * probability))
+                    })
+                    .collect(),
+            )?
+            .exact_if(is_exact),
+        )
+    }
+}
+
+/// The chance of each face of a fresh die, before any rerolls.
+fn uniform(die: Die) -> Vec<(i64, f64)> {
+    let probability = 1. / (die.highest - die.lowest + 1) as f64;
+    (die.lowest..=die.highest)
+        .map(|face| (face, probability))
+        .collect()
+}
+
+/// The chances of consecutive whole-number totals, starting from `lowest`.
+#[derive(Clone)]
+struct Totals {
+    lowest: i64,
+    probabilities: Vec<f64>,
+}
+
+impl Totals {
+    fn highest(&self) -> i64 {
+        self.lowest + self.probabilities.len() as i64 - 1
+    }
+
+    fn total_probability(&self) -> f64 {
+        self.probabilities.iter().sum()
+    }
+
+    /// Adds the chances of `other`'s totals to these, widening the range to fit them.
+    fn absorb(&mut self, other: Totals) {
+        let lowest = self.lowest.min(other.lowest);
+        let highest = self.highest().max(other.highest());
+        let mut probabilities = vec![0.; (highest - lowest + 1) as usize];
+        for totals in [&*self, &other] {
+            let offset = (totals.lowest - lowest) as usize;
+            for (i, probability) in totals.probabilities.iter().enumerate() {
+                probabilities[offset + i] += probability;
+            }
+        }
+
+        *self = Totals {
+            lowest,
+            probabilities,
+        };
+    }
+}
+
+/// Rolls one more die onto each total in an explosion chain. `faces` is the die being rolled and
+/// `value` turns each face into what it's worth. Returns the totals whose die explodes again and
+/// the totals whose chain stops there.
+fn explosion_step(
+    chains: &Totals,
+    faces: &[(i64, f64)],
+    value: impl Fn(i64) -> i64,
+    threshold: Comparison,
+) -> (Totals, Totals) {
+    let values: Vec<i64> = faces.iter().map(|&(face, _)| value(face)).collect();
+    let lowest_value = values.iter().copied().min().unwrap_or(0);
+    let highest_value = values.iter().copied().max().unwrap_or(0);
+
+    let empty = Totals {
+        lowest: chains.lowest + lowest_value,
+        probabilities: vec![
+            0.;
+            chains.probabilities.len() + (highest_value - lowest_value) as usize
+        ],
+    };
+    let (mut exploding, mut stopped) = (empty.clone(), empty);
+    for (&(face, face_probability), &value) in faces.iter().zip(&values) {
+        let totals = match threshold.matches(face) {
+            true => &mut exploding,
+            false => &mut stopped,
+        };
+        let offset = (value - lowest_value) as usize;
+        for (i, &probability) in chains.probabilities.iter().enumerate() {
+            totals.probabilities[offset + i] += probability * face_probability;
+        }
+    }
+
+    (exploding, stopped)
+}
+
+impl Dice {
+    /// The chance of each face a die ends up on once it's done rerolling.
+    fn rerolled_faces(&self, die: Die) -> Vec<(i64, f64)> {
+        let mut faces = uniform(die);
+        let fresh = faces.clone();
+
+        for num_rerolls in 0..MAX_REROLLS {
+            let mut rerolled = 0.;
+            for (face, probability) in faces.iter_mut() {
+                if self.should_reroll(*face, num_rerolls) {
+                    rerolled += *probability;
+                    *probability = 0.;
+                }
+            }
+            if rerolled == 0. {
+                break;
+            }
+
+            for ((_, probability), (_, fresh_probability)) in faces.iter_mut().zip(&fresh) {
+                *probability += rerolled * fresh_probability;
+            }
+        }
+
+        faces
+    }
+
+    /// What a single die showing `outcome` adds to the total, when nothing is kept or dropped.
+    fn face_value(&self, outcome: i64) -> f64 {
+        match self.success_target() {
+            Some(target) => self.success_value(target, outcome) as f64,
+            None => outcome as f64,
+        }
+    }
+
+    /// The distribution of everything a single die adds to the total, explosions included.
+    fn die_distribution(&self, die: Die) -> Option<Distribution> {
+        let first = self.rerolled_faces(die);
+        let Some((explosion, threshold)) = self.explosion() else {
+            return Distribution::exact(
+                first
+                    .into_iter()
+                    .map(|(face, probability)| (self.face_value(face), probability))
+                    .collect(),
+            );
+        };
+
+        let threshold = threshold.unwrap_or(Comparison::AtLeast(die.highest));
+        let fresh = uniform(die);
+        // compounded dice only count once they've been added together, and penetrated dice lose 1
+        // from every extra roll
+        let value = |face: i64, is_extra: bool| match explosion {
+            Explosion::Compound => face,
+            Explosion::Penetrate if is_extra => self.face_value(face - 1) as i64,
+            Explosion::Explode | Explosion::Penetrate => self.face_value(face) as i64,
+        };
+
+        // Each extra die widens the range of totals by the range of what it adds, so the work
+        // is known before any of it is done. Chains stop being followed once they're less likely
+        // than `MIN_PROBABILITY`, or after `MAX_EXPLOSIONS` like when they're rolled.
+        let explosion_chance: f64 = fresh
+            .iter()
+            .filter(|(face, _)| threshold.matches(*face))
+            .map(|(_, probability)| probability)
+            .sum();
+        let num_steps = if explosion_chance < 1. {
+            (MIN_PROBABILITY.ln() / explosion_chance.ln())
+                .ceil()
+                .min(MAX_EXPLOSIONS as f64) as usize
+        } else {
+            MAX_EXPLOSIONS as usize
+        };
+        let extra_values: Vec<i64> = fresh.iter().map(|&(face, _)| value(face, true)).collect();
+        let value_range = match (extra_values.iter().min(), extra_values.iter().max()) {
+            (Some(lowest), Some(highest)) => (highest - lowest) as usize + 1,
+            _ => 1,
+        };
+        let num_outcomes = value_range.saturating_mul(num_steps) + first.len();
+        let work = fresh
+            .len()
+            .saturating_mul(num_outcomes)
+            .saturating_mul(num_steps)
+            / 2;
+        if num_outcomes > MAX_OUTCOMES || work > MAX_CONVOLUTION_WORK {
+            return None;
+        }
+
+        let start = Totals {
+            lowest: 0,
+            probabilities: vec![1.],
+        };
+        let (mut chains, mut finished) =
+            explosion_step(&start, &first, |face| value(face, false), threshold);
+        for _ in 0..MAX_EXPLOSIONS {
+            if chains.total_probability() < MIN_PROBABILITY {
+                break;
+            }
+            let (exploding, stopped) =
+                explosion_step(&chains, &fresh, |face| value(face, true), threshold);
+            finished.absorb(stopped);
+            chains = exploding;
+        }
+        // Whatever is left stops exploding, the same as when the dice are rolled.
+        let is_cut_short = chains.total_probability() >= MIN_PROBABILITY;
+        finished.absorb(chains);
+
+        let outcomes = finished
+            .probabilities
+            .into_iter()
+            .enumerate()
+            .filter(|(_, probability)| *probability > 0.)
+            .map(|(i, probability)| {
+                let total = finished.lowest + i as i64;
+                match explosion {
+                    Explosion::Compound => (self.face_value(total), probability),
+                    Explosion::Explode | Explosion::Penetrate => (total as f64, probability),
+                }
+            })
+            .collect();
+        Some(Distribution::exact(outcomes)?.exact_if(!is_cut_short))
+    }
+
+    /// Works out keep/drop by checking every combination of dice.
+    fn kept_distribution(&self, number: u32, sign: i64, die: Die) -> Option<Distribution> {
+        let faces = self.rerolled_faces(die);
+        let num_combinations = faces.len().checked_pow(number)?;
+        if num_combinations > MAX_COMBINATIONS {
+            return None;
+        }
+
+        let mut outcomes = Vec::with_capacity(num_combinations);
+        let mut indices = vec![0; number as usize];
+        for _ in 0..num_combinations {
+            let results: Vec<i64> = indices.iter().map(|&i| faces[i].0).collect();
+            let probability: f64 = indices.iter().map(|&i| faces[i].1).product();
+            outcomes.push((self.tally(&results, sign, die).0.value, probability));
+
+            for index in indices.iter_mut() {
+                *index += 1;
+                if *index < faces.len() {
+                    break;
+                }
+                *index = 0;
+            }
+        }
+
+        Distribution::exact(outcomes)
+    }
+
+    fn distribution_for(
+        &self,
+        count: f64,
+        faces: Option<f64>,
+        limits: &DiceLimits,
+    ) -> Option<Distribution> {
+        let (number, sign, die) = match self.resolve(count, faces, limits).ok()? {
+            Resolved::Flat(value) => return Some(Distribution::constant(value)),
+            Resolved::Dice { number, sign, die } => (number, sign, die),
+        };
+        if number > MAX_DICE || (die.highest - die.lowest) as usize >= MAX_OUTCOMES {
+            return None;
+        }
+
+        let is_kept_or_dropped = self.modifiers.iter().any(|modifier| {
+            matches!(
+                modifier,
+                Modifier::KeepHighest(_)
+                    | Modifier::KeepLowest(_)
+                    | Modifier::DropHighest(_)
+                    | Modifier::DropLowest(_)
+            )
+        });
+        if is_kept_or_dropped {
+            // exploded dice can be kept or dropped separately, which we don't try to work out
+            if self.explosion().is_some() {
+                return None;
+            }
+            return self.kept_distribution(number, sign, die);
+        }
+
+        self.die_distribution(die)?
+            .repeat(number)?
+            .map(|total| total * sign as f64)
+    }
+
+    /// The exact distribution of this dice term, or `None` if it can't be worked out exactly.
+    pub fn distribution(&self) -> Option<Distribution> {
+        self.distribution_with(&DiceLimits::default())
+    }
+
+    /// Like `distribution`, but for dice that are rolled within `limits`.
+    pub fn distribution_with(&self, limits: &DiceLimits) -> Option<Distribution> {
+        let counts = match &self.count {
+            Some(count) => count.distribution_with(limits)?,
+            None => Distribution::constant(1.),
+        };
+        let faces = match &self.faces {
+            Faces::Fate => None,
+            Faces::Numbered(faces) => Some(faces.constant_value()?),
+        };
+
+        let parts = counts
+            .outcomes
+            .into_iter()
+            .map(|(count, probability)| {
+                Some((probability, self.distribution_for(count, faces, limits)?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Distribution::mix(parts)
+    }
+}
+
+impl Expr {
+    /// The exact distribution of the expression's result, or `None` if it has too many possible
+    /// results or uses something that can't be worked out exactly.
+    pub fn distribution(&self) -> Option<Distribution> {
+        self.distribution_with(&DiceLimits::default())
+    }
+
+    /// Like `distribution`, but for dice that are rolled within `limits`.
+    pub fn distribution_with(&self, limits: &DiceLimits) -> Option<Distribution> {
+        match self {
+            Expr::Number(value) => Some(Distribution::constant(*value)),
+            Expr::Dice(dice) => dice.distribution_with(limits),
+            Expr::Negate(expr) => expr.distribution_with(limits)?.map(|value| -value),
+            Expr::Binary(op, lhs, rhs, _) => lhs
+                .distribution_with(limits)?
+                .combine(*op, &rhs.distribution_with(limits)?),
+            Expr::Group(expr) => expr.distribution_with(limits),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expression;
+
+    fn exact(expr: &str) -> Distribution {
+        let distribution = parse_expression(expr).unwrap().distribution().unwrap();
+        assert!(distribution.is_exact);
+        distribution
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn distribution_sums() {
+        let two_d6 = exact("2d6");
+        assert_eq!(two_d6.outcomes.len(), 11);
+        assert_close(two_d6.probability_at_least(7.), 21. / 36.);
+        assert_close(two_d6.probability_at_most(2.), 1. / 36.);
+
+        assert_close(exact("1d20 + 5").probability_at_least(25.), 0.05);
+        assert_close(exact("-1d4").probability_at_most(-4.), 0.25);
+        assert_close(exact("2 * 3").probability_at_least(6.), 1.);
+        assert_close(exact("1dF").probability_at_most(-1.), 1. / 3.);
+        assert_close(exact("(1d2)d6").probability_at_least(12.), 0.5 / 36.);
+    }
+
+    #[test]
+    fn distribution_modifiers() {
+        assert_close(exact("4d6dl1").probability_at_least(18.), 21. / 1296.);
+        assert_close(exact("2d20kh1").probability_at_most(1.), 1. / 400.);
+        assert_close(exact("1d6ro1").probability_at_most(1.), 1. / 36.);
+        assert_close(exact("1d6r<2").probability_at_most(2.), 0.);
+        assert_close(exact("1d6!").probability_at_least(7.), 1. / 6.);
+        assert_close(exact("1d6!!").probability_at_least(13.), 1. / 36.);
+        assert_close(exact("1d6!p").probability_at_least(6.), 1. / 6.);
+        assert_close(exact("3d10>8").probability_at_least(3.), 0.027);
+        assert_close(exact("1d10>8f1").probability_at_most(-1.), 0.1);
+    }
+
+    #[test]
+    fn distribution_fallback() {
+        assert!(parse_expression("1d(1d6)")
+            .unwrap()
+            .distribution()
+            .is_none());
+        assert!(parse_expression("1d6 / 0")
+            .unwrap()
+            .distribution()
+            .is_none());
+
+        let sampled = Distribution::sample(
+            &parse_expression("1d(1d6)").unwrap(),
+            1000,
+            &DiceLimits::default(),
+        )
+        .unwrap();
+        assert!(!sampled.is_exact);
+        assert_close(sampled.outcomes.iter().map(|(_, p)| p).sum(), 1.);
+        assert_eq!(
+            Distribution::sample(
+                &parse_expression("1d6 / 0").unwrap(),
+                10,
+                &DiceLimits::default()
+            ),
+            Err(DiceError::DivisionByZero { position: 4 })
+        );
+    }
+
+    #[test]
+    fn distribution_explosion_limits() {
+        let distribution = |expr: &str| parse_expression(expr).unwrap().distribution();
+
+        // Most of these chains are still exploding after `MAX_EXPLOSIONS`, so they're cut short.
+        let cut_short = distribution("1d100!>2").unwrap();
+        assert!(!cut_short.is_exact);
+        assert_close(cut_short.outcomes.iter().map(|(_, p)| p).sum(), 1.);
+        assert!(!distribution("1d100!>2 + 1").unwrap().is_exact);
+
+        // Too much work to follow every chain, so these are sampled.
+        assert!(distribution("1d500!>10").is_none());
+        assert!(distribution("1d1000!>2").is_none());
+    }
+}
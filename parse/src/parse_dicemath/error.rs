@@ -0,0 +1,91 @@
+use std::fmt;
+
+/// Why a dice expression couldn't be parsed or rolled. Every variant carries the character offset
+/// in the original expression that the problem was found at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiceError {
+    /// A character or token that doesn't fit where it is, or the expression ending too early.
+    UnexpectedToken {
+        position: usize,
+    },
+    DivisionByZero {
+        position: usize,
+    },
+    /// More dice than can be rolled at once.
+    TooManyDice {
+        position: usize,
+    },
+    /// Dice with more faces than can be rolled.
+    TooManyFaces {
+        position: usize,
+    },
+    /// Parentheses, signs or exponents nested too deeply.
+    TooDeep {
+        position: usize,
+    },
+    /// A die that can't be rolled, like `0d6` or `2d0`.
+    InvalidDice {
+        position: usize,
+    },
+    /// A repeat like `1000x 1d20` that rolls too many times.
+    TooManyRepeats {
+        position: usize,
+    },
+    /// A `#macro` or `@variable` that hasn't been defined.
+    UnknownMacro {
+        position: usize,
+    },
+    /// A `^` whose result is too big, or whose exponent isn't positive.
+    Overflow {
+        position: usize,
+    },
+}
+
+impl DiceError {
+    pub fn position(&self) -> usize {
+        match *self {
+            DiceError::UnexpectedToken { position }
+            | DiceError::DivisionByZero { position }
+            | DiceError::TooManyDice { position }
+            | DiceError::TooManyFaces { position }
+            | DiceError::TooDeep { position }
+            | DiceError::InvalidDice { position }
+            | DiceError::TooManyRepeats { position }
+            | DiceError::UnknownMacro { position }
+            | DiceError::Overflow { position } => position,
+        }
+    }
+}
+
+impl fmt::Display for DiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let position = self.position();
+        match self {
+            DiceError::UnexpectedToken { .. } => {
+                write!(f, "unexpected input at character {position}")
+            }
+            DiceError::DivisionByZero { .. } => {
+                write!(f, "division by zero at character {position}")
+            }
+            DiceError::TooManyDice { .. } => write!(f, "too many dice at character {position}"),
+            DiceError::TooManyFaces { .. } => {
+                write!(f, "dice with too many faces at character {position}")
+            }
+            DiceError::TooDeep { .. } => write!(f, "too much nesting at character {position}"),
+            DiceError::InvalidDice { .. } => {
+                write!(f, "dice that can't be rolled at character {position}")
+            }
+            DiceError::TooManyRepeats { .. } => {
+                write!(f, "too many repeats at character {position}")
+            }
+            DiceError::UnknownMacro { .. } => {
+                write!(f, "unknown macro or variable at character {position}")
+            }
+            DiceError::Overflow { .. } => {
+                write!(f, "exponent out of range at character {position}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DiceError {}
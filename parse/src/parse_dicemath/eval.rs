@@ -0,0 +1,869 @@
+use super::{
+    ast::{BinaryOp, Comparison, Dice, Explosion, Expr, Faces, Modifier},
+    error::DiceError,
+    limits::DiceLimits,
+};
+use crate::{Roll, RollSingle};
+use rand::Rng;
+
+/// Upper bound on how many times a single die can explode, so `d6!>1` can't loop forever.
+pub(super) const MAX_EXPLOSIONS: u32 = 100;
+/// Upper bound on how many times a single die can be rerolled, so `d6r<6` can't loop forever.
+pub(super) const MAX_REROLLS: u32 = 100;
+
+/// Whether a result is the total of the dice or a count of the dice that hit a target number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResultKind {
+    Sum,
+    Successes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiceResult {
+    pub value: f64,
+    pub kind: ResultKind,
+    /// Kept dice that landed on a critical success, which is the highest face unless `cs` says
+    /// otherwise.
+    pub critical_successes: u32,
+    /// Kept dice that landed on a critical failure, which is the lowest face unless `cf` says
+    /// otherwise.
+    pub critical_failures: u32,
+    /// A success pool botches when it rolls no successes and at least one failure (`f`).
+    pub is_botch: bool,
+    /// Every dice term that was rolled, in formula order, with its subtotal and each die.
+    pub terms: Vec<Roll>,
+}
+
+impl DiceResult {
+    fn constant(value: f64) -> Self {
+        Self {
+            value,
+            kind: ResultKind::Sum,
+            critical_successes: 0,
+            critical_failures: 0,
+            is_botch: false,
+            terms: vec![],
+        }
+    }
+
+    /// Combines the results on either side of an operator. Adding a number to a success pool
+    /// adds successes, so the result is a success count if either side is.
+    fn combine(mut self, op: BinaryOp, other: Self, position: usize) -> Result<Self, DiceError> {
+        let kind = if self.kind == ResultKind::Successes || other.kind == ResultKind::Successes {
+            ResultKind::Successes
+        } else {
+            ResultKind::Sum
+        };
+
+        Ok(Self {
+            value: apply_at(op, self.value, other.value, position)?,
+            kind,
+            critical_successes: self.critical_successes + other.critical_successes,
+            critical_failures: self.critical_failures + other.critical_failures,
+            is_botch: self.is_botch || other.is_botch,
+            terms: {
+                self.terms.extend(other.terms);
+                self.terms
+            },
+        })
+    }
+}
+
+/// The range a single die can land in. Numbered dice start at 1, Fate dice run from -1 to 1.
+#[derive(Clone, Copy)]
+pub(super) struct Die {
+    pub(super) lowest: i64,
+    pub(super) highest: i64,
+}
+
+pub(super) enum Resolved {
+    /// `d1` and `d-1` don't roll anything.
+    Flat(f64),
+    Dice {
+        number: u32,
+        sign: i64,
+        die: Die,
+    },
+}
+
+impl Die {
+    const FATE: Die = Die {
+        lowest: -1,
+        highest: 1,
+    };
+
+    fn numbered(faces: u32) -> Self {
+        Self {
+            lowest: 1,
+            highest: faces as i64,
+        }
+    }
+
+    fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> i64 {
+        rng.gen_range(self.lowest..=self.highest)
+    }
+
+    /// Fate dice are stored as three-sided dice, and are the only dice that can land below 1.
+    fn single_roll(&self, outcome: i64) -> RollSingle {
+        RollSingle {
+            faces: self.highest - self.lowest + 1,
+            outcome,
+            is_rerolled: false,
+            is_fate: self.lowest < 1,
+            is_exploded: false,
+            is_dropped: false,
+        }
+    }
+
+    /// A die that landed on `face` and is worth `value`.
+    fn rolled(&self, value: i64, face: i64) -> Rolled {
+        Rolled {
+            value,
+            single_rolls: vec![self.single_roll(face)],
+        }
+    }
+}
+
+/// One result a term's modifiers and total work with, and every die that was rolled for it. That's
+/// usually a single die, but it also has any dice that were rerolled before it, and a compounded
+/// result is all of the dice that were added together.
+#[derive(Debug, PartialEq)]
+struct Rolled {
+    value: i64,
+    single_rolls: Vec<RollSingle>,
+}
+
+fn roll_dice<R: Rng + ?Sized>(number: u32, die: Die, rng: &mut R) -> Vec<i64> {
+    (0..number).map(|_| die.roll(rng)).collect()
+}
+
+/// Rerolls every result the term's reroll modifiers ask for. The dice that were rerolled stay
+/// with the die that replaced them, marked with `is_rerolled`.
+fn reroll<R: Rng + ?Sized>(dice: &Dice, results: Vec<i64>, die: Die, rng: &mut R) -> Vec<Rolled> {
+    results
+        .into_iter()
+        .map(|result| {
+            let mut rolled = die.rolled(result, result);
+            let mut num_rerolls = 0;
+            while dice.should_reroll(rolled.value, num_rerolls) && num_rerolls < MAX_REROLLS {
+                if let Some(single_roll) = rolled.single_rolls.last_mut() {
+                    single_roll.is_rerolled = true;
+                }
+                rolled.value = die.roll(rng);
+                rolled.single_rolls.push(die.single_roll(rolled.value));
+                num_rerolls += 1;
+            }
+
+            rolled
+        })
+        .collect()
+}
+
+/// Rolls the extra dice for every result whose last die hits the threshold. Exploded and penetrated dice
+/// are added as their own results, while compounded dice are summed into the result of the die
+/// that exploded. Every die keeps the face it landed on, and only the value it adds is lowered
+/// for penetrating dice.
+fn explode<R: Rng + ?Sized>(
+    results: Vec<Rolled>,
+    die: Die,
+    explosion: Explosion,
+    threshold: Option<Comparison>,
+    rng: &mut R,
+) -> Vec<Rolled> {
+    let threshold = threshold.unwrap_or(Comparison::AtLeast(die.highest));
+    let mut exploded: Vec<Rolled> = Vec::with_capacity(results.len());
+
+    for rolled in results {
+        let mut face = rolled.value;
+        let mut num_explosions = 0;
+        exploded.push(rolled);
+
+        while threshold.matches(face) && num_explosions < MAX_EXPLOSIONS {
+            let Some(last) = exploded.last_mut() else {
+                break;
+            };
+            if let Some(single_roll) = last.single_rolls.last_mut() {
+                single_roll.is_exploded = true;
+            }
+
+            face = die.roll(rng);
+            num_explosions += 1;
+            match explosion {
+                Explosion::Explode => exploded.push(die.rolled(face, face)),
+                Explosion::Penetrate => exploded.push(die.rolled(face - 1, face)),
+                Explosion::Compound => {
+                    last.value += face;
+                    last.single_rolls.push(die.single_roll(face));
+                }
+            }
+        }
+    }
+
+    exploded
+}
+
+/// Marks all but `count` of the still-kept dice as dropped, keeping either the highest or lowest.
+fn keep(results: &[i64], kept: &mut [bool], count: u32, highest: bool) {
+    let mut candidates: Vec<usize> = (0..results.len()).filter(|&i| kept[i]).collect();
+    candidates.sort_by_key(|&i| results[i]);
+    if highest {
+        candidates.reverse();
+    }
+
+    for &i in candidates.iter().skip(count as usize) {
+        kept[i] = false;
+    }
+}
+
+pub(super) fn apply(op: BinaryOp, value1: f64, value2: f64) -> Option<f64> {
+    match op {
+        BinaryOp::Add => Some(value1 + value2),
+        BinaryOp::Subtract => Some(value1 - value2),
+        BinaryOp::Multiply => Some(value1 * value2),
+        BinaryOp::Divide => {
+            if value2 == 0. {
+                None
+            } else {
+                Some(value1 / value2)
+            }
+        }
+        BinaryOp::Power => {
+            if value2 > 0. && value2 < u32::MAX.into() {
+                i64::checked_pow(value1.ceil() as i64, value2.ceil() as u32)?;
+                Some(f64::powf(value1, value2))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Like `apply`, but says what went wrong, pointing at the operator at `position`.
+fn apply_at(op: BinaryOp, value1: f64, value2: f64, position: usize) -> Result<f64, DiceError> {
+    apply(op, value1, value2).ok_or(match op {
+        BinaryOp::Divide => DiceError::DivisionByZero { position },
+        _ => DiceError::Overflow { position },
+    })
+}
+
+impl Dice {
+    pub(super) fn apply_modifiers(&self, results: &[i64]) -> Vec<bool> {
+        let mut kept = vec![true; results.len()];
+
+        for modifier in &self.modifiers {
+            let num_kept = kept.iter().filter(|&&is_kept| is_kept).count() as u32;
+            match *modifier {
+                Modifier::KeepHighest(count) => keep(results, &mut kept, count, true),
+                Modifier::KeepLowest(count) => keep(results, &mut kept, count, false),
+                Modifier::DropHighest(count) => {
+                    keep(results, &mut kept, num_kept.saturating_sub(count), false)
+                }
+                Modifier::DropLowest(count) => {
+                    keep(results, &mut kept, num_kept.saturating_sub(count), true)
+                }
+                Modifier::Explode(..)
+                | Modifier::Reroll(_)
+                | Modifier::RerollOnce(_)
+                | Modifier::CriticalSuccess(_)
+                | Modifier::CriticalFailure(_)
+                | Modifier::Success(_)
+                | Modifier::Failure(_) => (),
+            }
+        }
+
+        kept
+    }
+
+    /// Counts how many of `outcomes` hit each of the given modifiers' comparisons.
+    fn count_matching(
+        &self,
+        outcomes: &[i64],
+        comparison: impl Fn(&Modifier) -> Option<Comparison>,
+        default: Option<Comparison>,
+    ) -> u32 {
+        let Some(comparison) = self.modifiers.iter().find_map(comparison).or(default) else {
+            return 0;
+        };
+
+        outcomes
+            .iter()
+            .filter(|&&outcome| comparison.matches(outcome))
+            .count() as u32
+    }
+
+    /// Works out how many of which die a term rolls once its count and faces are known. `faces`
+    /// is `None` for Fate dice.
+    pub(super) fn resolve(
+        &self,
+        count: f64,
+        faces: Option<f64>,
+        limits: &DiceLimits,
+    ) -> Result<Resolved, DiceError> {
+        let position = self.position;
+        let die = match faces {
+            None => Die::FATE,
+            Some(faces) => {
+                if faces.abs().round() == 1. {
+                    return Ok(Resolved::Flat(count * faces));
+                } else if faces.round() <= 0. {
+                    return Err(DiceError::InvalidDice { position });
+                } else if faces.round() > limits.max_faces.into() {
+                    return Err(DiceError::TooManyFaces { position });
+                }
+                Die::numbered(faces.round() as u32)
+            }
+        };
+
+        if count.abs().round() > limits.max_dice.into() {
+            return Err(DiceError::TooManyDice { position });
+        } else if count.abs().round() == 0. {
+            return Err(DiceError::InvalidDice { position });
+        }
+
+        Ok(Resolved::Dice {
+            number: count.abs().round() as u32,
+            sign: if count < 0. { -1 } else { 1 },
+            die,
+        })
+    }
+
+    /// What a single die showing `outcome` adds to a success pool.
+    pub(super) fn success_value(&self, target: Comparison, outcome: i64) -> i64 {
+        let is_failure = self.modifiers.iter().any(
+            |modifier| matches!(modifier, Modifier::Failure(failure) if failure.matches(outcome)),
+        );
+
+        target.matches(outcome) as i64 - is_failure as i64
+    }
+
+    /// Applies keep/drop to the final dice and adds them up, or counts their successes. Also
+    /// returns which dice were kept.
+    pub(super) fn tally(&self, results: &[i64], sign: i64, die: Die) -> (DiceResult, Vec<bool>) {
+        let signed: Vec<i64> = results.iter().map(|result| result * sign).collect();
+        let kept = self.apply_modifiers(&signed);
+        let kept_results: Vec<i64> = results
+            .iter()
+            .zip(&kept)
+            .filter_map(|(&outcome, &is_kept)| is_kept.then_some(outcome))
+            .collect();
+
+        let critical_successes = self.count_matching(
+            &kept_results,
+            |modifier| match modifier {
+                Modifier::CriticalSuccess(comparison) => Some(*comparison),
+                _ => None,
+            },
+            Some(Comparison::AtLeast(die.highest)),
+        );
+        let critical_failures = self.count_matching(
+            &kept_results,
+            |modifier| match modifier {
+                Modifier::CriticalFailure(comparison) => Some(*comparison),
+                _ => None,
+            },
+            Some(Comparison::AtMost(die.lowest)),
+        );
+
+        let Some(target) = self.success_target() else {
+            let result = DiceResult {
+                value: (kept_results.iter().sum::<i64>() * sign) as f64,
+                kind: ResultKind::Sum,
+                critical_successes,
+                critical_failures,
+                is_botch: false,
+                terms: vec![],
+            };
+            return (result, kept);
+        };
+
+        let successes = self.count_matching(&kept_results, |_| None, Some(target));
+        let failures = self.count_matching(
+            &kept_results,
+            |modifier| match modifier {
+                Modifier::Failure(comparison) => Some(*comparison),
+                _ => None,
+            },
+            None,
+        );
+
+        let result = DiceResult {
+            value: ((successes as i64 - failures as i64) * sign) as f64,
+            kind: ResultKind::Successes,
+            critical_successes,
+            critical_failures,
+            is_botch: successes == 0 && failures > 0,
+            terms: vec![],
+        };
+        (result, kept)
+    }
+
+    pub fn roll_with<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        limits: &DiceLimits,
+    ) -> Result<DiceResult, DiceError> {
+        let count = match &self.count {
+            Some(count) => count.roll_with(rng, limits)?.value,
+            None => 1.,
+        };
+        let faces = match &self.faces {
+            Faces::Fate => None,
+            Faces::Numbered(faces) => Some(faces.roll_with(rng, limits)?.value),
+        };
+
+        let (number, sign, die) = match self.resolve(count, faces, limits)? {
+            Resolved::Flat(value) => return Ok(DiceResult::constant(value)),
+            Resolved::Dice { number, sign, die } => (number, sign, die),
+        };
+
+        let rerolled = reroll(self, roll_dice(number, die, rng), die, rng);
+        let rolled = match self.explosion() {
+            Some((explosion, threshold)) => explode(rerolled, die, explosion, threshold, rng),
+            None => rerolled,
+        };
+
+        let results: Vec<i64> = rolled.iter().map(|rolled| rolled.value).collect();
+        let (mut result, kept) = self.tally(&results, sign, die);
+        let single_rolls = rolled
+            .into_iter()
+            .zip(kept)
+            .flat_map(|(rolled, is_kept)| {
+                rolled.single_rolls.into_iter().map(move |mut single_roll| {
+                    single_roll.is_dropped = !is_kept && !single_roll.is_rerolled;
+                    single_roll
+                })
+            })
+            .collect();
+        result.terms.push(Roll {
+            formula: self.to_string(),
+            outcome: result.value,
+            single_rolls,
+        });
+
+        Ok(result)
+    }
+
+    pub fn roll(&self) -> Result<DiceResult, DiceError> {
+        self.roll_with(&mut rand::thread_rng(), &DiceLimits::default())
+    }
+
+    pub fn evaluate(&self) -> Result<f64, DiceError> {
+        Ok(self.roll()?.value)
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression without rolling, for parts of a formula that can't contain dice.
+    pub fn constant_value(&self) -> Option<f64> {
+        match self {
+            Expr::Number(value) => Some(*value),
+            Expr::Dice(_) => None,
+            Expr::Negate(expr) => Some(-expr.constant_value()?),
+            Expr::Binary(op, lhs, rhs, _) => {
+                apply(*op, lhs.constant_value()?, rhs.constant_value()?)
+            }
+            Expr::Group(expr) => expr.constant_value(),
+        }
+    }
+
+    /// Rolls every die in the expression using `rng`, keeping track of successes and criticals.
+    pub fn roll_with<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        limits: &DiceLimits,
+    ) -> Result<DiceResult, DiceError> {
+        match self {
+            Expr::Number(value) => Ok(DiceResult::constant(*value)),
+            Expr::Dice(dice) => dice.roll_with(rng, limits),
+            Expr::Negate(expr) => {
+                let mut result = expr.roll_with(rng, limits)?;
+                result.value = -result.value;
+                Ok(result)
+            }
+            Expr::Binary(op, lhs, rhs, position) => {
+                let lhs = lhs.roll_with(rng, limits)?;
+                lhs.combine(*op, rhs.roll_with(rng, limits)?, *position)
+            }
+            Expr::Group(expr) => expr.roll_with(rng, limits),
+        }
+    }
+
+    /// Rolls every die in the expression with the default limits.
+    pub fn roll(&self) -> Result<DiceResult, DiceError> {
+        self.roll_with(&mut rand::thread_rng(), &DiceLimits::default())
+    }
+
+    /// Rolls every die in the expression and returns the total.
+    pub fn evaluate(&self) -> Result<f64, DiceError> {
+        Ok(self.roll()?.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_add() {
+        assert_eq!(apply(BinaryOp::Add, 1., 1.).unwrap(), 2.);
+        assert_eq!(apply(BinaryOp::Add, 1., -1.).unwrap(), 0.);
+        assert_eq!(apply(BinaryOp::Add, 1., 2.5).unwrap(), 3.5);
+    }
+
+    #[test]
+    fn eval_sub() {
+        assert_eq!(apply(BinaryOp::Subtract, 1., 1.).unwrap(), 0.);
+        assert_eq!(apply(BinaryOp::Subtract, 1., -1.).unwrap(), 2.);
+        assert_eq!(apply(BinaryOp::Subtract, 1., 2.5).unwrap(), -1.5);
+    }
+
+    #[test]
+    fn eval_mul() {
+        assert_eq!(apply(BinaryOp::Multiply, 1., 1.).unwrap(), 1.);
+        assert_eq!(apply(BinaryOp::Multiply, 1., -1.).unwrap(), -1.);
+        assert_eq!(apply(BinaryOp::Multiply, 1., 2.5).unwrap(), 2.5);
+    }
+
+    #[test]
+    fn eval_div() {
+        assert_eq!(apply(BinaryOp::Divide, 1., 1.).unwrap(), 1.);
+        assert_eq!(apply(BinaryOp::Divide, 1., -1.).unwrap(), -1.);
+        assert_eq!(apply(BinaryOp::Divide, 10., 2.).unwrap(), 5.);
+        assert!(apply(BinaryOp::Divide, 10., 0.).is_none());
+    }
+
+    #[test]
+    fn eval_exp() {
+        assert_eq!(apply(BinaryOp::Power, 1., 1.).unwrap(), 1.);
+        assert_eq!(apply(BinaryOp::Power, 1., -1.).unwrap_or(0.), 0.);
+        assert_eq!(apply(BinaryOp::Power, 10., 2.).unwrap(), 100.);
+    }
+
+    #[test]
+    fn eval_roll() {
+        let dice = |count: f64, faces: f64| Dice {
+            count: Some(Box::new(Expr::Number(count))),
+            faces: Faces::Numbered(Box::new(Expr::Number(faces))),
+            modifiers: vec![],
+            position: 0,
+        };
+
+        assert_eq!(dice(1., 1.).evaluate().unwrap(), 1.);
+        assert_eq!(dice(2., 1.).evaluate().unwrap(), 2.);
+        assert_eq!(
+            dice(2., 0.).evaluate(),
+            Err(DiceError::InvalidDice { position: 0 })
+        );
+        assert!((-6. ..=-2.).contains(&dice(-2., 3.).evaluate().unwrap()));
+    }
+
+    #[test]
+    fn eval_keep_drop() {
+        let dice = |modifiers: Vec<Modifier>| Dice {
+            count: Some(Box::new(Expr::Number(4.))),
+            faces: Faces::Numbered(Box::new(Expr::Number(6.))),
+            modifiers,
+            position: 0,
+        };
+        let results = [3, 1, 6, 1];
+
+        let kept = dice(vec![Modifier::KeepHighest(3)]).apply_modifiers(&results);
+        assert_eq!(kept, vec![true, false, true, true]);
+        let kept = dice(vec![Modifier::KeepLowest(1)]).apply_modifiers(&results);
+        assert_eq!(kept, vec![false, true, false, false]);
+        let kept = dice(vec![Modifier::DropLowest(1)]).apply_modifiers(&results);
+        assert_eq!(kept, vec![true, false, true, true]);
+        let kept = dice(vec![Modifier::DropHighest(2)]).apply_modifiers(&results);
+        assert_eq!(kept, vec![false, true, false, true]);
+        let kept =
+            dice(vec![Modifier::DropHighest(1), Modifier::KeepLowest(2)]).apply_modifiers(&results);
+        assert_eq!(kept, vec![false, true, false, true]);
+        let kept = dice(vec![Modifier::KeepHighest(10)]).apply_modifiers(&results);
+        assert_eq!(kept, vec![true; 4]);
+
+        for _ in 0..100 {
+            let total = dice(vec![Modifier::DropLowest(1)]).evaluate().unwrap();
+            assert!((3. ..=18.).contains(&total));
+        }
+    }
+
+    #[test]
+    fn eval_reroll() {
+        let mut rng = rand::thread_rng();
+        let dice = |modifiers: Vec<Modifier>| Dice {
+            count: Some(Box::new(Expr::Number(2.))),
+            faces: Faces::Numbered(Box::new(Expr::Number(6.))),
+            modifiers,
+            position: 0,
+        };
+
+        let reroll_low = dice(vec![Modifier::Reroll(Comparison::AtMost(2))]);
+        let reroll_once = dice(vec![Modifier::RerollOnce(Comparison::AtMost(2))]);
+        let rerolled_flags = |rolled: &Rolled| -> Vec<bool> {
+            rolled
+                .single_rolls
+                .iter()
+                .map(|single_roll| single_roll.is_rerolled)
+                .collect()
+        };
+        for _ in 0..100 {
+            let rerolled = reroll(&reroll_low, vec![1, 2, 5], Die::numbered(6), &mut rng);
+            assert!(rerolled[..2].iter().all(|rolled| rolled.value >= 3));
+            for rolled in &rerolled[..2] {
+                let num_dice = rolled.single_rolls.len();
+                assert!(num_dice >= 2);
+                let mut expected = vec![true; num_dice - 1];
+                expected.push(false);
+                assert_eq!(rerolled_flags(rolled), expected);
+                assert_eq!(rolled.single_rolls[num_dice - 1].outcome, rolled.value);
+            }
+            assert_eq!(rerolled[0].single_rolls[0].outcome, 1);
+            assert_eq!(rerolled[2], Die::numbered(6).rolled(5, 5));
+
+            let rerolled = reroll(&reroll_once, vec![1, 6], Die::numbered(6), &mut rng);
+            assert!((1..=6).contains(&rerolled[0].value));
+            assert_eq!(rerolled_flags(&rerolled[0]), vec![true, false]);
+            assert_eq!(rerolled[1].value, 6);
+        }
+
+        let always = dice(vec![Modifier::Reroll(Comparison::AtLeast(1))]);
+        let rerolled = reroll(&always, vec![3], Die::numbered(6), &mut rng);
+        assert_eq!(rerolled.len(), 1);
+        assert_eq!(rerolled[0].single_rolls.len(), MAX_REROLLS as usize + 1);
+
+        let result = crate::parse_expression("4d6r<2").unwrap().roll().unwrap();
+        let single_rolls = &result.terms[0].single_rolls;
+        let kept: Vec<i64> = single_rolls
+            .iter()
+            .filter(|single_roll| !single_roll.is_rerolled)
+            .map(|single_roll| single_roll.outcome)
+            .collect();
+        assert_eq!(kept.len(), 4);
+        assert_eq!(result.value, kept.iter().sum::<i64>() as f64);
+        assert!(single_rolls
+            .iter()
+            .all(|single_roll| single_roll.is_rerolled == (single_roll.outcome <= 2)));
+        assert!(single_rolls
+            .iter()
+            .all(|single_roll| !single_roll.is_dropped));
+    }
+
+    #[test]
+    fn eval_explode() {
+        let mut rng = rand::thread_rng();
+        let rolled = |results: Vec<i64>| -> Vec<Rolled> {
+            results
+                .into_iter()
+                .map(|result| Die::numbered(6).rolled(result, result))
+                .collect()
+        };
+        let values = |rolled: &[Rolled]| -> Vec<i64> { rolled.iter().map(|r| r.value).collect() };
+        let faces = |rolled: &[Rolled]| -> Vec<i64> {
+            rolled
+                .iter()
+                .flat_map(|rolled| &rolled.single_rolls)
+                .map(|single_roll| single_roll.outcome)
+                .collect()
+        };
+
+        for _ in 0..100 {
+            let exploded = explode(
+                rolled(vec![6, 2]),
+                Die::numbered(6),
+                Explosion::Explode,
+                None,
+                &mut rng,
+            );
+            assert!(exploded.len() >= 3);
+            assert!(exploded[0].single_rolls[0].is_exploded);
+            assert!(!exploded.last().unwrap().single_rolls[0].is_exploded);
+            assert_eq!(exploded[0].value, 6);
+            assert_eq!(exploded.last().unwrap().value, 2);
+            assert_eq!(values(&exploded), faces(&exploded));
+
+            let penetrated = explode(
+                rolled(vec![6]),
+                Die::numbered(6),
+                Explosion::Penetrate,
+                None,
+                &mut rng,
+            );
+            assert!(penetrated.len() >= 2);
+            assert!(values(&penetrated)[1..]
+                .iter()
+                .all(|value| (0..=5).contains(value)));
+            assert!(faces(&penetrated).iter().all(|face| (1..=6).contains(face)));
+            assert!(penetrated[1..]
+                .iter()
+                .all(|rolled| rolled.value == rolled.single_rolls[0].outcome - 1));
+
+            let compounded = explode(
+                rolled(vec![6, 3]),
+                Die::numbered(6),
+                Explosion::Compound,
+                None,
+                &mut rng,
+            );
+            assert_eq!(compounded.len(), 2);
+            assert!(compounded[0].value >= 7);
+            assert!(compounded[0].single_rolls.len() >= 2);
+            assert_eq!(
+                compounded[0].value,
+                faces(&compounded[..1]).iter().sum::<i64>()
+            );
+            assert!(faces(&compounded).iter().all(|face| (1..=6).contains(face)));
+            assert!(compounded[0].single_rolls[0].is_exploded);
+            assert_eq!(compounded[1], Die::numbered(6).rolled(3, 3));
+
+            let thresholded = explode(
+                rolled(vec![4]),
+                Die::numbered(6),
+                Explosion::Explode,
+                Some(Comparison::AtLeast(5)),
+                &mut rng,
+            );
+            assert_eq!(thresholded, vec![Die::numbered(6).rolled(4, 4)]);
+        }
+
+        let always = explode(
+            rolled(vec![1]),
+            Die::numbered(6),
+            Explosion::Explode,
+            Some(Comparison::AtLeast(1)),
+            &mut rng,
+        );
+        assert_eq!(always.len(), MAX_EXPLOSIONS as usize + 1);
+    }
+
+    #[test]
+    fn eval_fate() {
+        let mut rng = rand::thread_rng();
+        let dice = Dice {
+            count: Some(Box::new(Expr::Number(4.))),
+            faces: Faces::Fate,
+            modifiers: vec![],
+            position: 0,
+        };
+
+        for _ in 0..100 {
+            assert!((-4. ..=4.).contains(&dice.evaluate().unwrap()));
+            assert!(roll_dice(10, Die::FATE, &mut rng)
+                .iter()
+                .all(|roll| (-1..=1).contains(roll)));
+        }
+    }
+
+    #[test]
+    fn eval_errors() {
+        let error = |expr: &str| crate::parse_expression(expr).unwrap().roll().unwrap_err();
+
+        assert_eq!(
+            error("1d20 + 4 / (2 - 2)"),
+            DiceError::DivisionByZero { position: 9 }
+        );
+        assert_eq!(
+            error("2 + 5000000000d6"),
+            DiceError::TooManyDice { position: 4 }
+        );
+        assert_eq!(error("1 + 10^100"), DiceError::Overflow { position: 6 });
+        assert_eq!(error("(1d4 - 1)d0"), DiceError::InvalidDice { position: 0 });
+    }
+
+    #[test]
+    fn eval_breakdown() {
+        let result = crate::parse_expression("4d6dl1 + 2d8!>9 + 3")
+            .unwrap()
+            .roll()
+            .unwrap();
+        assert_eq!(result.terms.len(), 2);
+
+        let kept_dice = &result.terms[0];
+        assert_eq!(kept_dice.formula, "4d6dl1");
+        assert_eq!(kept_dice.single_rolls.len(), 4);
+        assert_eq!(
+            kept_dice
+                .single_rolls
+                .iter()
+                .filter(|single_roll| single_roll.is_dropped)
+                .count(),
+            1
+        );
+        assert!(kept_dice
+            .single_rolls
+            .iter()
+            .all(|single_roll| single_roll.faces == 6 && !single_roll.is_exploded));
+
+        let never_exploding = &result.terms[1];
+        assert_eq!(never_exploding.single_rolls.len(), 2);
+        assert_eq!(
+            result.value,
+            kept_dice.outcome + never_exploding.outcome + 3.
+        );
+
+        let fate = Dice {
+            count: Some(Box::new(Expr::Number(2.))),
+            faces: Faces::Fate,
+            modifiers: vec![],
+            position: 0,
+        };
+        let single_rolls = &fate.roll().unwrap().terms[0].single_rolls;
+        assert!(single_rolls
+            .iter()
+            .all(|single_roll| single_roll.is_fate && single_roll.faces == 3));
+    }
+
+    #[test]
+    fn eval_successes() {
+        let pool = |modifiers: Vec<Modifier>| Dice {
+            count: Some(Box::new(Expr::Number(10.))),
+            faces: Faces::Numbered(Box::new(Expr::Number(10.))),
+            modifiers,
+            position: 0,
+        };
+
+        for _ in 0..100 {
+            let result = pool(vec![Modifier::Success(Comparison::AtLeast(7))])
+                .roll()
+                .unwrap();
+            assert_eq!(result.kind, ResultKind::Successes);
+            assert!((0. ..=10.).contains(&result.value));
+            assert!(!result.is_botch);
+
+            let result = pool(vec![
+                Modifier::Success(Comparison::AtLeast(11)),
+                Modifier::Failure(Comparison::AtMost(10)),
+            ])
+            .roll()
+            .unwrap();
+            assert_eq!(result.value, -10.);
+            assert!(result.is_botch);
+
+            let result = pool(vec![
+                Modifier::Success(Comparison::AtLeast(1)),
+                Modifier::CriticalSuccess(Comparison::AtLeast(1)),
+                Modifier::CriticalFailure(Comparison::AtLeast(11)),
+            ])
+            .roll()
+            .unwrap();
+            assert_eq!(result.value, 10.);
+            assert_eq!(result.critical_successes, 10);
+            assert_eq!(result.critical_failures, 0);
+
+            let result = Expr::Binary(
+                BinaryOp::Add,
+                Box::new(Expr::Dice(pool(vec![Modifier::Success(
+                    Comparison::AtLeast(11),
+                )]))),
+                Box::new(Expr::Number(2.)),
+                0,
+            )
+            .roll()
+            .unwrap();
+            assert_eq!(result.value, 2.);
+            assert_eq!(result.kind, ResultKind::Successes);
+        }
+
+        let result = Expr::Number(3.).roll().unwrap();
+        assert_eq!(result.kind, ResultKind::Sum);
+    }
+}
@@ -0,0 +1,68 @@
+/// Finds Roll20-style `[[1d20+5]]` inline rolls in `text` and replaces each with whatever `roll`
+/// returns for the expression inside it. Labels like `[[1d20+5[Str]]]` stay part of the
+/// expression. Returns `None` if `text` has no inline rolls.
+pub fn replace_inline_rolls<F: FnMut(&str) -> String>(text: &str, mut roll: F) -> Option<String> {
+    let mut replaced = String::new();
+    let mut rest = text;
+    let mut found_roll = false;
+
+    while let Some(start) = rest.find("[[") {
+        let inner = &rest[start + 2..];
+        let Some(length) = inline_roll_length(inner) else {
+            break;
+        };
+
+        replaced.push_str(&rest[..start]);
+        replaced.push_str(&roll(&inner[..length]));
+        rest = &inner[length + 2..];
+        found_roll = true;
+    }
+
+    if !found_roll {
+        return None;
+    }
+    replaced.push_str(rest);
+    Some(replaced)
+}
+
+/// How many bytes of `inner` come before the `]]` closing an inline roll, skipping over labels.
+fn inline_roll_length(inner: &str) -> Option<usize> {
+    let mut label_depth = 0;
+    let mut chars = inner.char_indices().peekable();
+
+    while let Some((index, symbol)) = chars.next() {
+        match symbol {
+            '[' => label_depth += 1,
+            ']' if label_depth > 0 => label_depth -= 1,
+            ']' if chars.next_if(|(_, next)| *next == ']').is_some() => return Some(index),
+            _ => (),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_rolls() {
+        let brackets = |expr: &str| format!("<{expr}>");
+
+        assert_eq!(replace_inline_rolls("no rolls here", brackets), None);
+        assert_eq!(replace_inline_rolls("unclosed [[1d20", brackets), None);
+        assert_eq!(
+            replace_inline_rolls("I hit for [[2d6+3]] and [[1d4]]!", brackets).unwrap(),
+            "I hit for <2d6+3> and <1d4>!"
+        );
+        assert_eq!(
+            replace_inline_rolls("[[1d20+5[Str]]] vs [Armor]", brackets).unwrap(),
+            "<1d20+5[Str]> vs [Armor]"
+        );
+        assert_eq!(
+            replace_inline_rolls("[[1]] then [[2", brackets).unwrap(),
+            "<1> then [[2"
+        );
+    }
+}
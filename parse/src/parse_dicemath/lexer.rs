@@ -0,0 +1,163 @@
+use super::error::DiceError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Number(f64),
+    Word(String),
+    Label(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    LeftParen,
+    RightParen,
+    Bang,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equals,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub position: usize,
+}
+
+/// Splits a dice expression into tokens, tagging each with the character offset it starts at.
+/// Square-bracketed labels like `[Mods]` are kept as their own token so the parser can skip them.
+pub fn tokenize(expr: &str) -> Result<Vec<Token>, DiceError> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut chars = expr.chars().enumerate().peekable();
+
+    while let Some((position, symbol)) = chars.next() {
+        let kind = match symbol {
+            ' ' | '\t' | '\n' | '\r' => continue,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '*' => TokenKind::Star,
+            '/' => TokenKind::Slash,
+            '^' => TokenKind::Caret,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '!' => TokenKind::Bang,
+            '=' => TokenKind::Equals,
+            '<' => {
+                if chars.next_if(|(_, next)| *next == '=').is_some() {
+                    TokenKind::LessOrEqual
+                } else {
+                    TokenKind::Less
+                }
+            }
+            '>' => {
+                if chars.next_if(|(_, next)| *next == '=').is_some() {
+                    TokenKind::GreaterOrEqual
+                } else {
+                    TokenKind::Greater
+                }
+            }
+            '[' => {
+                let mut label = String::new();
+                loop {
+                    match chars
+                        .next()
+                        .ok_or(DiceError::UnexpectedToken { position })?
+                    {
+                        (_, ']') => break,
+                        (_, ch) => label.push(ch),
+                    }
+                }
+                TokenKind::Label(label)
+            }
+            ch if ch.is_ascii_digit() || ch == '.' => {
+                let mut number = String::from(ch);
+                while let Some((_, next)) =
+                    chars.next_if(|(_, next)| next.is_ascii_digit() || *next == '.')
+                {
+                    number.push(next);
+                }
+                TokenKind::Number(
+                    number
+                        .parse::<f64>()
+                        .map_err(|_| DiceError::UnexpectedToken { position })?,
+                )
+            }
+            ch if ch.is_alphabetic() => {
+                let mut word = String::from(ch);
+                while let Some((_, next)) = chars.next_if(|(_, next)| next.is_alphabetic()) {
+                    word.push(next);
+                }
+                TokenKind::Word(word)
+            }
+            _ => return Err(DiceError::UnexpectedToken { position }),
+        };
+
+        tokens.push(Token { kind, position });
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(expr: &str) -> Vec<TokenKind> {
+        tokenize(expr)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_dice() {
+        assert_eq!(
+            kinds("2d20 + 3.5"),
+            vec![
+                TokenKind::Number(2.),
+                TokenKind::Word("d".to_string()),
+                TokenKind::Number(20.),
+                TokenKind::Plus,
+                TokenKind::Number(3.5),
+            ]
+        );
+        assert_eq!(
+            kinds("1d20cs>=19[Mods]"),
+            vec![
+                TokenKind::Number(1.),
+                TokenKind::Word("d".to_string()),
+                TokenKind::Number(20.),
+                TokenKind::Word("cs".to_string()),
+                TokenKind::GreaterOrEqual,
+                TokenKind::Number(19.),
+                TokenKind::Label("Mods".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_positions() {
+        let tokens = tokenize(" 10 *(d6)").unwrap();
+        let positions: Vec<usize> = tokens.iter().map(|token| token.position).collect();
+        assert_eq!(positions, vec![1, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn tokenize_invalid() {
+        assert_eq!(
+            tokenize("2d6 $ 3"),
+            Err(DiceError::UnexpectedToken { position: 4 })
+        );
+        assert_eq!(
+            tokenize("1d20 [unclosed"),
+            Err(DiceError::UnexpectedToken { position: 5 })
+        );
+        assert_eq!(
+            tokenize("1..2"),
+            Err(DiceError::UnexpectedToken { position: 0 })
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use serde::Deserialize;
+
+/// Caps on how big a dice expression can get before it's rejected instead of rolled, so one
+/// `4000000000d6` can't hang the bot. Can be set under `dice_limits` in the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DiceLimits {
+    /// Most dice a single term like `4d6` can roll, not counting explosions.
+    pub max_dice: u32,
+    /// Most faces a single die can have.
+    pub max_faces: u32,
+    /// How deeply parentheses, signs and exponents can be nested.
+    pub max_depth: usize,
+    /// Most times a repeated roll like `6x 4d6dl1` can be rolled.
+    pub max_repeats: u32,
+}
+
+impl Default for DiceLimits {
+    fn default() -> Self {
+        Self {
+            max_dice: 10_000,
+            max_faces: 1_000_000,
+            max_depth: 64,
+            max_repeats: 20,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use super::{error::DiceError, limits::DiceLimits};
+use std::collections::HashMap;
+
+/// Whether `name` is a macro like `#atk` or a variable like `@str`. Names have to start with a
+/// letter so repeats like `3#1d20` aren't mistaken for macros.
+pub fn is_macro_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some('#' | '@'))
+        && chars.next().is_some_and(|ch| ch.is_ascii_alphabetic())
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// Splits a definition like `#atk = 1d20+@str+@prof` into its name and expression.
+pub fn parse_macro_definition(definition: &str) -> Result<(String, String), DiceError> {
+    let trimmed = definition.trim_start();
+    let offset = definition.chars().count() - trimmed.chars().count();
+
+    let name_length = trimmed
+        .char_indices()
+        .skip(1)
+        .find(|(_, ch)| !ch.is_ascii_alphanumeric() && *ch != '_')
+        .map_or(trimmed.len(), |(index, _)| index);
+    let name = &trimmed[..name_length];
+    if !is_macro_name(name) {
+        return Err(DiceError::UnexpectedToken { position: offset });
+    }
+
+    let rest = &trimmed[name_length..];
+    let expression = rest
+        .trim_start()
+        .strip_prefix('=')
+        .map(str::trim)
+        .filter(|expression| !expression.is_empty());
+    match expression {
+        Some(expression) => Ok((name.to_string(), expression.to_string())),
+        None => Err(DiceError::UnexpectedToken {
+            position: offset + name.chars().count() + rest.chars().count()
+                - rest.trim_start().chars().count(),
+        }),
+    }
+}
+
+/// Replaces every `#macro` and `@variable` in `expr` with its parenthesized definition from
+/// `macros`, which is keyed by name including the `#` or `@`. Definitions can use other macros,
+/// up to `limits.max_depth` deep. Errors point at the reference in `expr` that failed.
+pub fn expand_macros(
+    expr: &str,
+    macros: &HashMap<String, String>,
+    limits: &DiceLimits,
+) -> Result<String, DiceError> {
+    expand(expr, macros, limits.max_depth, None)
+}
+
+fn expand(
+    expr: &str,
+    macros: &HashMap<String, String>,
+    depth_left: usize,
+    reference_position: Option<usize>,
+) -> Result<String, DiceError> {
+    let mut expanded = String::new();
+    let mut chars = expr.chars().enumerate().peekable();
+    let mut in_label = false;
+
+    while let Some((position, symbol)) = chars.next() {
+        let starts_name = matches!(symbol, '#' | '@')
+            && chars
+                .peek()
+                .is_some_and(|(_, next)| next.is_ascii_alphabetic());
+        if in_label || !starts_name {
+            match symbol {
+                '[' => in_label = true,
+                ']' => in_label = false,
+                _ => (),
+            }
+            expanded.push(symbol);
+            continue;
+        }
+
+        let mut name = String::from(symbol);
+        while let Some((_, next)) =
+            chars.next_if(|(_, next)| next.is_ascii_alphanumeric() || *next == '_')
+        {
+            name.push(next);
+        }
+
+        let position = reference_position.unwrap_or(position);
+        let definition = macros
+            .get(&name)
+            .ok_or(DiceError::UnknownMacro { position })?;
+        if depth_left == 0 {
+            return Err(DiceError::TooDeep { position });
+        }
+        let inner = expand(definition, macros, depth_left - 1, Some(position))?;
+        expanded.push_str(&format!("({inner})"));
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn macro_definitions() {
+        assert_eq!(
+            parse_macro_definition("#atk = 1d20+@str+@prof").unwrap(),
+            ("#atk".to_string(), "1d20+@str+@prof".to_string())
+        );
+        assert_eq!(
+            parse_macro_definition(" @str=3").unwrap(),
+            ("@str".to_string(), "3".to_string())
+        );
+        assert_eq!(
+            parse_macro_definition("atk = 1d20"),
+            Err(DiceError::UnexpectedToken { position: 0 })
+        );
+        assert_eq!(
+            parse_macro_definition("#atk 1d20"),
+            Err(DiceError::UnexpectedToken { position: 5 })
+        );
+        assert_eq!(
+            parse_macro_definition("#atk ="),
+            Err(DiceError::UnexpectedToken { position: 5 })
+        );
+        assert!(!is_macro_name("#1d20"));
+    }
+
+    #[test]
+    fn macro_expansion() {
+        let macros = HashMap::from([
+            ("#atk".to_string(), "1d20+@str+@prof".to_string()),
+            ("@str".to_string(), "3".to_string()),
+            ("@prof".to_string(), "2".to_string()),
+            ("#loop".to_string(), "1+#loop".to_string()),
+        ]);
+        let limits = DiceLimits::default();
+
+        assert_eq!(
+            expand_macros("#atk + 1", &macros, &limits).unwrap(),
+            "(1d20+(3)+(2)) + 1"
+        );
+        assert_eq!(
+            expand_macros("3#1d20 [#atk]", &macros, &limits).unwrap(),
+            "3#1d20 [#atk]"
+        );
+        assert_eq!(
+            expand_macros("1 + @dex", &macros, &limits),
+            Err(DiceError::UnknownMacro { position: 4 })
+        );
+        assert_eq!(
+            expand_macros("2 * #loop", &macros, &limits),
+            Err(DiceError::TooDeep { position: 4 })
+        );
+    }
+}
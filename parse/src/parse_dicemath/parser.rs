@@ -0,0 +1,635 @@
+use super::{
+    ast::{BinaryOp, Comparison, Dice, Explosion, Expr, Faces, Modifier},
+    error::DiceError,
+    lexer::{tokenize, Token, TokenKind},
+    limits::DiceLimits,
+};
+
+/// The lexer reads runs of letters as one word, so `4dFkh1` arrives as `dFkh` and has to be
+/// split back apart here.
+fn is_fate_word(word: &str) -> bool {
+    word.starts_with("dF") || word.starts_with("df")
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+    /// Where the expression ends, for errors about it ending too early.
+    end: usize,
+    /// How many signs, exponents and parentheses deep the parser currently is.
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.index).map(|token| &token.kind)
+    }
+
+    fn previous(&self) -> Option<&TokenKind> {
+        self.tokens
+            .get(self.index.checked_sub(1)?)
+            .map(|token| &token.kind)
+    }
+
+    /// Where the next token starts.
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .map_or(self.end, |token| token.position)
+    }
+
+    /// An error pointing at the next token.
+    fn unexpected(&self) -> DiceError {
+        DiceError::UnexpectedToken {
+            position: self.position(),
+        }
+    }
+
+    /// An error pointing at the token that was just read.
+    fn unexpected_previous(&self) -> DiceError {
+        DiceError::UnexpectedToken {
+            position: self.tokens[self.index - 1].position,
+        }
+    }
+
+    fn advance(&mut self) -> Result<&TokenKind, DiceError> {
+        let token = self
+            .tokens
+            .get(self.index)
+            .ok_or(DiceError::UnexpectedToken { position: self.end })?;
+        self.index += 1;
+        Ok(&token.kind)
+    }
+
+    fn next_is_dice(&self) -> bool {
+        matches!(self.peek(), Some(TokenKind::Word(word)) if word == "d" || is_fate_word(word))
+    }
+
+    fn next_is_comparison(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(
+                TokenKind::Number(_)
+                    | TokenKind::Equals
+                    | TokenKind::Greater
+                    | TokenKind::GreaterOrEqual
+                    | TokenKind::Less
+                    | TokenKind::LessOrEqual
+            )
+        )
+    }
+
+    /// After reading a `dF...` word, puts any modifier letters that were glued onto it back in
+    /// front of the parser.
+    fn split_fate_word(&mut self) {
+        let token = &self.tokens[self.index - 1];
+        let TokenKind::Word(word) = &token.kind else {
+            return;
+        };
+        if word.len() <= 2 {
+            return;
+        }
+
+        let rest = Token {
+            kind: TokenKind::Word(word[2..].to_string()),
+            position: token.position + 2,
+        };
+        self.tokens.insert(self.index, rest);
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, DiceError> {
+        let mut lhs = self.parse_product()?;
+
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Plus) => BinaryOp::Add,
+                Some(TokenKind::Minus) => BinaryOp::Subtract,
+                _ => return Ok(lhs),
+            };
+            let position = self.position();
+            self.advance()?;
+
+            let rhs = self.parse_product()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs), position);
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<Expr, DiceError> {
+        let mut lhs = self.parse_unary()?;
+
+        loop {
+            let position = self.position();
+            let op = match (self.peek(), self.previous()) {
+                (Some(TokenKind::Star), _) => {
+                    self.advance()?;
+                    BinaryOp::Multiply
+                }
+                (Some(TokenKind::Slash), _) => {
+                    self.advance()?;
+                    BinaryOp::Divide
+                }
+                // implicit multiplication, e.g. "2(1d4)" or "(1d4)2"
+                (Some(TokenKind::LeftParen), _)
+                | (Some(TokenKind::Number(_)), Some(TokenKind::RightParen)) => BinaryOp::Multiply,
+                _ => return Ok(lhs),
+            };
+
+            let rhs = self.parse_unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs), position);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, DiceError> {
+        if self.depth >= self.max_depth {
+            return Err(DiceError::TooDeep {
+                position: self.position(),
+            });
+        }
+
+        self.depth += 1;
+        let parsed = self.parse_unary_inner();
+        self.depth -= 1;
+        parsed
+    }
+
+    fn parse_unary_inner(&mut self) -> Result<Expr, DiceError> {
+        match self.peek() {
+            Some(TokenKind::Minus) => {
+                self.advance()?;
+                Ok(Expr::Negate(Box::new(self.parse_unary()?)))
+            }
+            Some(TokenKind::Plus) => {
+                self.advance()?;
+                self.parse_unary()
+            }
+            _ => self.parse_power(),
+        }
+    }
+
+    fn parse_power(&mut self) -> Result<Expr, DiceError> {
+        let base = self.parse_dice()?;
+
+        if let Some(TokenKind::Caret) = self.peek() {
+            let position = self.position();
+            self.advance()?;
+            let exponent = self.parse_unary()?;
+            return Ok(Expr::Binary(
+                BinaryOp::Power,
+                Box::new(base),
+                Box::new(exponent),
+                position,
+            ));
+        }
+
+        Ok(base)
+    }
+
+    fn parse_dice(&mut self) -> Result<Expr, DiceError> {
+        let position = self.position();
+        let count = if self.next_is_dice() {
+            None
+        } else {
+            let primary = self.parse_primary()?;
+            if !self.next_is_dice() {
+                return Ok(primary);
+            }
+            Some(Box::new(primary))
+        };
+
+        let faces = match self.advance()? {
+            TokenKind::Word(word) if is_fate_word(word) => {
+                self.split_fate_word();
+                Faces::Fate
+            }
+            _ => Faces::Numbered(Box::new(self.parse_primary()?)),
+        };
+        let modifiers = self.parse_modifiers()?;
+
+        Ok(Expr::Dice(Dice {
+            count,
+            faces,
+            modifiers,
+            position,
+        }))
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, DiceError> {
+        match self.advance()? {
+            TokenKind::Number(value) => Ok(Expr::Number(*value)),
+            TokenKind::LeftParen => {
+                let inner = self.parse_sum()?;
+                match self.advance()? {
+                    TokenKind::RightParen => Ok(Expr::Group(Box::new(inner))),
+                    _ => Err(self.unexpected_previous()),
+                }
+            }
+            _ => Err(self.unexpected_previous()),
+        }
+    }
+
+    fn parse_explosion(&mut self) -> Result<Modifier, DiceError> {
+        self.advance()?;
+
+        let explosion = match self.peek() {
+            Some(TokenKind::Bang) => {
+                self.advance()?;
+                Explosion::Compound
+            }
+            Some(TokenKind::Word(word)) if word == "p" => {
+                self.advance()?;
+                Explosion::Penetrate
+            }
+            _ => Explosion::Explode,
+        };
+
+        let threshold = if self.next_is_comparison() {
+            Some(self.parse_comparison()?)
+        } else {
+            None
+        };
+
+        Ok(Modifier::Explode(explosion, threshold))
+    }
+
+    fn parse_modifiers(&mut self) -> Result<Vec<Modifier>, DiceError> {
+        let mut modifiers: Vec<Modifier> = vec![];
+
+        loop {
+            let word = match self.peek() {
+                Some(TokenKind::Bang) => {
+                    let explosion = self.parse_explosion()?;
+                    modifiers.push(explosion);
+                    continue;
+                }
+                Some(
+                    TokenKind::Equals
+                    | TokenKind::Greater
+                    | TokenKind::GreaterOrEqual
+                    | TokenKind::Less
+                    | TokenKind::LessOrEqual,
+                ) => {
+                    let target = self.parse_comparison()?;
+                    modifiers.push(Modifier::Success(target));
+                    continue;
+                }
+                Some(TokenKind::Word(word)) => word,
+                _ => break,
+            };
+
+            let modifier = match word.as_str() {
+                "r" => {
+                    self.advance()?;
+                    Modifier::Reroll(self.parse_optional_comparison()?)
+                }
+                "ro" => {
+                    self.advance()?;
+                    Modifier::RerollOnce(self.parse_optional_comparison()?)
+                }
+                "k" | "kh" => {
+                    self.advance()?;
+                    Modifier::KeepHighest(self.parse_optional_count()?)
+                }
+                "kl" => {
+                    self.advance()?;
+                    Modifier::KeepLowest(self.parse_optional_count()?)
+                }
+                "d" | "dl" => {
+                    self.advance()?;
+                    Modifier::DropLowest(self.parse_optional_count()?)
+                }
+                "dh" => {
+                    self.advance()?;
+                    Modifier::DropHighest(self.parse_optional_count()?)
+                }
+                "cs" => {
+                    self.advance()?;
+                    Modifier::CriticalSuccess(self.parse_comparison()?)
+                }
+                "cf" => {
+                    self.advance()?;
+                    Modifier::CriticalFailure(self.parse_comparison()?)
+                }
+                "f" => {
+                    self.advance()?;
+                    Modifier::Failure(self.parse_comparison()?)
+                }
+                _ => return Err(self.unexpected()),
+            };
+
+            modifiers.push(modifier);
+        }
+
+        Ok(modifiers)
+    }
+
+    fn parse_whole_number(&mut self) -> Result<i64, DiceError> {
+        match self.advance()? {
+            TokenKind::Number(value) if value.fract() == 0. => Ok(*value as i64),
+            _ => Err(self.unexpected_previous()),
+        }
+    }
+
+    /// Keep and drop modifiers default to a single die, so `2d20kh` is the same as `2d20kh1`.
+    fn parse_optional_count(&mut self) -> Result<u32, DiceError> {
+        match self.peek() {
+            Some(TokenKind::Number(_)) => self
+                .parse_whole_number()?
+                .try_into()
+                .map_err(|_| self.unexpected_previous()),
+            _ => Ok(1),
+        }
+    }
+
+    /// Rerolls with no threshold, like Foundry's `1d20r`, reroll 1s.
+    fn parse_optional_comparison(&mut self) -> Result<Comparison, DiceError> {
+        if self.next_is_comparison() {
+            self.parse_comparison()
+        } else {
+            Ok(Comparison::Equal(1))
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Comparison, DiceError> {
+        let comparison = match self.peek() {
+            Some(TokenKind::Equals) => Comparison::Equal,
+            Some(TokenKind::Greater | TokenKind::GreaterOrEqual) => Comparison::AtLeast,
+            Some(TokenKind::Less | TokenKind::LessOrEqual) => Comparison::AtMost,
+            Some(TokenKind::Number(_)) => return Ok(Comparison::Equal(self.parse_whole_number()?)),
+            _ => return Err(self.unexpected()),
+        };
+        self.advance()?;
+
+        Ok(comparison(self.parse_whole_number()?))
+    }
+}
+
+/// Parses a dice expression into an `Expr` tree without rolling anything, or says where the first
+/// thing that isn't part of a complete, well-formed expression is.
+pub fn parse_expression(expr: &str) -> Result<Expr, DiceError> {
+    parse_expression_with(expr, &DiceLimits::default())
+}
+
+/// Parses `expr`, rejecting anything nested deeper than `limits` allows.
+pub fn parse_expression_with(expr: &str, limits: &DiceLimits) -> Result<Expr, DiceError> {
+    let tokens: Vec<Token> = tokenize(expr)?
+        .into_iter()
+        .filter(|token| !matches!(token.kind, TokenKind::Label(_)))
+        .collect();
+    let mut parser = Parser {
+        tokens,
+        index: 0,
+        end: expr.chars().count(),
+        depth: 0,
+        max_depth: limits.max_depth,
+    };
+
+    let parsed = parser.parse_sum()?;
+    if parser.index != parser.tokens.len() {
+        return Err(parser.unexpected());
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_precedence() {
+        assert_eq!(
+            parse_expression("1 + 2 * 3").unwrap(),
+            Expr::Binary(
+                BinaryOp::Add,
+                Box::new(Expr::Number(1.)),
+                Box::new(Expr::Binary(
+                    BinaryOp::Multiply,
+                    Box::new(Expr::Number(2.)),
+                    Box::new(Expr::Number(3.)),
+                    6,
+                )),
+                2,
+            )
+        );
+        assert_eq!(
+            parse_expression("-2d6").unwrap(),
+            Expr::Negate(Box::new(Expr::Dice(Dice {
+                count: Some(Box::new(Expr::Number(2.))),
+                faces: Faces::Numbered(Box::new(Expr::Number(6.))),
+                modifiers: vec![],
+                position: 1,
+            })))
+        );
+    }
+
+    #[test]
+    fn parse_modifiers() {
+        let Expr::Dice(dice) = parse_expression("d20cs>19cf1").unwrap() else {
+            panic!("expected dice expression");
+        };
+        assert!(dice.count.is_none());
+        assert_eq!(
+            dice.modifiers,
+            vec![
+                Modifier::CriticalSuccess(Comparison::AtLeast(19)),
+                Modifier::CriticalFailure(Comparison::Equal(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_keep_drop() {
+        let modifiers = |expr: &str| match parse_expression(expr) {
+            Ok(Expr::Dice(dice)) => dice.modifiers,
+            _ => panic!("expected dice expression for {expr}"),
+        };
+
+        assert_eq!(modifiers("2d20kh1"), vec![Modifier::KeepHighest(1)]);
+        assert_eq!(modifiers("2d20kl"), vec![Modifier::KeepLowest(1)]);
+        assert_eq!(modifiers("4d6k3"), vec![Modifier::KeepHighest(3)]);
+        assert_eq!(modifiers("4d6d1"), vec![Modifier::DropLowest(1)]);
+        assert_eq!(
+            modifiers("5d10dh1dl2"),
+            vec![Modifier::DropHighest(1), Modifier::DropLowest(2)]
+        );
+        assert!(parse_expression("4d6kh1.5").is_err());
+    }
+
+    #[test]
+    fn parse_explosions() {
+        let modifiers = |expr: &str| match parse_expression(expr) {
+            Ok(Expr::Dice(dice)) => dice.modifiers,
+            _ => panic!("expected dice expression for {expr}"),
+        };
+
+        assert_eq!(
+            modifiers("d6!"),
+            vec![Modifier::Explode(Explosion::Explode, None)]
+        );
+        assert_eq!(
+            modifiers("d6!!"),
+            vec![Modifier::Explode(Explosion::Compound, None)]
+        );
+        assert_eq!(
+            modifiers("d6!p"),
+            vec![Modifier::Explode(Explosion::Penetrate, None)]
+        );
+        assert_eq!(
+            modifiers("5d10!>8kh2"),
+            vec![
+                Modifier::Explode(Explosion::Explode, Some(Comparison::AtLeast(8))),
+                Modifier::KeepHighest(2),
+            ]
+        );
+        assert_eq!(
+            modifiers("d6!!5"),
+            vec![Modifier::Explode(
+                Explosion::Compound,
+                Some(Comparison::Equal(5))
+            )]
+        );
+        assert_eq!(
+            parse_expression("3d6!p>5 + 1").unwrap().to_string(),
+            "3d6!p>5 + 1"
+        );
+    }
+
+    #[test]
+    fn parse_rerolls() {
+        let modifiers = |expr: &str| match parse_expression(expr) {
+            Ok(Expr::Dice(dice)) => dice.modifiers,
+            _ => panic!("expected dice expression for {expr}"),
+        };
+
+        assert_eq!(
+            modifiers("2d6ro<2"),
+            vec![Modifier::RerollOnce(Comparison::AtMost(2))]
+        );
+        assert_eq!(
+            modifiers("1d20r"),
+            vec![Modifier::Reroll(Comparison::Equal(1))]
+        );
+        assert_eq!(
+            modifiers("4d6r1r2kh3"),
+            vec![
+                Modifier::Reroll(Comparison::Equal(1)),
+                Modifier::Reroll(Comparison::Equal(2)),
+                Modifier::KeepHighest(3),
+            ]
+        );
+        assert_eq!(parse_expression("2d6ro<2").unwrap().to_string(), "2d6ro<2");
+    }
+
+    #[test]
+    fn parse_success_pools() {
+        let modifiers = |expr: &str| match parse_expression(expr) {
+            Ok(Expr::Dice(dice)) => dice.modifiers,
+            _ => panic!("expected dice expression for {expr}"),
+        };
+
+        assert_eq!(
+            modifiers("10d10>7"),
+            vec![Modifier::Success(Comparison::AtLeast(7))]
+        );
+        assert_eq!(
+            modifiers("10d10>=8f1"),
+            vec![
+                Modifier::Success(Comparison::AtLeast(8)),
+                Modifier::Failure(Comparison::Equal(1)),
+            ]
+        );
+        assert_eq!(
+            modifiers("6d6!=6>5"),
+            vec![
+                Modifier::Explode(Explosion::Explode, Some(Comparison::Equal(6))),
+                Modifier::Success(Comparison::AtLeast(5)),
+            ]
+        );
+        assert_eq!(
+            parse_expression("10d10>7f<2").unwrap().to_string(),
+            "10d10>7f<2"
+        );
+        assert!(parse_expression("10d10>").is_err());
+        assert!(parse_expression("10d10f").is_err());
+    }
+
+    #[test]
+    fn parse_fate() {
+        let Expr::Dice(dice) = parse_expression("4dFkh2").unwrap() else {
+            panic!("expected dice expression");
+        };
+        assert_eq!(dice.faces, Faces::Fate);
+        assert_eq!(dice.modifiers, vec![Modifier::KeepHighest(2)]);
+
+        assert_eq!(
+            parse_expression("dF + 4df").unwrap().to_string(),
+            "dF + 4dF"
+        );
+        assert!(parse_expression("4dFx").is_err());
+    }
+
+    #[test]
+    fn parse_pretty_print() {
+        let parsed = parse_expression("2d20+(15+0)[Mods] +3d6cs>=6").unwrap();
+        assert_eq!(parsed.to_string(), "2d20 + (15 + 0) + 3d6cs>6");
+        assert_eq!(
+            parse_expression(&parsed.to_string()).unwrap().to_string(),
+            parsed.to_string()
+        );
+        assert_eq!(
+            parse_expression("2(3)^-1").unwrap().to_string(),
+            "2 * (3)^-1"
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse_expression("").is_err());
+        assert!(parse_expression("2d6+x").is_err());
+        assert!(parse_expression("2d6+").is_err());
+        assert!(parse_expression("(1 + 2").is_err());
+        assert!(parse_expression("1 + 2)").is_err());
+        assert!(parse_expression("1d20cs>").is_err());
+        assert!(parse_expression("1d20cs>1.5").is_err());
+    }
+
+    #[test]
+    fn parse_error_positions() {
+        let position = |expr: &str| match parse_expression(expr) {
+            Err(DiceError::UnexpectedToken { position }) => position,
+            _ => panic!("expected an unexpected token in {expr}"),
+        };
+
+        assert_eq!(position(""), 0);
+        assert_eq!(position("2d6+x"), 4);
+        assert_eq!(position("2d6+"), 4);
+        assert_eq!(position("(1 + 2"), 6);
+        assert_eq!(position("1 + 2)"), 5);
+        assert_eq!(position("1d20cs>1.5"), 7);
+        assert_eq!(position("4d6kq"), 3);
+        assert_eq!(position("2d6 $ 3"), 4);
+    }
+
+    #[test]
+    fn parse_depth_limit() {
+        let limits = DiceLimits {
+            max_depth: 4,
+            ..DiceLimits::default()
+        };
+
+        assert!(parse_expression_with("((1))", &limits).is_ok());
+        assert_eq!(
+            parse_expression_with("((((1))))", &limits),
+            Err(DiceError::TooDeep { position: 4 })
+        );
+        assert_eq!(
+            parse_expression_with("1 + ----2", &limits),
+            Err(DiceError::TooDeep { position: 8 })
+        );
+        assert!(matches!(
+            parse_expression(&format!("{}1{}", "(".repeat(1000), ")".repeat(1000))),
+            Err(DiceError::TooDeep { .. })
+        ));
+    }
+}
@@ -0,0 +1,80 @@
+use super::{error::DiceError, limits::DiceLimits};
+
+/// Finds a leading repeat count like the `6x` in `6x 4d6dl1` or the `3#` in `3#1d20`, returning
+/// where the count starts, the count itself, and how many bytes the whole prefix takes up.
+fn repeat_prefix(expr: &str) -> Option<(usize, &str, usize)> {
+    let count_start = expr.len() - expr.trim_start().len();
+    let count_length = expr[count_start..]
+        .find(|ch: char| !ch.is_ascii_digit())
+        .filter(|&length| length > 0)?;
+    let count = &expr[count_start..count_start + count_length];
+
+    let after_count = &expr[count_start + count_length..];
+    let marker = after_count.trim_start();
+    if !marker.starts_with(['x', 'X', '#']) {
+        return None;
+    }
+    // `6x` is followed by the expression, not more letters, so words like `2xyz` aren't repeats.
+    if marker[1..].starts_with(|ch: char| ch.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    Some((count_start, count, expr.len() - marker.len() + 1))
+}
+
+/// Splits a repeated expression like `6x 4d6dl1` into how many times to roll it and the
+/// expression to roll, which is `1` and the whole expression if there's no repeat prefix. The
+/// prefix is blanked out rather than removed so errors still point at the right character.
+pub fn split_repeat(expr: &str, limits: &DiceLimits) -> Result<(u32, String), DiceError> {
+    let Some((position, count, prefix_length)) = repeat_prefix(expr) else {
+        return Ok((1, expr.to_string()));
+    };
+    let position = expr[..position].chars().count();
+
+    let repeats = match count.parse::<u32>() {
+        Ok(0) => return Err(DiceError::UnexpectedToken { position }),
+        Ok(repeats) if repeats <= limits.max_repeats => repeats,
+        _ => return Err(DiceError::TooManyRepeats { position }),
+    };
+
+    Ok((repeats, " ".repeat(prefix_length) + &expr[prefix_length..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeat_prefixes() {
+        let limits = DiceLimits::default();
+
+        assert_eq!(
+            split_repeat("6x 4d6dl1", &limits).unwrap(),
+            (6, "   4d6dl1".to_string())
+        );
+        assert_eq!(
+            split_repeat(" 3#1d20+5", &limits).unwrap(),
+            (3, "   1d20+5".to_string())
+        );
+        assert_eq!(
+            split_repeat("2 X (1d4)", &limits).unwrap(),
+            (2, "    (1d4)".to_string())
+        );
+        assert_eq!(
+            split_repeat("4d6dl1", &limits).unwrap(),
+            (1, "4d6dl1".to_string())
+        );
+        assert_eq!(
+            split_repeat("3#atk", &limits).unwrap(),
+            (1, "3#atk".to_string())
+        );
+        assert_eq!(
+            split_repeat(" 0x 1d20", &limits),
+            Err(DiceError::UnexpectedToken { position: 1 })
+        );
+        assert_eq!(
+            split_repeat("1000x 1d20", &limits),
+            Err(DiceError::TooManyRepeats { position: 0 })
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use super::{
+    ast::{Comparison, Dice, Explosion, Expr, Faces},
+    parser::parse_expression,
+};
+use crate::{Roll, RollSingle};
+
+/// How a single die from a dice term gets stored. Fate dice are stored as three-sided dice that
+/// land on -1, 0 or 1.
+struct DieShape {
+    faces: i64,
+    highest: i64,
+    is_fate: bool,
+}
+
+impl DieShape {
+    fn from_dice(dice: &Dice) -> Option<Self> {
+        match &dice.faces {
+            Faces::Numbered(faces) => {
+                let faces = faces.constant_value()?.round() as i64;
+                Some(Self {
+                    faces,
+                    highest: faces,
+                    is_fate: false,
+                })
+            }
+            Faces::Fate => Some(Self {
+                faces: 3,
+                highest: 1,
+                is_fate: true,
+            }),
+        }
+    }
+
+    fn single_roll(&self, outcome: i64) -> RollSingle {
+        RollSingle {
+            faces: self.faces,
+            outcome,
+            is_rerolled: false,
+            is_fate: self.is_fate,
+            is_exploded: false,
+            is_dropped: false,
+        }
+    }
+
+    fn take_outcome(&self, results: &mut impl Iterator<Item = i64>) -> Option<RollSingle> {
+        Some(self.single_roll(results.next()?))
+    }
+
+    /// Takes the next die for a term, marking any results its reroll modifiers threw away.
+    fn take_rerolled_outcome(
+        &self,
+        dice: &Dice,
+        results: &mut impl Iterator<Item = i64>,
+        single_rolls: &mut Vec<RollSingle>,
+    ) -> Option<RollSingle> {
+        let mut single_roll = self.take_outcome(results)?;
+        let mut num_rerolls = 0;
+        while dice.should_reroll(single_roll.outcome, num_rerolls) {
+            single_roll.is_rerolled = true;
+            single_rolls.push(single_roll);
+            single_roll = self.take_outcome(results)?;
+            num_rerolls += 1;
+        }
+
+        Some(single_roll)
+    }
+
+    /// Splits a compounded total back into the individual dice that made it up. A single die
+    /// can't roll higher than its highest face, so anything above that must have exploded.
+    fn split_compounded(&self, total: i64) -> Vec<RollSingle> {
+        let mut single_rolls: Vec<RollSingle> = vec![];
+        let mut remaining = total;
+        while remaining > self.highest && self.highest > 0 {
+            let mut single_roll = self.single_roll(self.highest);
+            single_roll.is_exploded = true;
+            single_rolls.push(single_roll);
+            remaining -= self.highest;
+        }
+        single_rolls.push(self.single_roll(remaining));
+
+        single_rolls
+    }
+}
+
+fn replay_single_rolls(
+    parsed: &Expr,
+    results: &mut impl Iterator<Item = i64>,
+) -> Option<Vec<RollSingle>> {
+    let mut single_rolls: Vec<RollSingle> = vec![];
+
+    for dice in parsed.dice() {
+        let count = match &dice.count {
+            Some(count) => count.constant_value()?.abs().round() as u32,
+            None => 1,
+        };
+        let shape = DieShape::from_dice(dice)?;
+        let explosion = dice.explosion();
+
+        for _ in 0..count {
+            let single_roll = shape.take_rerolled_outcome(dice, results, &mut single_rolls)?;
+            let Some((explosion, threshold)) = explosion else {
+                single_rolls.push(single_roll);
+                continue;
+            };
+
+            let threshold = threshold.unwrap_or(Comparison::AtLeast(shape.highest));
+            match explosion {
+                Explosion::Explode => {
+                    let mut single_roll = single_roll;
+                    while threshold.matches(single_roll.outcome) {
+                        single_roll.is_exploded = true;
+                        single_rolls.push(single_roll);
+                        single_roll = shape.take_outcome(results)?;
+                    }
+                    single_rolls.push(single_roll);
+                }
+                Explosion::Penetrate => {
+                    let mut single_roll = single_roll;
+                    while threshold.matches(single_roll.outcome) {
+                        single_roll.is_exploded = true;
+                        single_rolls.push(single_roll);
+                        single_roll = shape.take_outcome(results)?;
+                        single_roll.outcome += 1;
+                    }
+                    single_rolls.push(single_roll);
+                }
+                Explosion::Compound => {
+                    single_rolls.extend(shape.split_compounded(single_roll.outcome));
+                }
+            }
+        }
+    }
+
+    Some(single_rolls)
+}
+
+/// Fallback for formulas the dice parser doesn't understand: pairs every `NdM` it can find in the
+/// raw text with the next `N` outcomes.
+fn scan_single_rolls(
+    expr: &str,
+    results: &mut impl Iterator<Item = i64>,
+) -> Option<Vec<RollSingle>> {
+    let mut single_rolls: Vec<RollSingle> = vec![];
+
+    let mut num_dice_string = String::from("");
+    let mut parsing_value = String::from("");
+    for symbol in expr.chars() {
+        if symbol == 'd' {
+            num_dice_string.drain(..);
+            let val_to_push = if !parsing_value.is_empty() {
+                parsing_value.as_str()
+            } else {
+                "1"
+            };
+            num_dice_string.push_str(val_to_push);
+            parsing_value.drain(..);
+            continue;
+        }
+
+        if symbol.to_digit(10).is_none() {
+            if !num_dice_string.is_empty() {
+                let num_dice = num_dice_string.parse::<i64>().ok()?;
+                if let Ok(faces) = parsing_value.parse::<i64>() {
+                    for _ in 0..num_dice {
+                        let outcome = results.next()?;
+                        single_rolls.push(RollSingle {
+                            faces,
+                            outcome,
+                            is_rerolled: false,
+                            is_fate: false,
+                            is_exploded: false,
+                            is_dropped: false,
+                        });
+                    }
+                }
+
+                num_dice_string.drain(..);
+            }
+
+            parsing_value.drain(..);
+        } else {
+            parsing_value.push(symbol);
+        }
+    }
+
+    if !num_dice_string.is_empty() {
+        let num_dice = num_dice_string.parse::<i64>().ok()?;
+        let faces = parsing_value.parse::<i64>().ok()?;
+
+        for _ in 0..num_dice {
+            let outcome = results.next()?;
+            single_rolls.push(RollSingle {
+                faces,
+                outcome,
+                is_rerolled: false,
+                is_fate: false,
+                is_exploded: false,
+                is_dropped: false,
+            });
+        }
+
+        num_dice_string.drain(..);
+    }
+
+    Some(single_rolls)
+}
+
+/// Rebuilds a `Roll` from a formula and the dice outcomes a VTT displayed for it, in order.
+/// Exploded dice are recorded as their own `RollSingle`s, with penetrating dice restored to what
+/// was actually rolled and compounded totals split back into individual dice. Dice thrown away by
+/// `r`/`ro` are kept too, but flagged with `is_rerolled`.
+pub fn get_roll_from_expression_and_outcomes(
+    expr: &str,
+    outcomes: Vec<i64>,
+    expr_outcome: f64,
+) -> Option<Roll> {
+    let mut results = outcomes.into_iter();
+    let single_rolls = match parse_expression(expr) {
+        Ok(parsed) => replay_single_rolls(&parsed, &mut results)?,
+        Err(_) => scan_single_rolls(expr, &mut results)?,
+    };
+
+    let roll = Roll {
+        formula: expr.to_string(),
+        outcome: expr_outcome,
+        single_rolls,
+    };
+
+    Some(roll)
+}
+
+/// The dice in a term the way a VTT would show them, which is the other way around from
+/// `get_roll_from_expression_and_outcomes`: penetrating dice lose the 1 they were penalised, and
+/// compounded dice are added back up into the die that exploded.
+pub fn displayed_single_rolls(term: &Roll) -> Vec<RollSingle> {
+    let explosion = parse_expression(&term.formula)
+        .ok()
+        .and_then(|parsed| parsed.dice().last()?.explosion())
+        .map(|(explosion, _)| explosion);
+
+    let mut displayed: Vec<RollSingle> = vec![];
+    let mut follows_explosion = false;
+    for single_roll in &term.single_rolls {
+        let mut single_roll = single_roll.clone();
+        match explosion {
+            Some(Explosion::Compound) if follows_explosion => {
+                if let Some(compounded) = displayed.last_mut() {
+                    compounded.outcome += single_roll.outcome;
+                }
+                follows_explosion = single_roll.is_exploded;
+                continue;
+            }
+            Some(Explosion::Penetrate) if follows_explosion => single_roll.outcome -= 1,
+            _ => (),
+        }
+
+        follows_explosion = single_roll.is_exploded;
+        displayed.push(single_roll);
+    }
+
+    displayed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_rolls_from_expr_and_results() {
+        let roll = get_roll_from_expression_and_outcomes("d20", vec![10], 10.).unwrap();
+        assert_eq!(roll.single_rolls.len(), 1);
+        assert_eq!(roll.single_rolls[0].faces, 20);
+        assert_eq!(roll.single_rolls[0].outcome, 10);
+        assert_eq!(roll.formula, "d20");
+        assert_eq!(roll.outcome, 10.);
+
+        let roll =
+            get_roll_from_expression_and_outcomes("2d20+(15+0) + 3d6", vec![10, 15, 3, 4, 5], 52.)
+                .unwrap();
+
+        assert_eq!(roll.single_rolls.len(), 5);
+        assert_eq!(roll.single_rolls[0].faces, 20);
+        assert_eq!(roll.single_rolls[0].outcome, 10);
+        assert_eq!(roll.single_rolls[1].outcome, 15);
+        assert_eq!(roll.single_rolls[2].outcome, 3);
+        assert_eq!(roll.single_rolls[3].outcome, 4);
+        assert_eq!(roll.single_rolls[4].outcome, 5);
+        assert_eq!(roll.formula, "2d20+(15+0) + 3d6");
+        assert_eq!(roll.outcome, 52.);
+
+        let roll = get_roll_from_expression_and_outcomes("4d6k3", vec![3, 1, 1, 3], 7.).unwrap();
+        assert_eq!(roll.single_rolls.len(), 4);
+        assert_eq!(roll.single_rolls[0].faces, 6);
+        assert_eq!(roll.single_rolls[0].outcome, 3);
+        assert_eq!(roll.single_rolls[1].outcome, 1);
+        assert_eq!(roll.single_rolls[2].outcome, 1);
+        assert_eq!(roll.single_rolls[3].outcome, 3);
+        assert_eq!(roll.formula, "4d6k3");
+        assert_eq!(roll.outcome, 7.);
+    }
+
+    #[test]
+    fn get_exploded_rolls_from_expr_and_results() {
+        let outcomes = |roll: Roll| -> Vec<i64> {
+            roll.single_rolls
+                .iter()
+                .map(|single_roll| single_roll.outcome)
+                .collect()
+        };
+
+        let roll = get_roll_from_expression_and_outcomes("3d6!", vec![6, 6, 2, 4, 1], 19.).unwrap();
+        let exploded: Vec<bool> = roll
+            .single_rolls
+            .iter()
+            .map(|single_roll| single_roll.is_exploded)
+            .collect();
+        assert_eq!(exploded, vec![true, true, false, false, false]);
+        assert_eq!(outcomes(roll), vec![6, 6, 2, 4, 1]);
+
+        let roll = get_roll_from_expression_and_outcomes("2d6!>5 + 1", vec![5, 3, 2], 11.).unwrap();
+        assert_eq!(outcomes(roll), vec![5, 3, 2]);
+        assert!(get_roll_from_expression_and_outcomes("2d6!", vec![6, 3], 9.).is_none());
+
+        let roll = get_roll_from_expression_and_outcomes("2d6!p", vec![6, 5, 2, 3], 16.).unwrap();
+        assert_eq!(outcomes(roll), vec![6, 6, 3, 3]);
+
+        let roll = get_roll_from_expression_and_outcomes("2d6!!", vec![15, 4], 19.).unwrap();
+        assert_eq!(roll.single_rolls.len(), 4);
+        assert!(roll
+            .single_rolls
+            .iter()
+            .all(|single_roll| single_roll.faces == 6));
+        assert_eq!(outcomes(roll), vec![6, 6, 3, 4]);
+    }
+
+    #[test]
+    fn get_rerolled_rolls_from_expr_and_results() {
+        let rerolled = |roll: &Roll| -> Vec<bool> {
+            roll.single_rolls
+                .iter()
+                .map(|single_roll| single_roll.is_rerolled)
+                .collect()
+        };
+
+        let roll = get_roll_from_expression_and_outcomes("2d6ro<2", vec![2, 1, 5], 6.).unwrap();
+        assert_eq!(roll.single_rolls.len(), 3);
+        assert_eq!(rerolled(&roll), vec![true, false, false]);
+
+        let roll = get_roll_from_expression_and_outcomes("1d6r<2", vec![1, 2, 4], 4.).unwrap();
+        assert_eq!(rerolled(&roll), vec![true, true, false]);
+
+        let roll = get_roll_from_expression_and_outcomes("1d6r1!", vec![1, 6, 3], 9.).unwrap();
+        assert_eq!(rerolled(&roll), vec![true, false, false]);
+        assert_eq!(roll.single_rolls[2].outcome, 3);
+    }
+
+    #[test]
+    fn get_fate_rolls_from_expr_and_results() {
+        let roll = get_roll_from_expression_and_outcomes("4dF+2", vec![1, -1, 0, 1], 3.).unwrap();
+        assert_eq!(roll.single_rolls.len(), 4);
+        assert!(roll
+            .single_rolls
+            .iter()
+            .all(|single_roll| single_roll.is_fate && single_roll.faces == 3));
+        assert_eq!(roll.single_rolls[1].outcome, -1);
+    }
+
+    #[test]
+    fn get_rolls_from_unparseable_expr() {
+        let roll =
+            get_roll_from_expression_and_outcomes("1d20+@{strength}", vec![12], 15.).unwrap();
+        assert_eq!(roll.single_rolls.len(), 1);
+        assert_eq!(roll.single_rolls[0].outcome, 12);
+    }
+
+    #[test]
+    fn display_exploded_rolls() {
+        let displayed = |expr: &str, outcomes: Vec<i64>, expr_outcome: f64| -> Vec<i64> {
+            let roll = get_roll_from_expression_and_outcomes(expr, outcomes, expr_outcome).unwrap();
+            displayed_single_rolls(&roll)
+                .iter()
+                .map(|single_roll| single_roll.outcome)
+                .collect()
+        };
+
+        assert_eq!(
+            displayed("3d6!", vec![6, 6, 2, 4, 1], 19.),
+            vec![6, 6, 2, 4, 1]
+        );
+        assert_eq!(displayed("2d6!p", vec![6, 5, 2, 3], 16.), vec![6, 5, 2, 3]);
+        assert_eq!(displayed("2d6!!", vec![15, 4], 19.), vec![15, 4]);
+        assert_eq!(displayed("4d6k3", vec![3, 1, 1, 3], 7.), vec![3, 1, 1, 3]);
+    }
+}
@@ -0,0 +1,353 @@
+use crate::{
+    get_roll_from_expression_and_outcomes, split_repeat, ChatLog, DiceLimits, ParseError, Post,
+    Roll, Visibility,
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use sqlx::types::chrono::DateTime;
+use std::{collections::HashMap, iter::Enumerate, vec::IntoIter};
+use tokio::{fs::File, io::AsyncReadExt};
+
+#[derive(Deserialize)]
+struct AuthorRaw {
+    name: String,
+    #[serde(default, rename = "isBot")]
+    is_bot: bool,
+}
+
+#[derive(Deserialize)]
+struct InteractionRaw {
+    id: String,
+    user: AuthorRaw,
+}
+
+#[derive(Deserialize)]
+struct ReferenceRaw {
+    #[serde(rename = "messageId")]
+    message_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MessageRaw {
+    id: String,
+    #[serde(rename = "type")]
+    message_type: String,
+    timestamp: String,
+    content: String,
+    author: AuthorRaw,
+    interaction: Option<InteractionRaw>,
+    reference: Option<ReferenceRaw>,
+}
+
+#[derive(Deserialize)]
+struct ExportRaw {
+    messages: Vec<MessageRaw>,
+}
+
+/// Whether a message is a prefix command for the bot, like `.r 1d20`, rather than chat.
+fn is_bot_command(content: &str) -> bool {
+    let mut chars = content.chars();
+    chars.next() == Some('.') && chars.next().is_some_and(|ch| ch.is_ascii_alphabetic())
+}
+
+/// Reads a die the way `/roll` prints it, like `4`, `~~1~~`, `6!` or `+`, returning its outcome
+/// and whether it was struck out, which dropped and rerolled dice both are.
+fn parse_displayed_die(text: &str) -> Option<(i64, bool)> {
+    let text = text.trim();
+    let (text, is_struck_out) = match text.strip_prefix("~~").and_then(|t| t.strip_suffix("~~")) {
+        Some(struck_out) => (struck_out, true),
+        None => (text, false),
+    };
+
+    let outcome = match text.trim_end_matches('!') {
+        "+" => 1,
+        "-" => -1,
+        number => number.parse().ok()?,
+    };
+
+    Some((outcome, is_struck_out))
+}
+
+/// Rebuilds one dice term from its formula and the dice `/roll` printed for it, like `4d6dl1`
+/// and `3, ~~1~~, 4, 5`.
+fn parse_displayed_term(formula: &str, dice: &str, subtotal: f64) -> Option<Roll> {
+    let displayed = dice
+        .split(", ")
+        .map(parse_displayed_die)
+        .collect::<Option<Vec<(i64, bool)>>>()?;
+    let outcomes = displayed.iter().map(|(outcome, _)| *outcome).collect();
+    let mut term = get_roll_from_expression_and_outcomes(formula, outcomes, subtotal)?;
+
+    if term.single_rolls.len() == displayed.len() {
+        for (single_roll, (_, is_struck_out)) in term.single_rolls.iter_mut().zip(displayed) {
+            single_roll.is_dropped = is_struck_out && !single_roll.is_rerolled;
+        }
+    }
+
+    Some(term)
+}
+
+/// Reads the number out of a total like `` `12` `` or `` `3` successes ``.
+fn parse_displayed_total(text: &str) -> Option<f64> {
+    text.strip_prefix('`')?.split('`').next()?.parse().ok()
+}
+
+/// Reads one line of a repeated roll, like ``1. `4d6dl1` [3, ~~1~~, 4, 5] = `12` ``.
+fn parse_repeated_line(formula: &str, line: &str) -> Option<Roll> {
+    let (number, rest) = line.split_once(". ")?;
+    number.parse::<u32>().ok()?;
+
+    let (terms, total) = rest.rsplit_once(" = ").unwrap_or(("", rest));
+    let mut single_rolls = vec![];
+    for term in terms.split("], ").filter(|term| !term.is_empty()) {
+        let (term_formula, dice) = term.strip_prefix('`')?.split_once("` [")?;
+        let term = parse_displayed_term(term_formula, dice.trim_end_matches(']'), 0.)?;
+        single_rolls.extend(term.single_rolls);
+    }
+
+    Some(Roll {
+        formula: formula.to_string(),
+        outcome: parse_displayed_total(total)?,
+        single_rolls,
+    })
+}
+
+/// Reads the rolls back out of a `/roll` reply, in any of the formats the bot has used.
+fn parse_roll_reply(content: &str) -> Option<Vec<Roll>> {
+    let mut lines = content.lines();
+    let header = lines
+        .next()?
+        .strip_prefix("Result for `")?
+        .strip_suffix(':')?;
+    let formula = match header.split_once("` (`") {
+        Some((_, expanded)) => expanded.strip_suffix("`)")?,
+        None => header.strip_suffix('`')?,
+    };
+    let formula = match split_repeat(formula, &DiceLimits::default()) {
+        Ok((_, formula)) => formula.trim().to_string(),
+        Err(_) => formula.to_string(),
+    };
+
+    let body: Vec<&str> = lines.collect();
+    let first_line = *body.first()?;
+
+    // Before it showed each die, the bot only replied with the total.
+    if let (1, Some(outcome)) = (body.len(), parse_displayed_total(first_line)) {
+        return Some(vec![Roll {
+            formula,
+            outcome,
+            single_rolls: vec![],
+        }]);
+    }
+
+    if first_line.starts_with(|ch: char| ch.is_ascii_digit()) {
+        return body
+            .iter()
+            .map(|line| parse_repeated_line(&formula, line))
+            .collect();
+    }
+
+    let mut single_rolls = vec![];
+    for line in body {
+        if let Some(total) = line.strip_prefix("Total: ") {
+            return Some(vec![Roll {
+                formula,
+                outcome: parse_displayed_total(total)?,
+                single_rolls,
+            }]);
+        }
+
+        let (term_formula, term) = line.strip_prefix('`')?.split_once("`: [")?;
+        let (dice, subtotal) = term.rsplit_once("] = ")?;
+        let term = parse_displayed_term(term_formula, dice, parse_displayed_total(subtotal)?)?;
+        single_rolls.extend(term.single_rolls);
+    }
+
+    None
+}
+
+/// Reads a Discord channel exported as JSON by DiscordChatExporter. Messages from players become
+/// chat messages, and the bot's replies to `/roll` become rolls made by whoever asked for them.
+pub struct DiscordChatLog {
+    messages: Enumerate<IntoIter<MessageRaw>>,
+    /// Why the export couldn't be read, if it couldn't, which is then all the log has to give.
+    error: Option<ParseError>,
+    /// Who sent each message so far, to work out who a bot reply was for.
+    authors: HashMap<String, String>,
+    last_player: Option<String>,
+    /// The id of the last message if it was a prefix command, like `.r 1d20`.
+    last_command: Option<String>,
+}
+
+impl DiscordChatLog {
+    /// Who a `/roll` reply from the bot was for: whoever ran the slash command, whoever sent the
+    /// message it replied to, or failing that whoever spoke last.
+    fn roller(&self, message: &MessageRaw) -> Option<String> {
+        if let Some(interaction) = &message.interaction {
+            return Some(interaction.user.name.clone());
+        }
+
+        message
+            .reference
+            .as_ref()
+            .and_then(|reference| self.authors.get(reference.message_id.as_ref()?))
+            .or(self.last_player.as_ref())
+            .cloned()
+    }
+
+    /// The id of the command a bot reply answered, which is what the bot records rolls made
+    /// through it under, so importing the channel doesn't count them twice. Replies that can't be
+    /// traced back to a command keep their own id.
+    fn command_id(message: &MessageRaw, last_command: Option<String>) -> String {
+        if let Some(interaction) = &message.interaction {
+            return interaction.id.clone();
+        }
+
+        message
+            .reference
+            .as_ref()
+            .and_then(|reference| reference.message_id.clone())
+            .or(last_command)
+            .unwrap_or_else(|| message.id.clone())
+    }
+}
+
+#[async_trait]
+impl ChatLog for DiscordChatLog {
+    async fn new(mut file: File, _: Option<i32>) -> Self {
+        let mut export_json = String::new();
+        let export = match file.read_to_string(&mut export_json).await {
+            Ok(_) => serde_json::from_str::<ExportRaw>(&export_json)
+                .map_err(|error| ParseError::malformed(error.line(), error)),
+            Err(error) => Err(ParseError::Io { line: 0, error }),
+        };
+        let (messages, error) = match export {
+            Ok(export) => (export.messages, None),
+            Err(error) => (vec![], Some(error)),
+        };
+
+        DiscordChatLog {
+            messages: messages.into_iter().enumerate(),
+            error,
+            authors: HashMap::new(),
+            last_player: None,
+            last_command: None,
+        }
+    }
+
+    async fn next_post(&mut self) -> Option<Result<Post, ParseError>> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+
+        while let Some((index, message)) = self.messages.next() {
+            if message.message_type != "Default" && message.message_type != "Reply" {
+                continue;
+            }
+            let datetime = match DateTime::parse_from_rfc3339(&message.timestamp) {
+                Ok(datetime) => datetime,
+                Err(error) => return Some(Err(ParseError::malformed(index + 1, error))),
+            };
+
+            if message.author.is_bot {
+                let last_command = self.last_command.take();
+                let Some(rolls) = parse_roll_reply(&message.content) else {
+                    continue;
+                };
+                let Some(sender_name) = self.roller(&message) else {
+                    continue;
+                };
+
+                return Some(Ok(Post {
+                    id: Self::command_id(&message, last_command),
+                    sender_name,
+                    datetime,
+                    content_raw: message.content,
+                    is_message: false,
+                    rolls,
+                    visibility: Visibility::Public,
+                }));
+            }
+
+            self.authors
+                .insert(message.id.clone(), message.author.name.clone());
+            self.last_player = Some(message.author.name.clone());
+            if is_bot_command(&message.content) {
+                self.last_command = Some(message.id);
+                continue;
+            }
+            self.last_command = None;
+            if message.content.is_empty() {
+                continue;
+            }
+
+            return Some(Ok(Post {
+                id: message.id,
+                sender_name: message.author.name,
+                datetime,
+                content_raw: message.content,
+                is_message: true,
+                rolls: vec![],
+                visibility: Visibility::Public,
+            }));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_rolls_from_reply() {
+        let rolls = parse_roll_reply("Result for `1d20 + 5`:\n`17`").unwrap();
+        assert_eq!(rolls.len(), 1);
+        assert_eq!(rolls[0].formula, "1d20 + 5");
+        assert_eq!(rolls[0].outcome, 17.);
+        assert!(rolls[0].single_rolls.is_empty());
+
+        let rolls = parse_roll_reply(
+            "Result for `#atk` (`(1d20+(3))`):\n`1d20`: [17] = `17`\nTotal: `20`\nCritical successes: `0`",
+        )
+        .unwrap();
+        assert_eq!(rolls[0].formula, "(1d20+(3))");
+        assert_eq!(rolls[0].outcome, 20.);
+        assert_eq!(rolls[0].single_rolls.len(), 1);
+        assert_eq!(rolls[0].single_rolls[0].faces, 20);
+        assert_eq!(rolls[0].single_rolls[0].outcome, 17);
+
+        let rolls = parse_roll_reply(
+            "Result for `4d6dl1 + 4dF`:\n`4d6dl1`: [3, ~~1~~, 4, 5] = `12`\n`4dF`: [+, 0, -, +] = `1`\nTotal: `13`",
+        )
+        .unwrap();
+        assert_eq!(rolls[0].single_rolls.len(), 8);
+        assert!(rolls[0].single_rolls[1].is_dropped);
+        assert!(!rolls[0].single_rolls[0].is_dropped);
+        assert!(rolls[0].single_rolls[4].is_fate);
+        assert_eq!(rolls[0].single_rolls[6].outcome, -1);
+
+        let rolls =
+            parse_roll_reply("Result for `2d6r<2`:\n`2d6r<2`: [~~1~~, 4, 5] = `9`\nTotal: `9`")
+                .unwrap();
+        assert_eq!(rolls[0].single_rolls.len(), 3);
+        assert!(rolls[0].single_rolls[0].is_rerolled);
+        assert!(!rolls[0].single_rolls[0].is_dropped);
+        assert!(!rolls[0].single_rolls[1].is_rerolled);
+
+        let rolls = parse_roll_reply(
+            "Result for `2x 2d6!`:\n1. `2d6!` [6!, 2, 3] = `11`\n2. `2d6!` [1, 4] = `5`\n",
+        )
+        .unwrap();
+        assert_eq!(rolls.len(), 2);
+        assert_eq!(rolls[0].formula, "2d6!");
+        assert_eq!(rolls[0].outcome, 11.);
+        assert_eq!(rolls[0].single_rolls.len(), 3);
+        assert!(rolls[0].single_rolls[0].is_exploded);
+        assert_eq!(rolls[1].outcome, 5.);
+
+        assert!(parse_roll_reply("Sorry, I couldn't roll that").is_none());
+        assert!(parse_roll_reply("Result for `1d20`:\nsomething else").is_none());
+    }
+}
@@ -1,13 +1,11 @@
 use std::time::Duration;
 
-use crate::{ChatLog, Post, Roll};
+use crate::{ChatLog, LogLines, LogPosition, ParseError, Post, ResumePoint, Roll, Visibility};
 use async_trait::async_trait;
 use scraper::{node::Text, ElementRef, Html, Node, Selector};
 use sqlx::types::chrono::{DateTime, FixedOffset};
-use tokio::{
-    fs::File,
-    io::{AsyncBufReadExt, BufReader, Lines},
-};
+use tokio::fs::File;
+use uuid::Uuid;
 
 const DATETIME_STRP: &'static str = "%Y-%m-%d %H:%M %z";
 const IGNORE_MESSAGES: [&'static str; 2] = ["Party taking long rest.", "Party taking short rest."];
@@ -185,12 +183,20 @@ fn sender_name_and_content_are_valid_message(sender_name: &str, content_raw: &st
     true
 }
 
+/// Fantasy Grounds doesn't give messages ids, so they're identified by a hash of who sent them,
+/// when and what they said, which stays the same however many times the log is read.
+fn message_id(sender_name: &str, datetime: &DateTime<FixedOffset>, content_raw: &str) -> String {
+    let message = format!("{}\n{sender_name}\n{content_raw}", datetime.to_rfc3339());
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, message.as_bytes())
+        .simple()
+        .to_string()
+}
+
 pub struct FantasyGroundsChatLog {
-    current_message_id: i64,
     timezone_offset: i32,
     current_message_html: String,
     last_parsed_datetime: Option<DateTime<FixedOffset>>,
-    lines: Lines<BufReader<File>>,
+    lines: LogLines,
 }
 
 impl FantasyGroundsChatLog {
@@ -202,17 +208,12 @@ impl FantasyGroundsChatLog {
         }
     }
 
-    fn increment_when_post_returned(&mut self) {
-        self.current_message_id += 1;
-
+    fn increment_when_post_returned(&mut self, prev_datetime: DateTime<FixedOffset>) {
         let increment_duration = Duration::new(60, 0);
-        let prev_datetime = self.last_parsed_datetime.expect(
-            "expected to already have parsed a datetime in fantasy grounds log, but none was found",
-        );
         self.last_parsed_datetime = Some(prev_datetime + increment_duration);
     }
 
-    fn post_from_current_message_html(&mut self) -> Option<Post> {
+    fn post_from_current_message_html(&mut self) -> Option<Result<Post, ParseError>> {
         let fragment = Html::parse_fragment(&self.current_message_html);
         self.current_message_html.drain(..);
 
@@ -248,24 +249,33 @@ impl FantasyGroundsChatLog {
             return None;
         }
 
+        let Some(datetime) = self.last_parsed_datetime else {
+            return Some(Err(ParseError::malformed(
+                self.lines.line(),
+                "message came before the session's start time",
+            )));
+        };
+        let sender_name = sender_name.trim();
+        let content_raw = content_raw.trim();
         let post = Post {
-            id: self.current_message_id.to_string(),
-            sender_name: sender_name.trim().to_string(),
-            datetime: self.last_parsed_datetime.clone().unwrap(),
-            content_raw: content_raw.trim().to_string(),
+            id: message_id(sender_name, &datetime, content_raw),
+            sender_name: sender_name.to_string(),
+            datetime,
+            content_raw: content_raw.to_string(),
             is_message,
             rolls,
+            visibility: Visibility::Public,
         };
-        self.increment_when_post_returned();
+        self.increment_when_post_returned(datetime);
 
-        Some(post)
+        Some(Ok(post))
     }
 }
 
 #[async_trait]
 impl ChatLog for FantasyGroundsChatLog {
     async fn new(file: File, timezone_offset: Option<i32>) -> Self {
-        let lines = BufReader::new(file).lines();
+        let lines = LogLines::new(file);
         let offset = if let Some(hours) = timezone_offset {
             hours
         } else {
@@ -273,7 +283,6 @@ impl ChatLog for FantasyGroundsChatLog {
         };
 
         Self {
-            current_message_id: 1,
             timezone_offset: offset,
             current_message_html: String::from(""),
             last_parsed_datetime: None,
@@ -281,8 +290,12 @@ impl ChatLog for FantasyGroundsChatLog {
         }
     }
 
-    async fn next_post(&mut self) -> Option<Post> {
-        while let Some(line) = self.lines.next_line().await.unwrap() {
+    async fn next_post(&mut self) -> Option<Result<Post, ParseError>> {
+        while let Some(line) = self.lines.next_line().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error)),
+            };
             self.current_message_html.push_str(line.as_str());
 
             if !self.current_message_html.ends_with("<br />") {
@@ -296,4 +309,17 @@ impl ChatLog for FantasyGroundsChatLog {
 
         None
     }
+
+    fn position(&self) -> Option<LogPosition> {
+        Some(self.lines.position())
+    }
+
+    async fn resume(&mut self, from: &ResumePoint) -> bool {
+        if self.lines.seek(from.position).await.is_err() {
+            return false;
+        }
+
+        self.increment_when_post_returned(from.datetime);
+        true
+    }
 }
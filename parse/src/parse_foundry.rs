@@ -1,31 +1,125 @@
-use crate::{ChatLog, Post, Roll, RollSingle};
+use crate::{
+    ChatLog, LogLines, LogPosition, ParseError, Post, ResumePoint, Roll, RollSingle, Visibility,
+};
 use async_trait::async_trait;
 use serde::Deserialize;
+use serde_json::Value;
 use sqlx::types::chrono::DateTime;
-use std::num::TryFromIntError;
-use tokio::{
-    fs::File,
-    io::{AsyncBufReadExt, BufReader, Lines},
-};
+use std::{collections::HashMap, num::TryFromIntError, path::Path, vec::IntoIter};
+use tokio::fs::File;
+
+mod leveldb;
+
+/// Foundry v11+ keys every chat message in its LevelDB store like `!messages!<id>`.
+const LEVELDB_MESSAGES_PREFIX: &[u8] = b"!messages!";
+
+/// The style of messages Foundry makes itself, like notices that someone joined.
+const STYLE_OTHER: u8 = 0;
 
 #[derive(Deserialize)]
 struct Speaker {
     alias: String,
 }
 
-#[derive(Deserialize)]
+fn is_active_by_default() -> bool {
+    true
+}
+
+#[derive(Clone, Deserialize)]
 struct RollResult {
     result: i64,
+    /// Older logs leave this out, since every result they recorded counted.
+    #[serde(default = "is_active_by_default")]
+    active: bool,
+    #[serde(default)]
+    discarded: bool,
+    #[serde(default)]
+    rerolled: bool,
+    #[serde(default)]
+    exploded: bool,
 }
 
-#[derive(Deserialize)]
+impl RollResult {
+    /// Whether a modifier like `kh` or `dl` threw this result away. Rerolled results are
+    /// inactive too, but they were replaced rather than dropped.
+    fn is_dropped(&self) -> bool {
+        self.discarded || (!self.active && !self.rerolled)
+    }
+}
+
+#[derive(Clone, Deserialize)]
 struct RollTerm {
     class: String,
     faces: Option<i64>,
-    results: Option<Vec<RollResult>>,
+    #[serde(default)]
+    results: Vec<RollResult>,
+    /// The rolls inside a `PoolTerm` like `{1d20, 1d12}kh`, in the same order as its results.
+    #[serde(default)]
+    rolls: Vec<NestedRoll>,
+    /// The roll inside a `ParentheticalTerm` like `(2d6 + 1)`.
+    roll: Option<NestedRoll>,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
+struct NestedRoll {
+    #[serde(default)]
+    terms: Vec<RollTerm>,
+}
+
+impl NestedRoll {
+    fn single_rolls(&self) -> Vec<RollSingle> {
+        self.terms.iter().flat_map(RollTerm::single_rolls).collect()
+    }
+}
+
+impl RollTerm {
+    /// Every die rolled for this term, including the dice inside pools and parentheses.
+    fn single_rolls(&self) -> Vec<RollSingle> {
+        match self.class.as_str() {
+            "PoolTerm" => self
+                .rolls
+                .iter()
+                .enumerate()
+                .flat_map(|(index, roll)| {
+                    // Dropping one of a pool's rolls drops every die in it.
+                    let is_dropped = self.results.get(index).is_some_and(RollResult::is_dropped);
+                    roll.single_rolls().into_iter().map(move |mut single_roll| {
+                        single_roll.is_dropped |= is_dropped;
+                        single_roll
+                    })
+                })
+                .collect(),
+            "ParentheticalTerm" => self
+                .roll
+                .as_ref()
+                .map(NestedRoll::single_rolls)
+                .unwrap_or_default(),
+            class => {
+                // Any other `DiceTerm` has faces, while operators, numbers and strings don't.
+                let (faces, is_fate) = match (class, self.faces) {
+                    ("FateDie", _) => (3, true),
+                    ("Coin", _) => (2, false),
+                    (_, Some(faces)) => (faces, false),
+                    _ => return vec![],
+                };
+
+                self.results
+                    .iter()
+                    .map(|res| RollSingle {
+                        faces,
+                        outcome: res.result,
+                        is_rerolled: res.rerolled,
+                        is_fate,
+                        is_exploded: res.exploded,
+                        is_dropped: res.is_dropped(),
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
 struct RollRaw {
     formula: String,
     terms: Vec<RollTerm>,
@@ -37,21 +131,7 @@ impl From<RollRaw> for Roll {
         let single_rolls: Vec<RollSingle> = roll_raw
             .terms
             .iter()
-            .filter_map(|term| match term.class.as_str() {
-                "Die" => Some(
-                    term.results
-                        .as_ref()
-                        .unwrap()
-                        .iter()
-                        .map(|res| RollSingle {
-                            faces: term.faces.unwrap(),
-                            outcome: res.result,
-                        })
-                        .collect::<Vec<_>>(),
-                ),
-                _ => None,
-            })
-            .flatten()
+            .flat_map(RollTerm::single_rolls)
             .collect();
 
         Roll {
@@ -62,6 +142,23 @@ impl From<RollRaw> for Roll {
     }
 }
 
+/// Foundry saves each roll as a string of JSON, but exported messages can have them as objects.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RollJson {
+    Encoded(String),
+    Decoded(RollRaw),
+}
+
+impl RollJson {
+    fn parse(&self) -> serde_json::Result<RollRaw> {
+        match self {
+            RollJson::Encoded(roll) => serde_json::from_str(roll),
+            RollJson::Decoded(roll) => Ok(roll.clone()),
+        }
+    }
+}
+
 impl FromIterator<RollRaw> for Vec<Roll> {
     fn from_iter<T: IntoIterator<Item = RollRaw>>(iter: T) -> Self {
         iter.into_iter()
@@ -73,13 +170,22 @@ impl FromIterator<RollRaw> for Vec<Roll> {
 #[derive(Deserialize)]
 struct PostRaw {
     _id: String,
-    #[serde(alias = "type")]
-    type_number: u8,
+    /// What kind of message this is. Up to v11 this was a number, and v12 made it a string subtype
+    /// and moved the number to `style`.
+    #[serde(rename = "type")]
+    message_type: Value,
+    style: Option<u8>,
     speaker: Speaker,
     timestamp: i64,
     content: String,
+    /// The id of whoever sent the message, which v12 renamed to `author`.
+    #[serde(alias = "author")]
+    user: Option<String>,
+    /// The ids of everyone a message was whispered to, empty if everyone could see it.
     whisper: Vec<String>,
-    rolls: Vec<String>,
+    #[serde(default)]
+    blind: bool,
+    rolls: Vec<RollJson>,
 }
 
 impl PostRaw {
@@ -87,11 +193,36 @@ impl PostRaw {
         serde_json::from_str(line)
     }
 
+    /// GM and blind rolls are whispered to the GMs, and self rolls are whispered to their sender.
+    fn visibility(&self) -> Visibility {
+        if self.blind {
+            Visibility::Blind
+        } else if self.whisper.is_empty() {
+            Visibility::Public
+        } else if self.whisper.iter().all(|id| Some(id) == self.user.as_ref()) {
+            Visibility::SelfOnly
+        } else {
+            Visibility::Whisper
+        }
+    }
+
+    /// Whether a message was in or out of character, an emote, or made by Foundry itself.
+    fn style(&self) -> u8 {
+        self.style
+            .or_else(|| u8::try_from(self.message_type.as_u64()?).ok())
+            .unwrap_or(STYLE_OTHER)
+    }
+
+    /// Messages Foundry makes itself are skipped, except for rolls, which v12 gives that style.
+    fn is_from_foundry(&self) -> bool {
+        self.style() == STYLE_OTHER && !self.contains_rolls()
+    }
+
     fn contains_rolls(&self) -> bool {
         self.rolls.len() > 0
     }
 
-    fn parse_rolls(&self) -> Vec<Roll> {
+    fn parse_rolls(&self) -> serde_json::Result<Vec<Roll>> {
         if !self.contains_rolls() {
             panic!(
                 "post with id {} doesn't contain any rolls, but parsing rolls was attempted",
@@ -101,27 +232,30 @@ impl PostRaw {
 
         self.rolls
             .iter()
-            .map(|roll| serde_json::from_str::<RollRaw>(roll).unwrap())
+            .map(|roll| roll.parse().map(Roll::from))
             .collect()
     }
 }
 
-impl From<PostRaw> for Post {
-    fn from(foundry_post: PostRaw) -> Self {
+impl TryFrom<PostRaw> for Post {
+    type Error = serde_json::Error;
+
+    fn try_from(foundry_post: PostRaw) -> serde_json::Result<Self> {
         let timestamp_s = foundry_post.timestamp / 1000;
         let timestamp_ns: Result<u32, TryFromIntError> =
             ((foundry_post.timestamp as i64 % 1000) * 1_000_000).try_into();
         let timestamp_ns = timestamp_ns.unwrap();
 
         let is_message = !foundry_post.contains_rolls();
+        let visibility = foundry_post.visibility();
 
         let rolls: Vec<Roll> = if foundry_post.contains_rolls() {
-            foundry_post.parse_rolls()
+            foundry_post.parse_rolls()?
         } else {
             vec![]
         };
 
-        Self {
+        Ok(Self {
             id: foundry_post._id,
             sender_name: foundry_post.speaker.alias,
             datetime: DateTime::from_timestamp(timestamp_s, timestamp_ns)
@@ -130,36 +264,252 @@ impl From<PostRaw> for Post {
             is_message,
             content_raw: foundry_post.content,
             rolls,
-        }
+            visibility,
+        })
     }
 }
 
+/// Whether a line of a NeDB file is one of NeDB's own records, like a marker for a deleted
+/// document, rather than a message.
+fn is_nedb_record(line: &str) -> bool {
+    serde_json::from_str::<HashMap<String, Value>>(line)
+        .is_ok_and(|document| document.keys().any(|key| key.starts_with("$$")))
+}
+
+enum MessageSource {
+    /// A v10 or older `messages.db`, with one NeDB document per line.
+    NeDb(LogLines),
+    /// The messages of a v11+ LevelDB store, read up front and sorted by when they were sent,
+    /// along with how many have been read. Anything in the store that couldn't be read comes
+    /// first, as errors.
+    LevelDb {
+        errors: IntoIter<ParseError>,
+        posts: IntoIter<PostRaw>,
+        read: usize,
+    },
+}
+
 pub struct FoundryChatLog {
-    lines: Lines<BufReader<File>>,
+    messages: MessageSource,
+}
+
+impl FoundryChatLog {
+    /// Reads the LevelDB `messages` directory Foundry v11 and newer keep a world's chat in. Files
+    /// in it that can't be read aren't any one message, so they're reported at line 0.
+    pub async fn from_leveldb(directory: &Path) -> Self {
+        let (values, store_errors) =
+            match leveldb::read_values(directory, LEVELDB_MESSAGES_PREFIX).await {
+                Ok(read) => read,
+                Err(error) => (vec![], vec![error]),
+            };
+        let mut errors: Vec<ParseError> = store_errors
+            .into_iter()
+            .map(|error| ParseError::Io { line: 0, error })
+            .collect();
+
+        let mut posts: Vec<PostRaw> = vec![];
+        for (index, value) in values.iter().enumerate() {
+            match serde_json::from_slice(value) {
+                Ok(post) => posts.push(post),
+                Err(error) => errors.push(ParseError::malformed(index + 1, error)),
+            }
+        }
+        posts.sort_by_key(|post| post.timestamp);
+
+        FoundryChatLog {
+            messages: MessageSource::LevelDb {
+                errors: errors.into_iter(),
+                posts: posts.into_iter(),
+                read: 0,
+            },
+        }
+    }
 }
 
 #[async_trait]
 impl ChatLog for FoundryChatLog {
     async fn new(file: File, _: Option<i32>) -> Self {
-        let lines = BufReader::new(file).lines();
-
-        FoundryChatLog { lines }
+        FoundryChatLog {
+            messages: MessageSource::NeDb(LogLines::new(file)),
+        }
     }
 
-    async fn next_post(&mut self) -> Option<Post> {
-        while let Some(line) = self.lines.next_line().await.unwrap() {
-            let post = match PostRaw::parse(&line) {
-                Ok(res) => res,
-                Err(_) => continue,
+    async fn next_post(&mut self) -> Option<Result<Post, ParseError>> {
+        loop {
+            let (post, line) = match &mut self.messages {
+                MessageSource::NeDb(lines) => {
+                    let line = match lines.next_line().await? {
+                        Ok(line) => line,
+                        Err(error) => return Some(Err(error)),
+                    };
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match PostRaw::parse(&line) {
+                        Ok(post) => (post, lines.line()),
+                        Err(_) if is_nedb_record(&line) => continue,
+                        Err(error) => return Some(Err(ParseError::malformed(lines.line(), error))),
+                    }
+                }
+                MessageSource::LevelDb {
+                    errors,
+                    posts,
+                    read,
+                } => {
+                    if let Some(error) = errors.next() {
+                        return Some(Err(error));
+                    }
+                    *read += 1;
+                    (posts.next()?, *read)
+                }
             };
 
-            if post.type_number == 0 || post.whisper.len() > 0 {
+            if post.is_from_foundry() {
                 continue;
             }
 
-            return Some(post.into());
+            return Some(Post::try_from(post).map_err(|error| ParseError::malformed(line, error)));
         }
+    }
+
+    fn position(&self) -> Option<LogPosition> {
+        match &self.messages {
+            MessageSource::NeDb(lines) => Some(lines.position()),
+            MessageSource::LevelDb { .. } => None,
+        }
+    }
+
+    async fn resume(&mut self, from: &ResumePoint) -> bool {
+        match &mut self.messages {
+            MessageSource::NeDb(lines) => lines.seek(from.position).await.is_ok(),
+            MessageSource::LevelDb { .. } => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODERN_ROLL: &str = r#"{
+        "class": "Roll",
+        "formula": "{1d20, 1d12}kh + (2d6x + 1) + 1dc + 1dF",
+        "total": 28,
+        "terms": [
+            {
+                "class": "PoolTerm",
+                "terms": ["1d20", "1d12"],
+                "modifiers": ["kh"],
+                "rolls": [
+                    {"formula": "1d20", "total": 15, "terms": [
+                        {"class": "Die", "faces": 20, "results": [{"result": 15, "active": true}]}
+                    ]},
+                    {"formula": "1d12", "total": 8, "terms": [
+                        {"class": "Die", "faces": 12, "results": [{"result": 8, "active": true}]}
+                    ]}
+                ],
+                "results": [
+                    {"result": 15, "active": true},
+                    {"result": 8, "active": false, "discarded": true}
+                ]
+            },
+            {"class": "OperatorTerm", "operator": "+"},
+            {
+                "class": "ParentheticalTerm",
+                "term": "2d6x + 1",
+                "roll": {"formula": "2d6x + 1", "total": 13, "terms": [
+                    {"class": "Die", "faces": 6, "modifiers": ["x"], "results": [
+                        {"result": 6, "active": true, "exploded": true},
+                        {"result": 2, "active": true},
+                        {"result": 4, "active": true}
+                    ]},
+                    {"class": "OperatorTerm", "operator": "+"},
+                    {"class": "NumericTerm", "number": 1}
+                ]}
+            },
+            {"class": "OperatorTerm", "operator": "+"},
+            {"class": "Coin", "faces": 2, "results": [{"result": 1, "active": true}]},
+            {"class": "OperatorTerm", "operator": "+"},
+            {"class": "FateDie", "faces": 3, "modifiers": ["r0"], "results": [
+                {"result": 0, "active": false, "rerolled": true},
+                {"result": -1, "active": true}
+            ]}
+        ]
+    }"#;
+
+    #[test]
+    fn modern_roll_terms() {
+        let roll: Roll = serde_json::from_str::<RollRaw>(MODERN_ROLL).unwrap().into();
+        assert_eq!(roll.outcome, 28.);
+
+        let single_rolls = roll.single_rolls;
+        let faces: Vec<i64> = single_rolls.iter().map(|single| single.faces).collect();
+        assert_eq!(faces, [20, 12, 6, 6, 6, 2, 3, 3]);
+
+        assert!(!single_rolls[0].is_dropped);
+        assert!(single_rolls[1].is_dropped);
+        assert!(single_rolls[2].is_exploded);
+        assert!(!single_rolls[3].is_exploded);
+        assert_eq!(single_rolls[5].outcome, 1);
+        assert!(single_rolls[6].is_fate && single_rolls[6].is_rerolled);
+        assert!(!single_rolls[6].is_dropped);
+        assert_eq!(single_rolls[7].outcome, -1);
+        assert!(!single_rolls[7].is_rerolled);
+    }
+
+    #[test]
+    fn encoded_and_decoded_rolls() {
+        let post = |rolls: String| {
+            format!(
+                r#"{{"_id": "a", "type": 5, "speaker": {{"alias": "Bob"}}, "timestamp": 0,
+                    "content": "28", "whisper": [], "rolls": [{rolls}]}}"#
+            )
+        };
+
+        let encoded = serde_json::to_string(MODERN_ROLL).unwrap();
+        for line in [post(encoded), post(MODERN_ROLL.to_string())] {
+            let post = Post::try_from(PostRaw::parse(&line).unwrap()).unwrap();
+            assert_eq!(post.rolls.len(), 1);
+            assert_eq!(post.rolls[0].single_rolls.len(), 8);
+        }
+    }
+
+    #[test]
+    fn post_visibility() {
+        let visibility = |whisper: &str, blind: bool| {
+            let line = format!(
+                r#"{{"_id": "a", "type": 5, "user": "player", "speaker": {{"alias": "Bob"}},
+                    "timestamp": 0, "content": "", "whisper": [{whisper}], "blind": {blind},
+                    "rolls": []}}"#
+            );
+            PostRaw::parse(&line).unwrap().visibility()
+        };
+
+        assert_eq!(visibility("", false), Visibility::Public);
+        assert_eq!(visibility(r#""gm""#, false), Visibility::Whisper);
+        assert_eq!(visibility(r#""gm", "player""#, false), Visibility::Whisper);
+        assert_eq!(visibility(r#""gm""#, true), Visibility::Blind);
+        assert_eq!(visibility(r#""player""#, false), Visibility::SelfOnly);
+    }
+
+    #[test]
+    fn message_styles() {
+        let post = |message_type: &str, rolls: &str| {
+            let line = format!(
+                r#"{{"_id": "a", {message_type}, "speaker": {{"alias": "Bob"}}, "timestamp": 0,
+                    "content": "", "whisper": [], "rolls": [{rolls}]}}"#
+            );
+            PostRaw::parse(&line).unwrap()
+        };
+
+        assert_eq!(post(r#""type": 2"#, "").style(), 2);
+        assert_eq!(post(r#""type": "base", "style": 1"#, "").style(), 1);
+        assert_eq!(post(r#""type": "base""#, "").style(), STYLE_OTHER);
+        assert!(post(r#""type": 0"#, "").is_from_foundry());
+        let roll = serde_json::to_string(MODERN_ROLL).unwrap();
+        assert!(!post(r#""type": "base", "style": 0"#, &roll).is_from_foundry());
 
-        None
+        assert!(is_nedb_record(r#"{"$$deleted": true, "_id": "a"}"#));
+        assert!(!is_nedb_record(r#"{"_id": "a", "type": "base"}"#));
     }
 }
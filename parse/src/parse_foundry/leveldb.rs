@@ -0,0 +1,560 @@
+//! Just enough of LevelDB to read a Foundry v11+ world's `messages` store: the sorted tables
+//! (`.ldb`) and the write-ahead log (`.log`), keeping the newest version of every key.
+
+use std::{collections::HashMap, io, path::Path};
+use tokio::fs;
+
+const TABLE_MAGIC: u64 = 0xdb4775248b80fb57;
+const FOOTER_LENGTH: usize = 48;
+const LOG_BLOCK_LENGTH: usize = 32768;
+const LOG_HEADER_LENGTH: usize = 7;
+
+const NO_COMPRESSION: u8 = 0;
+const SNAPPY_COMPRESSION: u8 = 1;
+
+const TYPE_DELETION: u8 = 0;
+const TYPE_VALUE: u8 = 1;
+
+const CRC_MASK_DELTA: u32 = 0xa282ead8;
+
+/// The most a Snappy block can grow by when decompressed, since its densest element is a
+/// three-byte copy of 64 bytes.
+const MAX_SNAPPY_EXPANSION: usize = 22;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// CRC-32C, which LevelDB checksums every log record with.
+fn crc32c(data: impl IntoIterator<Item = u8>) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f63b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// LevelDB stores checksums rotated and offset, since checksumming data that has its own checksums
+/// in it works badly.
+fn mask_crc(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(CRC_MASK_DELTA)
+}
+
+/// Reads a varint from the front of `data`, advancing past it.
+fn read_varint(data: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data
+            .split_first()
+            .ok_or_else(|| invalid_data("varint runs past the end of its block"))?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(invalid_data("varint is too long"))
+}
+
+/// Splits `length` bytes off the front of `data`.
+fn take<'a>(data: &mut &'a [u8], length: usize) -> io::Result<&'a [u8]> {
+    if data.len() < length {
+        return Err(invalid_data("record runs past the end of its block"));
+    }
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+/// Reads a varint length followed by that many bytes.
+fn read_length_prefixed<'a>(data: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let length = read_varint(data)? as usize;
+    take(data, length)
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[..4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    u64::from_le_bytes(data[..8].try_into().unwrap())
+}
+
+/// Decompresses a raw (unframed) Snappy block.
+fn decompress_snappy(mut data: &[u8]) -> io::Result<Vec<u8>> {
+    let length = read_varint(&mut data)? as usize;
+    if length > data.len().saturating_mul(MAX_SNAPPY_EXPANSION) {
+        return Err(invalid_data(
+            "snappy block claims to be longer than it could decompress to",
+        ));
+    }
+    let mut output: Vec<u8> = Vec::with_capacity(length);
+
+    while let Some((&tag, rest)) = data.split_first() {
+        data = rest;
+        let (copy_length, offset) = match tag & 3 {
+            0 => {
+                let literal_length = match tag >> 2 {
+                    short @ 0..=59 => usize::from(short) + 1,
+                    long => {
+                        let mut length_bytes = [0; 4];
+                        let num_bytes = usize::from(long - 59);
+                        length_bytes[..num_bytes].copy_from_slice(take(&mut data, num_bytes)?);
+                        u32::from_le_bytes(length_bytes) as usize + 1
+                    }
+                };
+                output.extend_from_slice(take(&mut data, literal_length)?);
+                continue;
+            }
+            1 => {
+                let low = take(&mut data, 1)?[0];
+                (
+                    usize::from((tag >> 2) & 7) + 4,
+                    usize::from(tag >> 5) << 8 | usize::from(low),
+                )
+            }
+            2 => {
+                let offset = take(&mut data, 2)?;
+                (
+                    usize::from(tag >> 2) + 1,
+                    usize::from(u16::from_le_bytes([offset[0], offset[1]])),
+                )
+            }
+            _ => (
+                usize::from(tag >> 2) + 1,
+                read_u32(take(&mut data, 4)?) as usize,
+            ),
+        };
+
+        if offset == 0 || offset > output.len() {
+            return Err(invalid_data("snappy copy points outside the output"));
+        }
+        // Copies can overlap what they're writing, so they have to go one byte at a time.
+        let start = output.len() - offset;
+        for index in start..start + copy_length {
+            output.push(output[index]);
+        }
+    }
+
+    if output.len() != length {
+        return Err(invalid_data(
+            "snappy block decompressed to the wrong length",
+        ));
+    }
+    Ok(output)
+}
+
+/// Every key and value in a table block, in order.
+fn read_block_entries(block: &[u8]) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if block.len() < 4 {
+        return Err(invalid_data("table block is too short"));
+    }
+    let num_restarts = read_u32(&block[block.len() - 4..]) as usize;
+    let entries_end = block
+        .len()
+        .checked_sub(4 + 4 * num_restarts)
+        .ok_or_else(|| invalid_data("table block has too many restarts"))?;
+
+    let mut data = &block[..entries_end];
+    let mut entries: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+    let mut key: Vec<u8> = vec![];
+    while !data.is_empty() {
+        let shared = read_varint(&mut data)? as usize;
+        let non_shared = read_varint(&mut data)? as usize;
+        let value_length = read_varint(&mut data)? as usize;
+        if shared > key.len() {
+            return Err(invalid_data("table key shares more than the previous key"));
+        }
+
+        key.truncate(shared);
+        key.extend_from_slice(take(&mut data, non_shared)?);
+        let value = take(&mut data, value_length)?;
+        entries.push((key.clone(), value.to_vec()));
+    }
+
+    Ok(entries)
+}
+
+/// Reads the block a handle in `table` points at, decompressing it if needed.
+fn read_table_block(table: &[u8], mut handle: &[u8]) -> io::Result<Vec<u8>> {
+    let offset = read_varint(&mut handle)? as usize;
+    let length = read_varint(&mut handle)? as usize;
+    let past_end = || invalid_data("table block runs past the end of the table");
+    let end = offset.checked_add(length).ok_or_else(past_end)?;
+    let block = table.get(offset..end).ok_or_else(past_end)?;
+    let compression = *table.get(end).ok_or_else(past_end)?;
+
+    match compression {
+        NO_COMPRESSION => Ok(block.to_vec()),
+        SNAPPY_COMPRESSION => decompress_snappy(block),
+        _ => Err(invalid_data("table block uses an unsupported compression")),
+    }
+}
+
+/// A value or deletion for a key, tagged with when it was written.
+struct Version {
+    sequence: u64,
+    value: Option<Vec<u8>>,
+}
+
+fn keep_newest(versions: &mut HashMap<Vec<u8>, Version>, key: Vec<u8>, version: Version) {
+    match versions.get(&key) {
+        Some(existing) if existing.sequence >= version.sequence => (),
+        _ => {
+            versions.insert(key, version);
+        }
+    }
+}
+
+fn read_table(table: &[u8], versions: &mut HashMap<Vec<u8>, Version>) -> io::Result<()> {
+    if table.len() < FOOTER_LENGTH {
+        return Err(invalid_data("table is too short"));
+    }
+    let footer = &table[table.len() - FOOTER_LENGTH..];
+    if read_u64(&footer[FOOTER_LENGTH - 8..]) != TABLE_MAGIC {
+        return Err(invalid_data("table has the wrong magic number"));
+    }
+
+    let mut handles = footer;
+    read_varint(&mut handles)?;
+    read_varint(&mut handles)?;
+    let index = read_table_block(table, handles)?;
+
+    for (_, data_handle) in read_block_entries(&index)? {
+        for (internal_key, value) in read_block_entries(&read_table_block(table, &data_handle)?)? {
+            if internal_key.len() < 8 {
+                return Err(invalid_data("table key is missing its sequence number"));
+            }
+            let (key, tag) = internal_key.split_at(internal_key.len() - 8);
+            let tag = read_u64(tag);
+            let value = match (tag & 0xff) as u8 {
+                TYPE_VALUE => Some(value),
+                TYPE_DELETION => None,
+                _ => continue,
+            };
+
+            keep_newest(
+                versions,
+                key.to_vec(),
+                Version {
+                    sequence: tag >> 8,
+                    value,
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits a write-ahead log back into the write batches it recorded, along with how many records
+/// were corrupt. A record whose checksum doesn't match, like the torn end of a log Foundry was
+/// still writing, is left out along with the rest of its batch.
+fn read_log_records(log: &[u8]) -> (Vec<Vec<u8>>, usize) {
+    let mut records: Vec<Vec<u8>> = vec![];
+    let mut fragments: Vec<u8> = vec![];
+    let mut num_corrupt = 0;
+    let mut is_skipping_batch = false;
+
+    for block in log.chunks(LOG_BLOCK_LENGTH) {
+        let mut data = block;
+        while data.len() >= LOG_HEADER_LENGTH {
+            let checksum = read_u32(data);
+            let length = usize::from(u16::from_le_bytes([data[4], data[5]]));
+            let record_type = data[6];
+            // Zeroed padding at the end of a block
+            if !(1..=4).contains(&record_type) {
+                break;
+            }
+            let Some(fragment) = data.get(LOG_HEADER_LENGTH..LOG_HEADER_LENGTH + length) else {
+                num_corrupt += 1;
+                break;
+            };
+            data = &data[LOG_HEADER_LENGTH + length..];
+
+            let crc = crc32c(std::iter::once(record_type).chain(fragment.iter().copied()));
+            if mask_crc(crc) != checksum {
+                num_corrupt += 1;
+                fragments.clear();
+                is_skipping_batch = true;
+                continue;
+            }
+
+            match record_type {
+                // Full
+                1 => {
+                    records.push(fragment.to_vec());
+                    is_skipping_batch = false;
+                }
+                // First
+                2 => {
+                    fragments = fragment.to_vec();
+                    is_skipping_batch = false;
+                }
+                // Middle
+                3 if !is_skipping_batch => fragments.extend_from_slice(fragment),
+                // Last
+                4 if !is_skipping_batch => {
+                    fragments.extend_from_slice(fragment);
+                    records.push(std::mem::take(&mut fragments));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    (records, num_corrupt)
+}
+
+/// Applies one write batch. Batches are written all at once, so one that can't be read is left out
+/// entirely.
+fn read_batch(batch: &[u8], versions: &mut HashMap<Vec<u8>, Version>) -> io::Result<()> {
+    if batch.len() < 12 {
+        return Err(invalid_data("write batch is too short"));
+    }
+    let mut sequence = read_u64(batch);
+    let mut data = &batch[12..];
+
+    let mut writes: Vec<(Vec<u8>, Option<Vec<u8>>)> = vec![];
+    while let Some((&record_type, rest)) = data.split_first() {
+        data = rest;
+        let key = read_length_prefixed(&mut data)?.to_vec();
+        let value = match record_type {
+            TYPE_VALUE => Some(read_length_prefixed(&mut data)?.to_vec()),
+            TYPE_DELETION => None,
+            _ => return Err(invalid_data("write batch has an unknown record type")),
+        };
+        writes.push((key, value));
+    }
+
+    for (key, value) in writes {
+        keep_newest(versions, key, Version { sequence, value });
+        sequence += 1;
+    }
+
+    Ok(())
+}
+
+/// Applies every batch in a write-ahead log that can be read, returning what went wrong with the
+/// rest.
+fn read_log(log: &[u8], versions: &mut HashMap<Vec<u8>, Version>) -> Vec<io::Error> {
+    let (batches, num_corrupt) = read_log_records(log);
+    let mut errors: Vec<io::Error> = vec![];
+    if num_corrupt > 0 {
+        errors.push(invalid_data(&format!(
+            "{num_corrupt} log records are corrupt or cut short"
+        )));
+    }
+
+    for batch in batches {
+        if let Err(error) = read_batch(&batch, versions) {
+            errors.push(error);
+        }
+    }
+
+    errors
+}
+
+/// Reads a whole table or none of it, so a table that's only partly readable doesn't leave older
+/// versions of some keys looking like the newest.
+fn read_whole_table(table: &[u8], versions: &mut HashMap<Vec<u8>, Version>) -> io::Result<()> {
+    let mut table_versions: HashMap<Vec<u8>, Version> = HashMap::new();
+    read_table(table, &mut table_versions)?;
+    for (key, version) in table_versions {
+        keep_newest(versions, key, version);
+    }
+
+    Ok(())
+}
+
+/// Says which file in the store an error came from.
+fn in_file(path: &Path, error: io::Error) -> io::Error {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    io::Error::new(error.kind(), format!("{file_name}: {error}"))
+}
+
+/// The newest value of every key in the LevelDB at `directory` that starts with `key_prefix`,
+/// sorted by key. Deleted keys are left out. Tables and log records that can't be read are
+/// skipped, and what went wrong with each is returned alongside the values.
+pub async fn read_values(
+    directory: &Path,
+    key_prefix: &[u8],
+) -> io::Result<(Vec<Vec<u8>>, Vec<io::Error>)> {
+    let mut versions: HashMap<Vec<u8>, Version> = HashMap::new();
+    let mut errors: Vec<io::Error> = vec![];
+
+    let mut entries = fs::read_dir(directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let is_table = matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("ldb" | "sst")
+        );
+        let is_log =
+            path.extension() == Some("log".as_ref()) && path.file_name() != Some("LOG".as_ref());
+        if !is_table && !is_log {
+            continue;
+        }
+
+        let contents = match fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(error) => {
+                errors.push(in_file(&path, error));
+                continue;
+            }
+        };
+        if is_table {
+            if let Err(error) = read_whole_table(&contents, &mut versions) {
+                errors.push(in_file(&path, error));
+            }
+        } else {
+            let log_errors = read_log(&contents, &mut versions);
+            errors.extend(log_errors.into_iter().map(|error| in_file(&path, error)));
+        }
+    }
+
+    let mut values: Vec<(Vec<u8>, Vec<u8>)> = versions
+        .into_iter()
+        .filter(|(key, _)| key.starts_with(key_prefix))
+        .filter_map(|(key, version)| Some((key, version.value?)))
+        .collect();
+    values.sort_unstable();
+
+    Ok((values.into_iter().map(|(_, value)| value).collect(), errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snappy() {
+        // "abcabcabcabc!" as a literal "abc", a copy of 9 bytes from 3 back, and a literal "!".
+        let compressed = [13, 2 << 2, b'a', b'b', b'c', (5 << 2) | 1, 3, 0, b'!'];
+        assert_eq!(decompress_snappy(&compressed).unwrap(), b"abcabcabcabc!");
+
+        assert!(decompress_snappy(&[4, 1, 3]).is_err());
+        assert!(decompress_snappy(&[5, 0, b'a']).is_err());
+    }
+
+    #[test]
+    fn oversized_snappy_preamble() {
+        // A preamble claiming 2^62 bytes, in front of a one-byte literal.
+        let mut compressed = vec![0x80; 8];
+        compressed.extend_from_slice(&[0x40, 0, b'a']);
+        assert!(decompress_snappy(&compressed).is_err());
+    }
+
+    #[test]
+    fn truncated_block_handles() {
+        let table = [0; 16];
+        // An offset and length that overflow when added together
+        let mut handle = vec![0xff; 9];
+        handle.extend_from_slice(&[1, 1]);
+        assert!(read_table_block(&table, &handle).is_err());
+
+        // A block that runs past the end of the table, and one that ends right at it
+        assert!(read_table_block(&table, &[8, 10]).is_err());
+        assert!(read_table_block(&table, &[8, 8]).is_err());
+        assert_eq!(read_table_block(&table, &[8, 7]).unwrap(), [0; 7]);
+    }
+
+    #[test]
+    fn block_entries() {
+        // "!messages!a" => "1", then "!messages!b" => "2" sharing the first 10 bytes.
+        let mut block = vec![0, 11, 1];
+        block.extend_from_slice(b"!messages!a1");
+        block.extend_from_slice(&[10, 1, 1]);
+        block.extend_from_slice(b"b2");
+        block.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 0]);
+
+        let entries = read_block_entries(&block).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], (b"!messages!a".to_vec(), b"1".to_vec()));
+        assert_eq!(entries[1], (b"!messages!b".to_vec(), b"2".to_vec()));
+    }
+
+    /// A log block holding each batch as a single full record.
+    fn log_of(batches: &[&[u8]]) -> Vec<u8> {
+        let mut log = vec![];
+        for batch in batches {
+            let crc = crc32c(std::iter::once(1).chain(batch.iter().copied()));
+            log.extend_from_slice(&mask_crc(crc).to_le_bytes());
+            log.extend_from_slice(&(batch.len() as u16).to_le_bytes());
+            log.push(1);
+            log.extend_from_slice(batch);
+        }
+        log
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32c(*b"123456789"), 0xe3069283);
+        assert_eq!(mask_crc(0), CRC_MASK_DELTA);
+    }
+
+    #[test]
+    fn log_batches() {
+        let mut batch = 7u64.to_le_bytes().to_vec();
+        batch.extend_from_slice(&2u32.to_le_bytes());
+        batch.extend_from_slice(&[TYPE_VALUE, 1, b'a', 1, b'1']);
+        batch.extend_from_slice(&[TYPE_DELETION, 1, b'b']);
+
+        let mut versions = HashMap::from([(
+            b"b".to_vec(),
+            Version {
+                sequence: 1,
+                value: Some(b"old".to_vec()),
+            },
+        )]);
+        assert!(read_log(&log_of(&[&batch]), &mut versions).is_empty());
+
+        assert_eq!(versions[&b"a".to_vec()].sequence, 7);
+        assert_eq!(versions[&b"a".to_vec()].value, Some(b"1".to_vec()));
+        assert_eq!(versions[&b"b".to_vec()].sequence, 8);
+        assert_eq!(versions[&b"b".to_vec()].value, None);
+    }
+
+    #[test]
+    fn corrupt_log_records() {
+        let batch = |sequence: u64, key: u8| {
+            let mut batch = sequence.to_le_bytes().to_vec();
+            batch.extend_from_slice(&1u32.to_le_bytes());
+            batch.extend_from_slice(&[TYPE_VALUE, 1, key, 1, b'1']);
+            batch
+        };
+        let mut log = log_of(&[&batch(1, b'a'), &batch(2, b'b'), &batch(3, b'c')]);
+        // Flip a byte in the second batch's value, then cut the third batch off partway through.
+        let second_value = 2 * (LOG_HEADER_LENGTH + batch(1, b'a').len()) - 1;
+        log[second_value] = b'2';
+        log.truncate(log.len() - 2);
+
+        let mut versions = HashMap::new();
+        let errors = read_log(&log, &mut versions);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[&b"a".to_vec()].value, Some(b"1".to_vec()));
+
+        // A batch that passes its checksum but can't be decoded is skipped on its own.
+        let mut versions = HashMap::new();
+        let errors = read_log(&log_of(&[&[0; 4], &batch(4, b'd')]), &mut versions);
+        assert_eq!(errors.len(), 1);
+        assert!(versions.contains_key(b"d".as_slice()));
+    }
+
+    #[test]
+    fn bad_tables_are_skipped() {
+        let mut versions = HashMap::new();
+        assert!(read_whole_table(&[0; FOOTER_LENGTH], &mut versions).is_err());
+        assert!(versions.is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    get_roll_from_expression_and_outcomes,
+    parse_config::{JsonMapping, TimestampUnit},
+    ChatLog, LogLines, LogPosition, ParseError, Post, ResumePoint, Roll, Visibility,
+};
+use async_trait::async_trait;
+use serde_json::Value;
+use sqlx::types::chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone};
+use tokio::fs::File;
+
+/// Follows a dotted path like `author.name` or `rolls.0.total` into a JSON value.
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| match value {
+        Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        _ => value.get(key),
+    })
+}
+
+/// Reads a field that should hold text, accepting numbers too since some logs use numeric ids.
+fn lookup_text(value: &Value, path: &str) -> Option<String> {
+    match lookup(value, path)? {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+/// Reads a chat log with one JSON object per line, using a `JsonMapping` to find each part of a
+/// post. Meant for VTTs like Owlbear Rodeo that don't get a parser of their own.
+pub struct JsonLinesChatLog {
+    lines: LogLines,
+    mapping: JsonMapping,
+    timezone: FixedOffset,
+}
+
+impl JsonLinesChatLog {
+    pub fn with_mapping(file: File, timezone_offset: Option<i32>, mapping: JsonMapping) -> Self {
+        let timezone = FixedOffset::east_opt(timezone_offset.unwrap_or(0) * 3600).unwrap();
+
+        JsonLinesChatLog {
+            lines: LogLines::new(file),
+            mapping,
+            timezone,
+        }
+    }
+
+    fn parse_timestamp(&self, line: &Value) -> Option<DateTime<FixedOffset>> {
+        match lookup(line, &self.mapping.timestamp)? {
+            Value::Number(number) => {
+                let timestamp = number.as_f64()?;
+                let timestamp_ms = match self.mapping.timestamp_unit {
+                    TimestampUnit::Seconds => timestamp * 1000.,
+                    TimestampUnit::Milliseconds => timestamp,
+                };
+                Some(DateTime::from_timestamp_millis(timestamp_ms as i64)?.into())
+            }
+            Value::String(text) => DateTime::parse_from_rfc3339(text).ok().or_else(|| {
+                let naive =
+                    NaiveDateTime::parse_from_str(text, &self.mapping.timestamp_format).ok()?;
+                self.timezone.from_local_datetime(&naive).single()
+            }),
+            _ => None,
+        }
+    }
+
+    fn parse_roll(&self, line: &Value) -> Option<Roll> {
+        let formula = lookup_text(line, &self.mapping.roll_formula)?;
+        let outcome = lookup(line, &self.mapping.roll_total)?.as_f64()?;
+        let dice: Vec<i64> = lookup(line, &self.mapping.roll_dice)
+            .and_then(Value::as_array)
+            .map(|dice| dice.iter().filter_map(Value::as_i64).collect())
+            .unwrap_or_default();
+
+        // Without every die's outcome, the roll can still be kept by its total.
+        Some(
+            get_roll_from_expression_and_outcomes(&formula, dice, outcome).unwrap_or(Roll {
+                formula,
+                outcome,
+                single_rolls: vec![],
+            }),
+        )
+    }
+
+    /// Lines without every part of a post the mapping asks for are skipped, since VTTs log
+    /// other events alongside chat.
+    fn parse_line(&self, line: &Value) -> Option<Post> {
+        let rolls: Vec<Roll> = self.parse_roll(line).into_iter().collect();
+
+        Some(Post {
+            id: lookup_text(line, &self.mapping.id)?,
+            sender_name: lookup_text(line, &self.mapping.sender)?,
+            datetime: self.parse_timestamp(line)?,
+            content_raw: lookup_text(line, &self.mapping.content).unwrap_or_default(),
+            is_message: rolls.is_empty(),
+            rolls,
+            visibility: Visibility::Public,
+        })
+    }
+}
+
+#[async_trait]
+impl ChatLog for JsonLinesChatLog {
+    async fn new(file: File, timezone_offset: Option<i32>) -> Self {
+        JsonLinesChatLog::with_mapping(file, timezone_offset, JsonMapping::default())
+    }
+
+    async fn next_post(&mut self) -> Option<Result<Post, ParseError>> {
+        while let Some(line) = self.lines.next_line().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error)),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let line: Value = match serde_json::from_str(&line) {
+                Ok(line) => line,
+                Err(error) => return Some(Err(ParseError::malformed(self.lines.line(), error))),
+            };
+            if let Some(post) = self.parse_line(&line) {
+                return Some(Ok(post));
+            }
+        }
+
+        None
+    }
+
+    fn position(&self) -> Option<LogPosition> {
+        Some(self.lines.position())
+    }
+
+    async fn resume(&mut self, from: &ResumePoint) -> bool {
+        self.lines.seek(from.position).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lookup_paths() {
+        let line = json!({"author": {"name": "Bob"}, "rolls": [{"total": 12}], "id": 7});
+
+        assert_eq!(lookup_text(&line, "author.name").unwrap(), "Bob");
+        assert_eq!(lookup(&line, "rolls.0.total").unwrap(), 12);
+        assert_eq!(lookup_text(&line, "id").unwrap(), "7");
+        assert!(lookup(&line, "rolls.1.total").is_none());
+        assert!(lookup(&line, "author.name.first").is_none());
+    }
+}
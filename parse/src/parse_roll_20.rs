@@ -1,11 +1,11 @@
-use crate::{get_roll_from_expression_and_outcomes, trim_whitespace, ChatLog, Post, Roll};
+use crate::{
+    get_roll_from_expression_and_outcomes, trim_whitespace, ChatLog, LogLines, LogPosition,
+    ParseError, Post, ResumePoint, Roll, Visibility,
+};
 use async_trait::async_trait;
 use scraper::{html::Select, ElementRef, Html, Selector};
 use sqlx::types::chrono::{DateTime, FixedOffset, NaiveTime};
-use tokio::{
-    fs::File,
-    io::{AsyncBufReadExt, BufReader, Lines},
-};
+use tokio::fs::File;
 use unicode_segmentation::UnicodeSegmentation;
 
 const DATETIME_STRP: &'static str = "%B %d, %Y %I:%M%p %z";
@@ -19,6 +19,15 @@ fn fragment_is_private(fragment: &Html) -> bool {
     fragment.select(&private_message_selector).next().is_some()
 }
 
+/// Roll20 shows Fate dice as `+`, `0` and `-` rather than as numbers.
+fn parse_die_outcome(text: &str) -> Option<i64> {
+    match text.trim() {
+        "+" => Some(1),
+        "-" => Some(-1),
+        number => number.parse::<i64>().ok(),
+    }
+}
+
 fn try_get_roll_from_plain(
     formula_elem: &ElementRef<'_>,
     roll_results_elems: &mut Select<'_, '_>,
@@ -36,13 +45,7 @@ fn try_get_roll_from_plain(
         .join("")
         .replace("rolling ", "");
     let outcomes: Vec<i64> = roll_results_elems
-        .filter_map(|frag| {
-            frag.text()
-                .collect::<Vec<&str>>()
-                .join("")
-                .parse::<i64>()
-                .ok()
-        })
+        .filter_map(|frag| parse_die_outcome(&frag.text().collect::<Vec<&str>>().join("")))
         .collect();
 
     get_roll_from_expression_and_outcomes(expr_string.as_str(), outcomes, expr_outcome)
@@ -61,18 +64,10 @@ fn try_get_roll_from_macro(roll_result_elem: &ElementRef) -> Option<Roll> {
     let results_elems_selector = Selector::parse(".basicdiceroll").unwrap();
     let outcomes: Vec<i64> = expr_fragment
         .select(&results_elems_selector)
-        .map(|frag| {
-            frag.text()
-                .collect::<Vec<&str>>()
-                .join("")
-                .parse::<i64>()
-                .unwrap()
-        })
-        .collect();
+        .map(|frag| parse_die_outcome(&frag.text().collect::<Vec<&str>>().join("")))
+        .collect::<Option<Vec<i64>>>()?;
 
-    let equals_position = expr_raw
-        .find(" = ")
-        .expect("inlinerollresult span attribute 'title' does not include equals sign");
+    let equals_position = expr_raw.find(" = ")?;
     let expr_string = expr_raw[..equals_position].replace("Rolling ", "");
 
     get_roll_from_expression_and_outcomes(expr_string.as_str(), outcomes, expr_outcome)
@@ -113,7 +108,7 @@ pub struct Roll20ChatLog {
     current_message_html: String,
     last_parsed_sender_name: Option<String>,
     last_parsed_datetime: Option<DateTime<FixedOffset>>,
-    lines: Lines<BufReader<File>>,
+    lines: LogLines,
     // #[cfg(debug_assertions)]
     // pub time_spent_parsing_div_depth: tokio::time::Duration,
     // #[cfg(debug_assertions)]
@@ -129,7 +124,8 @@ impl Roll20ChatLog {
         let sender_selector = Selector::parse(".by").unwrap();
         if let Some(sender_elem) = fragment.select(&sender_selector).next() {
             let sender_raw = sender_elem.text().collect::<Vec<&str>>().join("");
-            self.last_parsed_sender_name = Some(sender_raw.strip_suffix(":").unwrap().to_string());
+            let sender_name = sender_raw.strip_suffix(':').unwrap_or(&sender_raw);
+            self.last_parsed_sender_name = Some(sender_name.to_string());
         }
 
         // if cfg!(debug_assertions) {
@@ -150,8 +146,10 @@ impl Roll20ChatLog {
             if let Ok(timestamp) = DateTime::parse_from_str(ts_text.as_str(), DATETIME_STRP) {
                 let offset_s = FixedOffset::east_opt(-self.timezone_offset * 3600).unwrap();
                 self.last_parsed_datetime = Some(timestamp + offset_s);
-            } else if let Ok(_) = NaiveTime::parse_from_str(ts_text.as_str(), TIME_STRP_STRF) {
-                let date_prefix = self.last_parsed_datetime.unwrap().format(DATE_STRF);
+            } else if let Ok(_) = NaiveTime::parse_from_str(ts_text.as_str(), TIME_STRP_STRF)
+                && let Some(last_parsed_datetime) = self.last_parsed_datetime
+            {
+                let date_prefix = last_parsed_datetime.format(DATE_STRF);
                 let new_ts_text = format!("{date_prefix} {ts_text}");
                 let timestamp =
                     DateTime::parse_from_str(new_ts_text.as_str(), DATETIME_STRP).unwrap();
@@ -165,7 +163,7 @@ impl Roll20ChatLog {
         // }
     }
 
-    fn post_from_current_message_html(&mut self) -> Option<Post> {
+    fn post_from_current_message_html(&mut self) -> Option<Result<Post, ParseError>> {
         let fragment = Html::parse_fragment(self.current_message_html.as_str());
         let general_message_selector = Selector::parse(".message.general").unwrap();
         let full_message_selector = Selector::parse(".message").unwrap();
@@ -179,8 +177,15 @@ impl Roll20ChatLog {
 
         let full_message = fragment.select(&full_message_selector).next()?.value();
         let id = full_message.attr("data-messageid")?.to_string();
-        let sender_name = self.last_parsed_sender_name.clone().unwrap();
-        let datetime = self.last_parsed_datetime.clone().unwrap();
+        let (Some(sender_name), Some(datetime)) = (
+            self.last_parsed_sender_name.clone(),
+            self.last_parsed_datetime,
+        ) else {
+            return Some(Err(ParseError::malformed(
+                self.lines.line(),
+                format!("message {id} came before any sender or date"),
+            )));
+        };
         let mut content_raw = String::from("");
         let mut is_message = false;
         let rolls = get_rolls_from_fragment(&fragment);
@@ -206,16 +211,17 @@ impl Roll20ChatLog {
             content_raw,
             is_message,
             rolls,
+            visibility: Visibility::Public,
         };
 
-        Some(post)
+        Some(Ok(post))
     }
 }
 
 #[async_trait]
 impl ChatLog for Roll20ChatLog {
     async fn new(file: File, timezone_offset: Option<i32>) -> Self {
-        let lines = BufReader::new(file).lines();
+        let lines = LogLines::new(file);
         let offset = if let Some(hours) = timezone_offset {
             hours
         } else {
@@ -240,14 +246,18 @@ impl ChatLog for Roll20ChatLog {
         }
     }
 
-    async fn next_post(&mut self) -> Option<Post> {
+    async fn next_post(&mut self) -> Option<Result<Post, ParseError>> {
         // #[cfg(debug_assertions)]
         // let mut start_parsing_div_depth = tokio::time::Instant::now();
         // #[cfg(debug_assertions)]
         // let mut start_getting_post = tokio::time::Instant::now();
 
         let mut current_tag = String::from("");
-        while let Some(line) = self.lines.next_line().await.unwrap() {
+        while let Some(line) = self.lines.next_line().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error)),
+            };
             if self.div_depth == -1 {
                 self.div_depth += 1;
                 continue;
@@ -319,6 +329,23 @@ impl ChatLog for Roll20ChatLog {
         // }
         None
     }
+
+    fn position(&self) -> Option<LogPosition> {
+        Some(self.lines.position())
+    }
+
+    async fn resume(&mut self, from: &ResumePoint) -> bool {
+        if self.lines.seek(from.position).await.is_err() {
+            return false;
+        }
+
+        // The `<div class="content">` every log starts with was read before, and messages don't
+        // repeat their sender or date when they're the same as the last one's.
+        self.div_depth = 0;
+        self.last_parsed_sender_name = Some(from.sender_name.clone());
+        self.last_parsed_datetime = Some(from.datetime);
+        true
+    }
 }
 
 #[cfg(test)]
@@ -476,4 +503,45 @@ mod tests {
         assert_eq!(rolls[1].single_rolls[1].outcome, 6);
         assert_eq!(rolls[1].single_rolls[2].outcome, 5);
     }
+
+    #[test]
+    fn get_rerolled_roll_from_macro() {
+        let raw_roll_html = r#"
+            <div class="message general" data-messageid="-Tes--1-tEsTIDFFFFFH">
+                <span
+                    class="inlinerollresult showtip tipsy-n-right"
+                    title='Rolling 2d6ro<2+3[STR] = (<span class="basicdiceroll critfail">1</span>+<span class="basicdiceroll">2</span>+<span class="basicdiceroll">5</span>)+3'
+                    >10</span
+                >
+            </div>"#;
+        let fragment = Html::parse_fragment(raw_roll_html);
+
+        let rolls = get_rolls_from_fragment(&fragment);
+        assert_eq!(rolls.len(), 1);
+        assert_eq!(rolls[0].outcome, 10.);
+        assert_eq!(rolls[0].single_rolls.len(), 3);
+        assert!(rolls[0].single_rolls[0].is_rerolled);
+        assert!(!rolls[0].single_rolls[1].is_rerolled);
+        assert!(!rolls[0].single_rolls[2].is_rerolled);
+    }
+
+    #[test]
+    fn skip_unreadable_macro() {
+        let raw_roll_html = r#"
+            <div class="message general" data-messageid="-Tes--1-tEsTIDFFFFFI">
+                <span
+                    class="inlinerollresult showtip tipsy-n-right"
+                    title='Rolling 1d20+3 = (<span class="basicdiceroll">?</span>)+3'
+                    >15</span
+                >
+                <span
+                    class="inlinerollresult showtip tipsy-n-right"
+                    title='Rolling 1d20+3'
+                    >15</span
+                >
+            </div>"#;
+        let fragment = Html::parse_fragment(raw_roll_html);
+
+        assert!(get_rolls_from_fragment(&fragment).is_empty());
+    }
 }
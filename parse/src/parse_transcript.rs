@@ -0,0 +1,183 @@
+use crate::{
+    get_roll_from_expression_and_outcomes, parse_config::TranscriptFormat, parse_expression,
+    ChatLog, LogLines, ParseError, Post, Roll, Visibility,
+};
+use async_trait::async_trait;
+use regex::{Captures, Regex};
+use sqlx::types::chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeZone};
+use std::collections::HashMap;
+use tokio::fs::File;
+use uuid::Uuid;
+
+/// Transcripts don't give posts ids, and lines get added and removed when they're pasted by hand,
+/// so posts are identified by a hash of who sent them, when and what they said.
+fn post_id(sender_name: &str, datetime: &DateTime<FixedOffset>, content_raw: &str) -> String {
+    let post = format!("{}\n{sender_name}\n{content_raw}", datetime.to_rfc3339());
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, post.as_bytes())
+        .simple()
+        .to_string()
+}
+
+/// Reads a session transcript that was copied out of a chat as plain text or Markdown, like
+/// `[8:14 PM] Bob: I open the door`, using the regular expressions in a `TranscriptFormat`.
+pub struct TranscriptChatLog {
+    lines: LogLines,
+    format: TranscriptFormat,
+    line_pattern: Regex,
+    date_pattern: Regex,
+    roll_pattern: Regex,
+    timezone: FixedOffset,
+    current_date: Option<NaiveDate>,
+    last_time: NaiveTime,
+    /// The post being read, which isn't finished until the next one starts.
+    pending_post: Option<Post>,
+    /// How many posts so far had each id, to tell identical posts apart.
+    occurrences: HashMap<String, u32>,
+}
+
+impl TranscriptChatLog {
+    pub fn with_format(
+        file: File,
+        timezone_offset: Option<i32>,
+        format: TranscriptFormat,
+    ) -> Result<Self, regex::Error> {
+        Ok(TranscriptChatLog {
+            lines: LogLines::new(file),
+            line_pattern: Regex::new(&format.line_pattern)?,
+            date_pattern: Regex::new(&format.date_pattern)?,
+            roll_pattern: Regex::new(&format.roll_pattern)?,
+            format,
+            timezone: FixedOffset::east_opt(timezone_offset.unwrap_or(0) * 3600).unwrap(),
+            current_date: None,
+            last_time: NaiveTime::MIN,
+            pending_post: None,
+            occurrences: HashMap::new(),
+        })
+    }
+
+    fn parse_date(&self, captures: &Captures) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(
+            captures.name("date")?.as_str().trim(),
+            &self.format.date_format,
+        )
+        .ok()
+    }
+
+    /// Starts a post from a line the line pattern matched. Posts from before the transcript
+    /// gives a date are skipped, since there's no way to tell when they were sent.
+    fn start_post(&mut self, captures: &Captures) -> Option<Post> {
+        let date = self.parse_date(captures).or(self.current_date)?;
+        if let Some(time) = captures.name("time").and_then(|time| {
+            NaiveTime::parse_from_str(time.as_str().trim(), &self.format.time_format).ok()
+        }) {
+            self.last_time = time;
+        }
+
+        // Markdown transcripts often bold the sender, like `**Bob**: I open the door`.
+        let sender_name = captures.name("sender")?.as_str().trim_matches('*').trim();
+        let content = captures.name("content")?.as_str().trim();
+
+        Some(Post {
+            // Set when the post is finished, since the id depends on all of its content.
+            id: String::new(),
+            sender_name: sender_name.to_string(),
+            datetime: self
+                .timezone
+                .from_local_datetime(&date.and_time(self.last_time))
+                .single()?,
+            content_raw: content.to_string(),
+            is_message: true,
+            rolls: vec![],
+            visibility: Visibility::Public,
+        })
+    }
+
+    fn parse_roll(&self, content: &str) -> Option<Roll> {
+        let captures = self.roll_pattern.captures(content)?;
+        let formula = captures.name("formula")?.as_str().trim();
+        let outcome: f64 = captures.name("total")?.as_str().parse().ok()?;
+        let dice: Vec<i64> = captures
+            .name("dice")
+            .map(|dice| {
+                dice.as_str()
+                    .split([',', ' '])
+                    .filter_map(|die| die.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        get_roll_from_expression_and_outcomes(formula, dice, outcome).or_else(|| {
+            // Transcripts often only give the total, which is still worth keeping.
+            parse_expression(formula).ok()?;
+            Some(Roll {
+                formula: formula.to_string(),
+                outcome,
+                single_rolls: vec![],
+            })
+        })
+    }
+
+    fn finish_post(&mut self, mut post: Post) -> Post {
+        let id = post_id(&post.sender_name, &post.datetime, &post.content_raw);
+        let occurrence = self.occurrences.entry(id.clone()).or_default();
+        post.id = match occurrence {
+            0 => id,
+            _ => format!("{id}-{occurrence}"),
+        };
+        *occurrence += 1;
+
+        if let Some(roll) = self.parse_roll(&post.content_raw) {
+            post.is_message = false;
+            post.rolls = vec![roll];
+        }
+
+        post
+    }
+}
+
+#[async_trait]
+impl ChatLog for TranscriptChatLog {
+    async fn new(file: File, timezone_offset: Option<i32>) -> Self {
+        TranscriptChatLog::with_format(file, timezone_offset, TranscriptFormat::default())
+            .expect("failed to compile default transcript patterns")
+    }
+
+    async fn next_post(&mut self) -> Option<Result<Post, ParseError>> {
+        while let Some(line) = self.lines.next_line().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error)),
+            };
+
+            let date = self
+                .date_pattern
+                .captures(&line)
+                .and_then(|captures| self.parse_date(&captures));
+            if date.is_some() {
+                self.current_date = date;
+                if let Some(post) = self.pending_post.take() {
+                    return Some(Ok(self.finish_post(post)));
+                }
+                continue;
+            }
+
+            if let Some(captures) = self.line_pattern.captures(&line) {
+                let next_post = self.start_post(&captures);
+                if let Some(post) = std::mem::replace(&mut self.pending_post, next_post) {
+                    return Some(Ok(self.finish_post(post)));
+                }
+                continue;
+            }
+
+            if let Some(post) = &mut self.pending_post
+                && !line.trim().is_empty()
+            {
+                post.content_raw.push('\n');
+                post.content_raw.push_str(line.trim_end());
+            }
+        }
+
+        let post = self.pending_post.take()?;
+        Some(Ok(self.finish_post(post)))
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{
+    parse_config::CampaignConfig, parse_discord_log, parse_fantasy_grounds_log, parse_foundry_log,
+    parse_json_lines_log, parse_roll20_log, parse_transcript_log, post_stream, ChatLog, LogFormat,
+    PostStream,
+};
+use std::{collections::HashMap, future::Future, pin::Pin};
+
+pub type BoxedChatLog = Box<dyn ChatLog + Send>;
+
+pub type ChatLogFuture<'a> = Pin<Box<dyn Future<Output = BoxedChatLog> + Send + 'a>>;
+
+/// Opens the log at a path for a campaign, which has settings like its timezone and any
+/// format-specific config.
+pub type ChatLogFactory =
+    Box<dyn for<'a> Fn(&'a str, &'a CampaignConfig) -> ChatLogFuture<'a> + Send + Sync>;
+
+/// Every kind of chat log that can be imported, keyed by the name a campaign's `format` uses.
+/// `ChatLogRegistry::default()` has the built-in formats, and other crates can `register` more.
+pub struct ChatLogRegistry {
+    factories: HashMap<String, ChatLogFactory>,
+}
+
+impl ChatLogRegistry {
+    /// A registry without any formats, not even the built-in ones.
+    pub fn empty() -> Self {
+        ChatLogRegistry {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Adds a format, replacing any already registered under the same name.
+    pub fn register<F>(&mut self, format: &str, factory: F)
+    where
+        F: for<'a> Fn(&'a str, &'a CampaignConfig) -> ChatLogFuture<'a> + Send + Sync + 'static,
+    {
+        self.factories.insert(format.to_string(), Box::new(factory));
+    }
+
+    pub fn contains(&self, format: &str) -> bool {
+        self.factories.contains_key(format)
+    }
+
+    /// Opens the log at `path_to_log` as the given format, or `None` if it isn't registered.
+    pub async fn open(
+        &self,
+        format: &str,
+        path_to_log: &str,
+        campaign_config: &CampaignConfig,
+    ) -> Option<PostStream> {
+        Some(post_stream(
+            self.open_log(format, path_to_log, campaign_config).await?,
+        ))
+    }
+
+    /// Like `open`, but gives the log itself, for callers that want to `resume` it.
+    pub async fn open_log(
+        &self,
+        format: &str,
+        path_to_log: &str,
+        campaign_config: &CampaignConfig,
+    ) -> Option<BoxedChatLog> {
+        let factory = self.factories.get(format)?;
+        Some(factory(path_to_log, campaign_config).await)
+    }
+}
+
+// Foundry and Discord timestamps say what timezone they're in, so only the other formats need
+// the campaign's offset.
+
+fn open_foundry<'a>(path_to_log: &'a str, _: &'a CampaignConfig) -> ChatLogFuture<'a> {
+    Box::pin(async move { Box::new(parse_foundry_log(path_to_log, None).await) as BoxedChatLog })
+}
+
+fn open_roll20<'a>(path_to_log: &'a str, campaign: &'a CampaignConfig) -> ChatLogFuture<'a> {
+    Box::pin(async move {
+        Box::new(parse_roll20_log(path_to_log, Some(campaign.timezone_offset)).await)
+            as BoxedChatLog
+    })
+}
+
+fn open_fantasy_grounds<'a>(
+    path_to_log: &'a str,
+    campaign: &'a CampaignConfig,
+) -> ChatLogFuture<'a> {
+    Box::pin(async move {
+        Box::new(parse_fantasy_grounds_log(path_to_log, Some(campaign.timezone_offset)).await)
+            as BoxedChatLog
+    })
+}
+
+fn open_discord<'a>(path_to_log: &'a str, _: &'a CampaignConfig) -> ChatLogFuture<'a> {
+    Box::pin(async move { Box::new(parse_discord_log(path_to_log, None).await) as BoxedChatLog })
+}
+
+fn open_json_lines<'a>(path_to_log: &'a str, campaign: &'a CampaignConfig) -> ChatLogFuture<'a> {
+    Box::pin(async move {
+        let timezone_offset = Some(campaign.timezone_offset);
+        Box::new(parse_json_lines_log(path_to_log, timezone_offset, &campaign.json_mapping).await)
+            as BoxedChatLog
+    })
+}
+
+fn open_transcript<'a>(path_to_log: &'a str, campaign: &'a CampaignConfig) -> ChatLogFuture<'a> {
+    Box::pin(async move {
+        let timezone_offset = Some(campaign.timezone_offset);
+        Box::new(
+            parse_transcript_log(path_to_log, timezone_offset, &campaign.transcript_format).await,
+        ) as BoxedChatLog
+    })
+}
+
+impl Default for ChatLogRegistry {
+    fn default() -> Self {
+        let mut registry = ChatLogRegistry::empty();
+        registry.register(LogFormat::Foundry.name(), open_foundry);
+        registry.register(LogFormat::Roll20.name(), open_roll20);
+        registry.register(LogFormat::FantasyGrounds.name(), open_fantasy_grounds);
+        registry.register(LogFormat::Discord.name(), open_discord);
+        registry.register(LogFormat::JsonLines.name(), open_json_lines);
+        registry.register(LogFormat::Transcript.name(), open_transcript);
+
+        registry
+    }
+}
@@ -109,6 +109,46 @@ async fn pronouns_map_and_censor() {
     assert_eq!(avoid_text, censor_values.0);
 }
 
+#[tokio::test]
+#[serial]
+async fn roll_macro() {
+    use data::{Player, RollMacro};
+
+    let pool = data::create_connection_pool("../.env.test").await;
+    let player_name = "Bob".to_string();
+    let player = Player::from_values(&player_name).await;
+
+    let mut transaction = data::begin_transaction(&pool).await;
+    let player_id = player.fetch_or_insert_id(&mut transaction).await;
+
+    let macro_values = ("#atk".to_string(), "1d20+@str".to_string(), player_id);
+    let roll_macro = RollMacro::from_values(&macro_values).await;
+    let macro_id = roll_macro.fetch_or_insert_id(&mut transaction).await;
+    let dupe_macro_id = roll_macro.fetch_or_insert_id(&mut transaction).await;
+
+    let updated_macro = RollMacro {
+        expression: "1d20+@str+@prof",
+        ..roll_macro
+    };
+    let updated_macro_id = updated_macro.upsert(&mut transaction).await;
+    let (macro_name, expression, macro_player_id) =
+        RollMacro::try_fetch_values(&mut transaction, macro_id)
+            .await
+            .unwrap();
+
+    let was_removed = RollMacro::delete(&mut transaction, "#atk", player_id).await;
+    let was_removed_again = RollMacro::delete(&mut transaction, "#atk", player_id).await;
+
+    transaction.rollback().await.unwrap();
+    assert_eq!(macro_id, dupe_macro_id);
+    assert_eq!(macro_id, updated_macro_id);
+    assert_eq!(macro_name, "#atk");
+    assert_eq!(expression, "1d20+@str+@prof");
+    assert_eq!(macro_player_id, player_id);
+    assert!(was_removed);
+    assert!(!was_removed_again);
+}
+
 #[tokio::test]
 #[serial]
 async fn campaign() {
@@ -448,20 +488,27 @@ async fn update_posts() {
 
     data::update_posts_from_log(
         &pool,
+        &parse::ChatLogRegistry::default(),
         "Descent into Avernus",
         "../test_files",
-        "fnd_test_campaign.db",
-        None,
+        &config.campaigns["Descent into Avernus"],
     )
     .await;
 
     let pool = data::create_connection_pool("../.env.test").await;
-    let first_successful_post = sqlx::query!(r#"SELECT id FROM post WHERE id = 'TeStId12355'"#)
+    let campaign_id =
+        sqlx::query!(r#"SELECT id FROM campaign WHERE campaign_name = 'Descent into Avernus'"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .id;
+    let post_id = data::PostInterface::post_id(campaign_id, "TeStId12355");
+    let first_successful_post = sqlx::query!(r#"SELECT id FROM post WHERE id = $1"#, post_id)
         .fetch_one(&pool)
         .await
         .unwrap()
         .id;
-    let first_successful_roll = sqlx::query!(r#"SELECT * FROM roll WHERE post_id = 'TeStId12355'"#)
+    let first_successful_roll = sqlx::query!(r#"SELECT * FROM roll WHERE post_id = $1"#, post_id)
         .fetch_one(&pool)
         .await
         .unwrap();
@@ -479,8 +526,323 @@ async fn update_posts() {
     assert_eq!(first_successful_roll_single.faces, 20);
     assert_eq!(first_successful_roll_single.outcome, 12);
 
+    let count_rows = || async {
+        sqlx::query!(
+            r#"SELECT
+                (SELECT COUNT(*) FROM post) AS "posts!",
+                (SELECT COUNT(*) FROM chat_message) AS "messages!",
+                (SELECT COUNT(*) FROM roll_single) AS "roll_singles!""#
+        )
+        .fetch_one(&pool)
+        .await
+        .map(|counts| (counts.posts, counts.messages, counts.roll_singles))
+        .unwrap()
+    };
+    let imported_once = count_rows().await;
+    data::update_posts_from_log(
+        &pool,
+        &parse::ChatLogRegistry::default(),
+        "Descent into Avernus",
+        "../test_files",
+        &config.campaigns["Descent into Avernus"],
+    )
+    .await;
+    assert_eq!(count_rows().await, imported_once);
+
+    sqlx::query!(r#"CALL clear_all_tables()"#)
+        .execute(&pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn incremental_imports() {
+    let pool = data::create_connection_pool("../.env.test").await;
+    let config = parse::parse_config("../test_files/test_config.json".to_string()).await;
+    sqlx::query!(r#"CALL clear_all_tables()"#)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let mut transaction = data::begin_transaction(&pool).await;
+    data::update_players(&mut transaction, &config).await;
+    data::update_campaigns(&mut transaction, &config).await;
+    transaction.commit().await.unwrap();
+
+    let directory = std::env::temp_dir().join("squidbot_incremental_imports");
+    tokio::fs::create_dir_all(&directory).await.unwrap();
+    let path_to_log = directory.join("fnd_incremental.db");
+    let mut campaign_config = config.campaigns["Descent into Avernus"].clone();
+    campaign_config.log = "fnd_incremental.db".to_string();
+
+    let log = tokio::fs::read_to_string("../test_files/fnd_test_campaign.db")
+        .await
+        .unwrap();
+    let lines: Vec<&str> = log.split_inclusive('\n').collect();
+    let import = |contents: String| {
+        let (pool, path_to_log, directory, campaign_config) =
+            (&pool, &path_to_log, &directory, &campaign_config);
+        async move {
+            tokio::fs::write(path_to_log, contents).await.unwrap();
+            data::update_posts_from_log(
+                pool,
+                &parse::ChatLogRegistry::default(),
+                "Descent into Avernus",
+                directory.to_str().unwrap(),
+                campaign_config,
+            )
+            .await;
+        }
+    };
+
+    let campaign_id =
+        sqlx::query!(r#"SELECT id FROM campaign WHERE campaign_name = 'Descent into Avernus'"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .id;
+    let first_post_id = data::PostInterface::post_id(campaign_id, "TeStId12345");
+    let first_post_content = || async {
+        sqlx::query!(
+            r#"SELECT content FROM chat_message WHERE post_id = $1"#,
+            first_post_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .content
+    };
+
+    import(lines[..2].concat()).await;
+    let checkpoint = data::ImportCheckpoint::fetch(&pool, campaign_id)
+        .await
+        .unwrap();
+    assert_eq!(checkpoint.log, "fnd_incremental.db");
+    assert_eq!(
+        checkpoint.resume_point.position.offset,
+        lines[..2].concat().len() as u64
+    );
+    assert_eq!(checkpoint.last_post_id, "TeStId12346");
+
+    // Anything already imported is left alone when the log has only grown.
+    sqlx::query!(
+        r#"UPDATE chat_message SET content = 'tampered' WHERE post_id = $1"#,
+        first_post_id
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    import(log.clone()).await;
+    assert_eq!(first_post_content().await, "tampered");
+    let checkpoint = data::ImportCheckpoint::fetch(&pool, campaign_id)
+        .await
+        .unwrap();
+    assert_eq!(checkpoint.resume_point.position.offset, log.len() as u64);
+    assert_eq!(checkpoint.last_post_id, "TeStId12356");
+
+    // An edit to a post that was already imported means reading the whole log again.
+    import(log.replacen("foobar! Bobby", "foobar! Bobby (edited)", 1)).await;
+    assert_eq!(first_post_content().await, "foobar! Bobby (edited)");
+
+    tokio::fs::remove_dir_all(&directory).await.unwrap();
     sqlx::query!(r#"CALL clear_all_tables()"#)
         .execute(&pool)
         .await
         .unwrap();
 }
+
+#[tokio::test]
+#[serial]
+async fn whispers_stay_private() {
+    let pool = data::create_connection_pool("../.env.test").await;
+    let config = parse::parse_config("../test_files/test_config.json".to_string()).await;
+    sqlx::query!(r#"CALL clear_all_tables()"#)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let mut transaction = data::begin_transaction(&pool).await;
+    data::update_players(&mut transaction, &config).await;
+    data::update_campaigns(&mut transaction, &config).await;
+    transaction.commit().await.unwrap();
+
+    data::update_posts_from_log(
+        &pool,
+        &parse::ChatLogRegistry::default(),
+        "Descent into Avernus",
+        "../test_files",
+        &config.campaigns["Descent into Avernus"],
+    )
+    .await;
+
+    let whisper_visibility = sqlx::query!(
+        r#"SELECT visibility FROM post
+            JOIN chat_message ON post.id = post_id
+        WHERE content = 'foobar 2!'"#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap()
+    .visibility;
+    let search_results = data::search_for_message(&pool, &config, "foobar", 10)
+        .await
+        .unwrap();
+    let whisper_trace = data::trace_message(&pool, "foobar 2!").await;
+
+    let num_public_rolls = data::fetch_all_parseable_rolls(&pool).await.len();
+    sqlx::query!(r#"UPDATE post SET visibility = 'blind' WHERE id IN (SELECT post_id FROM roll)"#)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let blind_rolls = data::fetch_all_parseable_rolls(&pool).await;
+
+    sqlx::query!(r#"CALL clear_all_tables()"#)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    assert_eq!(whisper_visibility, "whisper");
+    assert_eq!(search_results.len(), 1);
+    assert!(!search_results[0]
+        .as_message(false, true)
+        .contains("foobar 2!"));
+    assert!(whisper_trace.is_none());
+    assert!(num_public_rolls > 0);
+    assert!(blind_rolls.is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn insert_discord_post() {
+    let pool = data::create_connection_pool("../.env.test").await;
+    let config = parse::parse_config("../test_files/test_config.json".to_string()).await;
+
+    let mut transaction = data::begin_transaction(&pool).await;
+    data::update_players(&mut transaction, &config).await;
+    data::update_campaigns(&mut transaction, &config).await;
+    transaction.commit().await.unwrap();
+
+    let roll_result = parse::dicemath_result("2d20kl1 + 3").unwrap();
+    let post = parse::Post {
+        id: "1234567890".to_string(),
+        sender_name: "cool_guy_420".to_string(),
+        datetime: sqlx::types::chrono::DateTime::parse_from_rfc3339("2026-10-18T12:00:00-06:00")
+            .unwrap(),
+        content_raw: "2d20kl1 + 3".to_string(),
+        is_message: false,
+        rolls: vec![parse::Roll {
+            formula: "2d20kl1 + 3".to_string(),
+            outcome: roll_result.value,
+            single_rolls: roll_result.terms[0].single_rolls.clone(),
+        }],
+        visibility: parse::Visibility::Public,
+    };
+    data::insert_discord_post(&pool, "Tomb of Annihilation", post)
+        .await
+        .unwrap();
+
+    let discord_roll = sqlx::query!(
+        r#"SELECT roll.id, player_name, formula, outcome
+        FROM roll
+            JOIN post ON post_id = post.id
+            JOIN campaign ON post.campaign_id = campaign.id
+            JOIN alias ON alias.sender_id = post.sender_id
+            JOIN player ON alias.player_id = player.id
+        WHERE
+            campaign_name = 'Tomb of Annihilation'"#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let discord_roll_singles = sqlx::query!(
+        r#"SELECT faces, is_dropped FROM roll_single WHERE roll_id = $1"#,
+        discord_roll.id
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query!(r#"CALL clear_all_tables()"#)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(discord_roll.player_name, "Bob");
+    assert_eq!(discord_roll.formula, "2d20kl1 + 3");
+    assert_eq!(discord_roll.outcome, roll_result.value);
+    assert_eq!(discord_roll_singles.len(), 2);
+    assert!(discord_roll_singles.iter().all(|single| single.faces == 20));
+    assert_eq!(
+        discord_roll_singles
+            .iter()
+            .filter(|single| single.is_dropped)
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn import_after_discord_roll() {
+    let pool = data::create_connection_pool("../.env.test").await;
+    let config = parse::parse_config("../test_files/test_config.json".to_string()).await;
+    sqlx::query!(r#"CALL clear_all_tables()"#)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let mut transaction = data::begin_transaction(&pool).await;
+    data::update_players(&mut transaction, &config).await;
+    data::update_campaigns(&mut transaction, &config).await;
+    transaction.commit().await.unwrap();
+
+    // The roll the bot recorded when cool_guy_420 sent `.r 1d20+3` in the exported channel
+    let roll_result = parse::dicemath_result("1d20+3").unwrap();
+    let post = parse::Post {
+        id: "1000000000000000003".to_string(),
+        sender_name: "cool_guy_420".to_string(),
+        datetime: sqlx::types::chrono::DateTime::parse_from_rfc3339("2026-10-18T12:01:30-06:00")
+            .unwrap(),
+        content_raw: "1d20+3".to_string(),
+        is_message: false,
+        rolls: vec![parse::Roll {
+            formula: "1d20+3".to_string(),
+            outcome: roll_result.value,
+            single_rolls: roll_result.terms[0].single_rolls.clone(),
+        }],
+        visibility: parse::Visibility::Public,
+    };
+    data::insert_discord_post(&pool, "Tomb of Annihilation", post)
+        .await
+        .unwrap();
+
+    let mut campaign_config = config.campaigns["Tomb of Annihilation"].clone();
+    campaign_config.log = "dsc_test_campaign.json".to_string();
+    data::update_posts_from_log(
+        &pool,
+        &parse::ChatLogRegistry::default(),
+        "Tomb of Annihilation",
+        "../test_files",
+        &campaign_config,
+    )
+    .await;
+
+    let num_rolls = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM roll"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .count;
+    let num_messages = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM chat_message"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .count;
+
+    sqlx::query!(r#"CALL clear_all_tables()"#)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    assert_eq!(num_rolls, 1);
+    assert_eq!(num_messages, 1);
+}
@@ -1,4 +1,6 @@
+use futures::{StreamExt, TryStreamExt};
 use parse::ChatLog;
+use rand::{rngs::StdRng, SeedableRng};
 
 #[tokio::test]
 async fn parse_config() {
@@ -18,11 +20,493 @@ async fn parse_foundry_chatlog() {
 
     let mut posts: Vec<parse::Post> = vec![];
     while let Some(post) = log.next_post().await {
-        posts.push(post);
+        posts.push(post.unwrap());
     }
     assert_eq!(posts[0].id, "TeStId12345");
-    assert_eq!(posts[1].sender_name, "");
+    assert_eq!(posts[0].visibility, parse::Visibility::Public);
+    assert_eq!(posts[1].id, "TeStId12346");
+    assert_eq!(posts[1].visibility, parse::Visibility::Whisper);
+    assert_eq!(posts[2].sender_name, "");
+    assert_eq!(posts.len(), 5);
+}
+
+#[tokio::test]
+async fn parse_foundry_leveldb_chatlog() {
+    let path_to_log = "../test_files/fnd_test_campaign_v11";
+    let mut log = parse::parse_foundry_log(path_to_log, None).await;
+
+    let mut posts: Vec<parse::Post> = vec![];
+    while let Some(post) = log.next_post().await {
+        posts.push(post.unwrap());
+    }
+    assert_eq!(posts.len(), 4);
+
+    assert_eq!(posts[0].id, "TeStId12345");
+    assert_eq!(posts[0].content_raw, "foobar! Bobby (edited)");
+    assert_eq!(posts[1].visibility, parse::Visibility::Whisper);
+    assert_eq!(posts[2].sender_name, "");
+    assert_eq!(posts[3].id, "TeStId12356");
+    assert!(!posts[3].is_message);
+    assert!(!posts[3].rolls.is_empty());
+}
+
+#[tokio::test]
+async fn parse_foundry_v12_leveldb_chatlog() {
+    let path_to_log = "../test_files/fnd_test_campaign_v12";
+    let mut log = parse::parse_foundry_log(path_to_log, None).await;
+
+    let mut posts: Vec<parse::Post> = vec![];
+    while let Some(post) = log.next_post().await {
+        posts.push(post.unwrap());
+    }
+    assert_eq!(posts.len(), 2);
+
+    assert_eq!(posts[0].id, "TeStId12412");
+    assert_eq!(posts[0].sender_name, "cool_guy 420");
+    assert!(posts[0].is_message);
+    assert_eq!(posts[1].id, "TeStId12413");
+    assert!(!posts[1].is_message);
+    assert_eq!(posts[1].rolls[0].outcome, 16.);
+    assert_eq!(posts[1].rolls[0].single_rolls[0].faces, 20);
+}
+
+#[tokio::test]
+async fn torn_foundry_leveldb_chatlog() {
+    let directory = std::env::temp_dir().join("squidbot_torn_leveldb");
+    tokio::fs::create_dir_all(&directory).await.unwrap();
+    for file_name in ["000005.ldb", "000006.log"] {
+        tokio::fs::copy(
+            format!("../test_files/fnd_test_campaign_v11/{file_name}"),
+            directory.join(file_name),
+        )
+        .await
+        .unwrap();
+    }
+    // A record Foundry was partway through writing when the store was copied.
+    let mut log = tokio::fs::read(directory.join("000006.log")).await.unwrap();
+    log.extend_from_slice(&[1, 2, 3, 4, 200, 0, 1, 0, 0, 0]);
+    tokio::fs::write(directory.join("000006.log"), log)
+        .await
+        .unwrap();
+
+    let mut log = parse::parse_foundry_log(directory.to_str().unwrap(), None).await;
+    let mut posts: Vec<parse::Post> = vec![];
+    let mut errors: Vec<parse::ParseError> = vec![];
+    while let Some(post) = log.next_post().await {
+        match post {
+            Ok(post) => posts.push(post),
+            Err(error) => errors.push(error),
+        }
+    }
+    tokio::fs::remove_dir_all(&directory).await.unwrap();
+
+    assert_eq!(posts.len(), 4);
+    assert_eq!(posts[0].content_raw, "foobar! Bobby (edited)");
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], parse::ParseError::Io { line: 0, .. }));
+}
+
+/// A LevelDB table whose footer points its index at `index_handle`, after `blocks`.
+fn leveldb_table(blocks: &[u8], index_handle: &[u8]) -> Vec<u8> {
+    let mut footer = vec![0, 0];
+    footer.extend_from_slice(index_handle);
+    footer.resize(40, 0);
+    footer.extend_from_slice(&0xdb4775248b80fb57u64.to_le_bytes());
+
+    let mut table = blocks.to_vec();
+    table.extend_from_slice(&footer);
+    table
+}
+
+#[tokio::test]
+async fn corrupt_foundry_leveldb_tables() {
+    let directory = std::env::temp_dir().join("squidbot_corrupt_leveldb");
+    tokio::fs::create_dir_all(&directory).await.unwrap();
+    for file_name in ["000005.ldb", "000006.log"] {
+        tokio::fs::copy(
+            format!("../test_files/fnd_test_campaign_v11/{file_name}"),
+            directory.join(file_name),
+        )
+        .await
+        .unwrap();
+    }
+    // An index whose offset and length overflow when added together
+    let mut overflowing_handle = vec![0xff; 9];
+    overflowing_handle.extend_from_slice(&[1, 1]);
+    tokio::fs::write(
+        directory.join("000007.ldb"),
+        leveldb_table(&[], &overflowing_handle),
+    )
+    .await
+    .unwrap();
+    // A Snappy-compressed index claiming to decompress to 2^62 bytes
+    let mut oversized_block = vec![0x80; 8];
+    oversized_block.extend_from_slice(&[0x40, 0, b'a', 1, 0, 0, 0, 0]);
+    tokio::fs::write(
+        directory.join("000008.ldb"),
+        leveldb_table(&oversized_block, &[0, 11]),
+    )
+    .await
+    .unwrap();
+
+    let mut log = parse::parse_foundry_log(directory.to_str().unwrap(), None).await;
+    let mut posts: Vec<parse::Post> = vec![];
+    let mut errors: Vec<parse::ParseError> = vec![];
+    while let Some(post) = log.next_post().await {
+        match post {
+            Ok(post) => posts.push(post),
+            Err(error) => errors.push(error),
+        }
+    }
+    tokio::fs::remove_dir_all(&directory).await.unwrap();
+
     assert_eq!(posts.len(), 4);
+    assert_eq!(errors.len(), 2);
+    assert!(errors
+        .iter()
+        .all(|error| matches!(error, parse::ParseError::Io { line: 0, .. })));
+}
+
+#[tokio::test]
+async fn parse_discord_chatlog() {
+    let path_to_log = "../test_files/dsc_test_campaign.json";
+    let mut log = parse::parse_discord_log(path_to_log, None).await;
+
+    let mut posts: Vec<parse::Post> = vec![];
+    while let Some(post) = log.next_post().await {
+        posts.push(post.unwrap());
+    }
+    assert_eq!(posts.len(), 3);
+
+    assert_eq!(posts[0].id, "1000000000000000002");
+    assert_eq!(posts[0].sender_name, "cool_guy_420");
+    assert!(posts[0].is_message);
+
+    // Rolls are keyed on the command they answered, like the bot records them when they're made.
+    assert_eq!(posts[1].id, "1000000000000000003");
+    assert_eq!(posts[1].sender_name, "cool_guy_420");
+    assert!(!posts[1].is_message);
+    assert_eq!(posts[1].rolls[0].formula, "1d20+3");
+    assert_eq!(posts[1].rolls[0].outcome, 15.);
+    assert_eq!(posts[1].rolls[0].single_rolls[0].faces, 20);
+
+    assert_eq!(posts[2].id, "600000000000000001");
+    assert_eq!(posts[2].sender_name, "cool_girl_420");
+    assert_eq!(posts[2].rolls.len(), 2);
+    assert_eq!(posts[2].rolls[1].outcome, 15.);
+    assert!(posts[2].rolls[1].single_rolls[2].is_dropped);
+}
+
+#[tokio::test]
+async fn malformed_discord_chatlog() {
+    let path_to_log = std::env::temp_dir().join("squidbot_malformed_discord.json");
+    tokio::fs::write(&path_to_log, "{\n  \"messages\": [\n    {\"id\": ")
+        .await
+        .unwrap();
+
+    let mut log = parse::parse_discord_log(path_to_log.to_str().unwrap(), None).await;
+    let error = log.next_post().await.unwrap().err().unwrap();
+    assert!(log.next_post().await.is_none());
+    tokio::fs::remove_file(&path_to_log).await.unwrap();
+
+    assert!(matches!(
+        error,
+        parse::ParseError::Malformed { line: 3, .. }
+    ));
+}
+
+#[tokio::test]
+async fn parse_fantasy_grounds_chatlog() {
+    let path_to_log = "../test_files/fg_test_campaign.html";
+    let read_ids = || async {
+        let mut log = parse::parse_fantasy_grounds_log(path_to_log, Some(-6)).await;
+        let mut ids: Vec<String> = vec![];
+        while let Some(post) = log.next_post().await {
+            ids.push(post.unwrap().id);
+        }
+        ids
+    };
+
+    let ids = read_ids().await;
+    assert!(!ids.is_empty());
+    assert_eq!(ids, read_ids().await);
+    assert_eq!(
+        ids.iter().collect::<std::collections::HashSet<_>>().len(),
+        ids.len()
+    );
+}
+
+#[tokio::test]
+async fn resume_chat_logs() {
+    let config = parse::parse_config("../test_files/test_config.json".to_string()).await;
+    let campaign = &config.campaigns["Descent into Avernus"];
+    let registry = parse::ChatLogRegistry::default();
+
+    for (format, path_to_log) in [
+        ("foundry", "../test_files/fnd_test_campaign.db"),
+        ("roll20", "../test_files/r20_test_campaign.html"),
+    ] {
+        let read_from = |resume_point: Option<parse::ResumePoint>| {
+            let registry = &registry;
+            async move {
+                let mut log = registry
+                    .open_log(format, path_to_log, campaign)
+                    .await
+                    .unwrap();
+                if let Some(resume_point) = resume_point {
+                    assert!(log.resume(&resume_point).await);
+                }
+                let mut posts = vec![];
+                while let Some(post) = log.next_post().await {
+                    let Ok(post) = post else { continue };
+                    let position = log.position().unwrap();
+                    posts.push((post.id, post.sender_name, post.datetime, position));
+                }
+                posts
+            }
+        };
+
+        let posts = read_from(None).await;
+        assert!(posts.len() > 1, "{format}");
+        let (_, sender_name, datetime, position) = posts[0].clone();
+        let resumed = read_from(Some(parse::ResumePoint {
+            position,
+            sender_name,
+            datetime,
+        }))
+        .await;
+        assert_eq!(resumed, posts[1..], "{format}");
+    }
+}
+
+#[tokio::test]
+async fn parse_json_lines_chatlog() {
+    let path_to_log = "../test_files/jsl_test_campaign.jsonl";
+    let mapping = parse::parse_config::JsonMapping {
+        id: "messageId".to_string(),
+        sender: "author.name".to_string(),
+        timestamp: "createdAt".to_string(),
+        content: "text".to_string(),
+        roll_formula: "dice.expression".to_string(),
+        roll_total: "dice.total".to_string(),
+        roll_dice: "dice.values".to_string(),
+        ..Default::default()
+    };
+    let mut log = parse::parse_json_lines_log(path_to_log, Some(-6), &mapping).await;
+
+    let mut posts: Vec<parse::Post> = vec![];
+    let mut errors: Vec<parse::ParseError> = vec![];
+    while let Some(post) = log.next_post().await {
+        match post {
+            Ok(post) => posts.push(post),
+            Err(error) => errors.push(error),
+        }
+    }
+    assert_eq!(posts.len(), 3);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line(), 4);
+
+    assert_eq!(posts[0].id, "m1");
+    assert_eq!(posts[0].sender_name, "cool_guy 420");
+    assert_eq!(posts[0].content_raw, "I open the door");
+    assert!(posts[0].is_message);
+
+    assert!(!posts[1].is_message);
+    assert_eq!(posts[1].rolls[0].formula, "1d20+5");
+    assert_eq!(posts[1].rolls[0].outcome, 17.);
+    assert_eq!(posts[1].rolls[0].single_rolls[0].outcome, 12);
+
+    assert_eq!(posts[2].sender_name, "boBBy");
+    assert_eq!(
+        posts[2].datetime,
+        sqlx::types::chrono::DateTime::parse_from_rfc3339("2024-02-11T20:16:30-06:00").unwrap()
+    );
+}
+
+#[tokio::test]
+async fn parse_transcript_chatlog() {
+    let path_to_log = "../test_files/txt_test_campaign.md";
+    let format = parse::parse_config::TranscriptFormat::default();
+    let mut log = parse::parse_transcript_log(path_to_log, Some(-6), &format).await;
+
+    let mut posts: Vec<parse::Post> = vec![];
+    while let Some(post) = log.next_post().await {
+        posts.push(post.unwrap());
+    }
+    assert_eq!(posts.len(), 5);
+
+    assert_eq!(posts[0].sender_name, "cool_guy 420");
+    assert_eq!(posts[0].content_raw, "I open the door\nand step inside");
+    assert_eq!(
+        posts[0].datetime,
+        sqlx::types::chrono::DateTime::parse_from_rfc3339("2024-02-11T20:14:00-06:00").unwrap()
+    );
+
+    assert!(!posts[1].is_message);
+    assert_eq!(posts[1].rolls[0].formula, "1d20+5");
+    assert_eq!(posts[1].rolls[0].outcome, 17.);
+    assert!(posts[1].rolls[0].single_rolls.is_empty());
+
+    assert_eq!(posts[2].rolls[0].outcome, 7.);
+    assert_eq!(posts[2].rolls[0].single_rolls.len(), 2);
+    assert_eq!(posts[2].rolls[0].single_rolls[1].outcome, 4);
+
+    assert!(posts[3].is_message);
+    assert_eq!(
+        posts[4].datetime,
+        sqlx::types::chrono::DateTime::parse_from_rfc3339("2024-02-18T19:02:00-06:00").unwrap()
+    );
+    assert_ne!(posts[3].id, posts[4].id);
+}
+
+#[tokio::test]
+async fn transcript_ids_survive_edits() {
+    let read_ids = |transcript: String| async move {
+        let path = std::env::temp_dir().join("squidbot_transcript_ids.md");
+        tokio::fs::write(&path, transcript).await.unwrap();
+        let format = parse::parse_config::TranscriptFormat::default();
+        let mut log = parse::parse_transcript_log(path.to_str().unwrap(), None, &format).await;
+
+        let mut ids: Vec<String> = vec![];
+        while let Some(post) = log.next_post().await {
+            ids.push(post.unwrap().id);
+        }
+        tokio::fs::remove_file(&path).await.unwrap();
+        ids
+    };
+
+    let transcript = tokio::fs::read_to_string("../test_files/txt_test_campaign.md")
+        .await
+        .unwrap();
+    let ids = read_ids(transcript.clone()).await;
+    let edited_ids = read_ids(transcript.replacen("# Session 1\n", "# Session 1\n\n\n", 1)).await;
+    assert_eq!(ids, edited_ids);
+
+    let repeated_ids =
+        read_ids("## 2024-02-11\n[8:15 PM] boBBy: hi\n[8:15 PM] boBBy: hi\n".to_string()).await;
+    assert_eq!(repeated_ids.len(), 2);
+    assert_ne!(repeated_ids[0], repeated_ids[1]);
+}
+
+#[tokio::test]
+async fn detect_log_formats() {
+    let expected_formats = [
+        ("fnd_test_campaign.db", parse::LogFormat::Foundry),
+        ("fnd_test_campaign_v11", parse::LogFormat::Foundry),
+        ("r20_test_campaign.html", parse::LogFormat::Roll20),
+        ("fg_test_campaign.html", parse::LogFormat::FantasyGrounds),
+        ("dsc_test_campaign.json", parse::LogFormat::Discord),
+        ("jsl_test_campaign.jsonl", parse::LogFormat::JsonLines),
+        ("txt_test_campaign.md", parse::LogFormat::Transcript),
+    ];
+    for (filename, format) in expected_formats {
+        let path = std::path::Path::new("../test_files").join(filename);
+        assert_eq!(
+            parse::detect_log_format(&path).await,
+            Some(format),
+            "{filename}"
+        );
+    }
+
+    let config = parse::parse_config("../test_files/test_config.json".to_string()).await;
+    let mut campaign = config.campaigns["Descent into Avernus"].clone();
+    assert_eq!(
+        campaign.log_format("../test_files").await.as_deref(),
+        Some("foundry")
+    );
+    campaign.format = Some("json_lines".to_string());
+    assert_eq!(
+        campaign.log_format("../test_files").await.as_deref(),
+        Some("json_lines")
+    );
+    assert_eq!(
+        config.campaigns["Tomb of Annihilation"]
+            .log_format("../test_files")
+            .await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn chat_log_registry() {
+    let config = parse::parse_config("../test_files/test_config.json".to_string()).await;
+    let campaign = &config.campaigns["Descent into Avernus"];
+    let path_to_log = "../test_files/fnd_test_campaign.db";
+
+    let mut registry = parse::ChatLogRegistry::default();
+    assert!(registry
+        .open("owlbear", path_to_log, campaign)
+        .await
+        .is_none());
+
+    // A format from another crate, which only keeps the posts that are rolls.
+    registry.register("foundry_rolls", |path_to_log, _| {
+        Box::pin(async move {
+            struct RollsOnly(parse::BoxedChatLog);
+
+            #[async_trait::async_trait]
+            impl ChatLog for RollsOnly {
+                async fn new(_: tokio::fs::File, _: Option<i32>) -> Self {
+                    unimplemented!()
+                }
+
+                async fn next_post(&mut self) -> Option<Result<parse::Post, parse::ParseError>> {
+                    while let Some(post) = self.0.next_post().await {
+                        if post.as_ref().map_or(true, |post| !post.is_message) {
+                            return Some(post);
+                        }
+                    }
+                    None
+                }
+            }
+
+            let log = parse::parse_foundry_log(path_to_log, None).await;
+            Box::new(RollsOnly(Box::new(log))) as parse::BoxedChatLog
+        })
+    });
+
+    let all_posts = registry
+        .open("foundry", path_to_log, campaign)
+        .await
+        .unwrap();
+    let rolls: Vec<parse::Post> = registry
+        .open("foundry_rolls", path_to_log, campaign)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(all_posts.count().await, 5);
+    assert!(rolls.iter().all(|post| !post.rolls.is_empty()));
+    assert!(!rolls.is_empty() && rolls.len() < 5);
+}
+
+#[tokio::test]
+async fn chat_log_errors_are_skipped() {
+    let config = parse::parse_config("../test_files/test_config.json".to_string()).await;
+    let campaign = &config.campaigns["Descent into Avernus"];
+    let posts: Vec<Result<parse::Post, parse::ParseError>> = parse::ChatLogRegistry::default()
+        .open(
+            "json_lines",
+            "../test_files/jsl_malformed_campaign.jsonl",
+            campaign,
+        )
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert_eq!(posts.len(), 4);
+
+    assert_eq!(posts[0].as_ref().unwrap().id, "1");
+    assert!(matches!(
+        posts[1],
+        Err(parse::ParseError::Malformed { line: 2, .. })
+    ));
+    assert!(matches!(
+        posts[2],
+        Err(parse::ParseError::Io { line: 3, .. })
+    ));
+    assert_eq!(posts[3].as_ref().unwrap().id, "4");
 }
 
 #[tokio::test]
@@ -34,3 +518,114 @@ async fn get_random_message() {
 
     assert!(possible_messages.contains(&message));
 }
+
+fn seeded_roll(expr: &str, seed: u64) -> parse::DiceResult {
+    let mut rng = StdRng::seed_from_u64(seed);
+    parse::dicemath_with(expr, &mut rng, &parse::DiceLimits::default()).unwrap()
+}
+
+fn outcomes(result: &parse::DiceResult) -> Vec<Vec<i64>> {
+    result
+        .terms
+        .iter()
+        .map(|term| {
+            term.single_rolls
+                .iter()
+                .map(|single| single.outcome)
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn seeded_rolls() {
+    let ability = seeded_roll("4d6dl1", 20);
+    assert_eq!(outcomes(&ability), vec![vec![3, 1, 4, 5]]);
+    assert_eq!(ability.value, 12.);
+    assert_eq!(seeded_roll("4d6dl1", 20), ability);
+
+    let attack = seeded_roll("1d20 + 2d8!", 20);
+    assert_eq!(outcomes(&attack), vec![vec![17], vec![5, 6]]);
+    assert_eq!(attack.value, 28.);
+
+    let fate = seeded_roll("4dF", 20);
+    assert_eq!(outcomes(&fate), vec![vec![0, -1, 1, 0]]);
+    assert_eq!(fate.value, 0.);
+
+    assert_eq!(seeded_roll("10d10>7", 20).value, 1.);
+}
+
+#[test]
+fn seeded_exploding_rolls() {
+    for seed in 0..50 {
+        let compounded = seeded_roll("10d6!!", seed);
+        let penetrated = seeded_roll("10d6!p", seed);
+        for result in [&compounded, &penetrated] {
+            let single_rolls = &result.terms[0].single_rolls;
+            assert!(single_rolls.len() >= 10);
+            assert!(single_rolls
+                .iter()
+                .all(|single| single.faces == 6 && (1..=6).contains(&single.outcome)));
+        }
+
+        let sum = |result: &parse::DiceResult| -> i64 { outcomes(result)[0].iter().sum() };
+        let num_exploded = penetrated.terms[0]
+            .single_rolls
+            .iter()
+            .filter(|single| single.is_exploded)
+            .count() as i64;
+        assert_eq!(compounded.value, sum(&compounded) as f64);
+        assert_eq!(penetrated.value, (sum(&penetrated) - num_exploded) as f64);
+    }
+}
+
+#[test]
+fn dice_limits() {
+    let limits = parse::DiceLimits {
+        max_dice: 100,
+        max_faces: 1000,
+        max_depth: 8,
+        max_repeats: 3,
+    };
+    let roll = |expr: &str| parse::dicemath_with(expr, &mut StdRng::seed_from_u64(0), &limits);
+
+    assert!(roll("100d1000").is_ok());
+    assert_eq!(
+        parse::dicemath_repeated("4x 1d20", &mut StdRng::seed_from_u64(0), &limits),
+        Err(parse::DiceError::TooManyRepeats { position: 0 })
+    );
+    assert_eq!(
+        roll("1 + 101d6"),
+        Err(parse::DiceError::TooManyDice { position: 4 })
+    );
+    assert_eq!(
+        roll("2d1001"),
+        Err(parse::DiceError::TooManyFaces { position: 0 })
+    );
+    assert!(matches!(
+        roll("((((((((1))))))))"),
+        Err(parse::DiceError::TooDeep { .. })
+    ));
+}
+
+#[test]
+fn seeded_repeated_rolls() {
+    let limits = parse::DiceLimits::default();
+    let results =
+        parse::dicemath_repeated("6x 4d6dl1", &mut StdRng::seed_from_u64(20), &limits).unwrap();
+    let values: Vec<f64> = results.iter().map(|result| result.value).collect();
+    assert_eq!(values, vec![12., 10., 8., 14., 7., 15.]);
+
+    let mut rng = StdRng::seed_from_u64(20);
+    let one_at_a_time: Vec<parse::DiceResult> = (0..6)
+        .map(|_| parse::dicemath_with("4d6dl1", &mut rng, &limits).unwrap())
+        .collect();
+    assert_eq!(results, one_at_a_time);
+    assert_eq!(results[0], seeded_roll("4d6dl1", 20));
+    assert_eq!(
+        parse::dicemath_repeated("3#1d20", &mut StdRng::seed_from_u64(20), &limits)
+            .unwrap()
+            .len(),
+        3
+    );
+}